@@ -1,12 +1,21 @@
 use crate::{
     comparelist::CompareList,
+    dicom_series::DicomSeriesState,
+    dicom_window::DicomWindowState,
+    exr_layers::ExrLayerState,
     filebrowser::BrowserDir,
     image_editing::EditState,
+    layers::LayerState,
+    pages::PageState,
+    raw_develop::RawDevelopState,
     scrubber::Scrubber,
     settings::{PersistentSettings, VolatileSettings},
+    shortcuts::InputEvent,
+    texture_inspector::TextureInspectorState,
     texture_wrapper::TextureWrapperManager,
     thumbnails::Thumbnails,
     utils::{ExtendedImageInfo, Frame, Player},
+    web_export::WebExportState,
 };
 
 use egui_notify::Toasts;
@@ -34,6 +43,10 @@ pub enum Message {
     Error(String),
     LoadError(String),
     Saved(PathBuf),
+    /// A display name for an image that has no file, for example one received over the network
+    SourceName(String),
+    /// Act as if the shortcut for this event was pressed, for example when sent remotely
+    Command(InputEvent),
 }
 
 impl Message {
@@ -98,6 +111,25 @@ pub struct OculanteState {
     pub filebrowser_last_dir: BrowserDir,
     pub thumbnails: Thumbnails,
     pub new_image_loaded: bool,
+    /// The current image is a frame of an animation
+    pub is_animation: bool,
+    /// The image whose embedded preview is shown while it is decoded
+    pub preview_of: Option<PathBuf>,
+    pub web_export: WebExportState,
+    /// Layers and channels of EXR images
+    pub exr_layers: ExrLayerState,
+    /// Pages of multi-page TIFF and frames of multi-frame DICOM images
+    pub pages: PageState,
+    /// Window/level of DICOM images
+    pub dicom_window: DicomWindowState,
+    /// The slices of the current DICOM series, in series mode
+    pub dicom_series: DicomSeriesState,
+    /// Layers of PSD and KRA documents
+    pub layers: LayerState,
+    /// Levels, layers and faces of KTX2 and DDS textures
+    pub texture_inspector: TextureInspectorState,
+    /// The sensor data of RAW files, to develop them again
+    pub raw_develop: RawDevelopState,
 }
 
 impl OculanteState {
@@ -117,6 +149,16 @@ impl OculanteState {
         let _ = self.texture_channel.0.send(frame);
     }
 
+    /// Show another version of the current image, such as a page or an EXR layer,
+    /// without treating it as a newly loaded image
+    pub fn replace_current_image(&mut self, img: DynamicImage) {
+        self.image_geometry.dimensions = (img.width(), img.height());
+        self.current_image = Some(img);
+        self.edit_state.result_pixel_op = Default::default();
+        self.edit_state.result_image_op = Default::default();
+        self.send_frame(Frame::UpdateTexture);
+    }
+
     /// Evaluate the base path for the file browser.
     pub fn filebrowser_path(&self) -> PathBuf {
         match self.filebrowser_last_dir {
@@ -187,6 +229,16 @@ impl Default for OculanteState {
             filebrowser_last_dir: Default::default(),
             thumbnails: Default::default(),
             new_image_loaded: false,
+            is_animation: false,
+            preview_of: None,
+            web_export: Default::default(),
+            exr_layers: Default::default(),
+            pages: Default::default(),
+            dicom_window: Default::default(),
+            dicom_series: Default::default(),
+            layers: Default::default(),
+            texture_inspector: Default::default(),
+            raw_develop: Default::default(),
         }
     }
 }
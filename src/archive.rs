@@ -0,0 +1,143 @@
+//! Browsing images inside ZIP, CBZ and TAR archives.
+//!
+//! An image in an archive is addressed by a virtual path: The path of the archive
+//! followed by the name of the entry, for example `comic.cbz/pages/001.jpg`. This way
+//! the scrubber, recent files and the title treat entries like files in a folder.
+
+use crate::utils::is_ext_compatible;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Archives that are browsed like folders
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "cbz", "tar", "cbt"];
+
+fn is_tar(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("tar") || e.eq_ignore_ascii_case("cbt"))
+}
+
+/// The name of an entry with `/` separators and without a leading `./`
+fn entry_name(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether `path` is an archive file
+pub fn is_archive(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    ARCHIVE_EXTENSIONS.contains(&extension.as_str()) && path.is_file()
+}
+
+/// Split the virtual path of an archive entry into the archive and the entry name
+pub fn split_path(path: &Path) -> Option<(&Path, String)> {
+    let archive = path.ancestors().skip(1).find(|a| is_archive(a))?;
+    let entry = entry_name(path.strip_prefix(archive).ok()?);
+    Some((archive, entry))
+}
+
+/// The archive `path` is, or is an entry of
+pub fn containing_archive(path: &Path) -> Option<&Path> {
+    if is_archive(path) {
+        return Some(path);
+    }
+    split_path(path).map(|(archive, _)| archive)
+}
+
+/// The virtual paths of the supported images in `archive`, in natural order
+pub fn image_paths(archive: &Path) -> Result<Vec<PathBuf>> {
+    let mut names = vec![];
+    if is_tar(archive) {
+        let mut tar = tar::Archive::new(BufReader::new(File::open(archive)?));
+        for entry in tar.entries()? {
+            let entry = entry?;
+            if entry.header().entry_type().is_file() {
+                names.push(entry_name(&entry.path()?));
+            }
+        }
+    } else {
+        let zip = zip::ZipArchive::new(BufReader::new(File::open(archive)?))?;
+        names.extend(
+            zip.file_names()
+                .filter(|name| !name.ends_with('/'))
+                .map(String::from),
+        );
+    }
+
+    // Skip metadata such as `__MACOSX/._001.jpg`
+    names.retain(|name| {
+        !name
+            .split('/')
+            .any(|part| part.starts_with('.') || part == "__MACOSX")
+            && is_ext_compatible(Path::new(name))
+    });
+    names.sort_unstable_by(|a, b| lexical_sort::natural_lexical_cmp(a, b));
+    Ok(names.iter().map(|name| archive.join(name)).collect())
+}
+
+/// Read the entry a virtual path points to
+pub fn read_entry(path: &Path) -> Result<Vec<u8>> {
+    let (archive, name) = split_path(path).context("This is not inside an archive")?;
+    let mut data = vec![];
+    if is_tar(archive) {
+        let mut tar = tar::Archive::new(BufReader::new(File::open(archive)?));
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if entry_name(&entry.path()?) == name {
+                entry.read_to_end(&mut data)?;
+                return Ok(data);
+            }
+        }
+        bail!("{name} is missing from {}", archive.display());
+    }
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(archive)?))?;
+    zip.by_name(&name)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn lists_and_reads_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("comic.cbz");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for name in [
+            "10.png",
+            "2.png",
+            "notes.txt",
+            "__MACOSX/._2.png",
+            "extras/1.jpg",
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(name.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let paths = image_paths(&archive).unwrap();
+        assert_eq!(
+            paths,
+            ["2.png", "10.png", "extras/1.jpg"].map(|name| archive.join(name))
+        );
+        assert_eq!(
+            split_path(&paths[2]),
+            Some((archive.as_path(), "extras/1.jpg".to_string()))
+        );
+        assert_eq!(containing_archive(&paths[0]), Some(archive.as_path()));
+        assert_eq!(read_entry(&paths[1]).unwrap(), b"10.png");
+        assert!(read_entry(&archive.join("missing.png")).is_err());
+    }
+}
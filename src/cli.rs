@@ -0,0 +1,740 @@
+//! Command line interface.
+//!
+//! Besides the arguments of the viewer itself, this defines subcommands that run headless:
+//! They are dispatched from `main()` before any window is created.
+
+use crate::file_encoder::FileEncoder;
+use crate::image_editing::EditState;
+use crate::image_loader::{open_animation, open_image, rotate_dynimage};
+use crate::metadata::ImageMetadata;
+use crate::pages::{load_oriented_page, page_count};
+use crate::remote::{self, RemoteCommand};
+use crate::scrubber::get_image_filenames_for_directory;
+use crate::settings::{DecoderSettings, PersistentSettings, VolatileSettings};
+use crate::tonemapping::is_scene_linear;
+use crate::utils::{is_ext_compatible, ExtendedImageInfo, Frame};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Arg, ArgMatches, Command};
+use image::DynamicImage;
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+
+/// The complete command line definition of oculante
+pub fn command() -> Command<'static> {
+    let command = Command::new("Oculante")
+        .arg(
+            Arg::new("INPUT")
+                .help("Display this image")
+                .multiple_values(true),
+        )
+        .arg(
+            Arg::new("l")
+                .short('l')
+                .help("Listen on port")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .takes_value(true)
+                .default_value("127.0.0.1")
+                .help("Address to listen on with -l. Use 0.0.0.0 to accept other machines"),
+        )
+        .arg(
+            Arg::new("stdin")
+                .short('s')
+                .id("stdin")
+                .takes_value(false)
+                .help("Load images from STDIN until it is closed"),
+        )
+        .arg(
+            Arg::new("chainload")
+                .required(false)
+                .takes_value(false)
+                .short('c')
+                .help("Chainload on Mac"),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Apply a saved edit stack to many images without opening a window")
+                .arg(
+                    Arg::new("edits")
+                        .short('e')
+                        .long("edits")
+                        .takes_value(true)
+                        .required(true)
+                        .help("A .oculante sidecar or edit stack JSON file"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Directory to write the results to"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .takes_value(true)
+                        .help(
+                            "Output extension such as png or jpg. Defaults to the input extension",
+                        ),
+                )
+                .arg(strip_location_arg())
+                .arg(
+                    Arg::new("INPUT")
+                        .help("Images or folders of images to process")
+                        .required(true)
+                        .multiple_values(true),
+                ),
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Convert an image to another format without opening a window")
+                .arg(
+                    Arg::new("quality")
+                        .short('q')
+                        .long("quality")
+                        .takes_value(true)
+                        .help("Encoder quality from 0 to 100, for formats that support it"),
+                )
+                .arg(
+                    Arg::new("all-frames")
+                        .short('a')
+                        .long("all-frames")
+                        .takes_value(false)
+                        .help("Write every frame of an animation or page of a multi-page TIFF or DICOM. '{}' in OUTPUT is replaced by the frame number"),
+                )
+                .arg(
+                    Arg::new("page")
+                        .short('p')
+                        .long("page")
+                        .takes_value(true)
+                        .conflicts_with("all-frames")
+                        .help("Convert this page of a multi-page TIFF or frame of a DICOM, counting from 1"),
+                )
+                .arg(strip_location_arg())
+                .arg(
+                    Arg::new("INPUT")
+                        .help("The image to convert")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("OUTPUT")
+                        .help("The file to write. The format is picked by its extension. Animations stay animated as GIF, PNG or WebP")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("remote")
+                .about("Control a viewer running in single-instance mode")
+                .arg(
+                    Arg::new("open")
+                        .long("open")
+                        .takes_value(true)
+                        .help("Load this image or folder"),
+                )
+                .arg(
+                    Arg::new("COMMAND")
+                        .help("Shortcut actions to perform, such as NextImage or ZoomIn")
+                        .multiple_values(true),
+                ),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Print image information as JSON without opening a window")
+                .arg(
+                    Arg::new("INPUT")
+                        .help("Images to inspect")
+                        .required(true)
+                        .multiple_values(true),
+                ),
+        );
+
+    #[cfg(unix)]
+    let command = command.arg(
+        Arg::new("socket")
+            .long("socket")
+            .takes_value(true)
+            .help("Listen on a Unix domain socket at this path"),
+    );
+
+    command
+}
+
+fn strip_location_arg() -> Arg<'static> {
+    Arg::new("strip-location")
+        .long("strip-location")
+        .takes_value(false)
+        .help("Remove GPS data from the EXIF of the output")
+}
+
+/// Parse the arguments oculante was started with
+pub fn matches() -> ArgMatches {
+    // Filter out strange mac args
+    let args = std::env::args().filter(|a| !a.contains("psn_")).collect();
+    matches_from(args, Path::is_file)
+}
+
+/// Parse `args`. A first argument that is named like a subcommand but `is_file`
+/// is an image to display.
+fn matches_from(mut args: Vec<String>, is_file: impl Fn(&Path) -> bool) -> ArgMatches {
+    let command = command();
+    let names_file = args
+        .get(1)
+        .is_some_and(|first| command.find_subcommand(first).is_some() && is_file(Path::new(first)));
+    if names_file {
+        args.insert(1, "--".into());
+    }
+    command.get_matches_from(args)
+}
+
+/// Run a headless subcommand. Returns `None` if the arguments are meant for the viewer.
+pub fn run(matches: &ArgMatches) -> Option<Result<()>> {
+    matches.subcommand()?;
+    let settings = PersistentSettings::load().unwrap_or_default();
+    let encoders = VolatileSettings::load()
+        .unwrap_or_default()
+        .encoding_options;
+    run_with(matches, &settings, &encoders)
+}
+
+/// Run a headless subcommand with the given settings and encoder options
+fn run_with(
+    matches: &ArgMatches,
+    settings: &PersistentSettings,
+    encoders: &[FileEncoder],
+) -> Option<Result<()>> {
+    match matches.subcommand() {
+        Some(("batch", sub_matches)) => Some(batch(sub_matches, settings, encoders)),
+        Some(("convert", sub_matches)) => Some(convert(sub_matches, settings, encoders)),
+        Some(("info", sub_matches)) => Some(info(sub_matches, settings)),
+        Some(("remote", sub_matches)) => Some(remote_control(sub_matches)),
+        _ => None,
+    }
+}
+
+/// In single-instance mode, hand the images to open to a viewer that is already running.
+/// Returns `true` if they were accepted and no new window should be opened.
+pub fn forward_to_running_instance(matches: &ArgMatches) -> bool {
+    let single_instance = PersistentSettings::load()
+        .map(|settings| settings.single_instance)
+        .unwrap_or_default();
+    let Some(inputs) = matches.values_of("INPUT") else {
+        return false;
+    };
+    if !single_instance {
+        return false;
+    }
+    // The running instance may have a different working directory
+    let commands = inputs
+        .map(|input| {
+            let path = Path::new(input)
+                .canonicalize()
+                .unwrap_or_else(|_| PathBuf::from(input));
+            RemoteCommand::Open(path)
+        })
+        .collect::<Vec<_>>();
+    match remote::send(&commands) {
+        Ok(_) => true,
+        Err(e) => {
+            debug!("Starting a new instance: {e:#}");
+            false
+        }
+    }
+}
+
+/// Headless commands keep the original pixels, the color profile is carried over when saving
+fn unmanaged_decoders(settings: &PersistentSettings) -> DecoderSettings {
+    DecoderSettings {
+        color_managed: false,
+        ..settings.decoders
+    }
+}
+
+/// Decode the first frame of an image, oriented the same way the viewer displays it.
+pub fn load_first_frame(path: &Path, settings: &PersistentSettings) -> Result<DynamicImage> {
+    Ok(load_oriented_frame(path, settings)?.0)
+}
+
+/// Like [`load_first_frame`], also telling if the EXIF orientation was applied to the pixels
+fn load_oriented_frame(path: &Path, settings: &PersistentSettings) -> Result<(DynamicImage, bool)> {
+    let receiver = open_image(path, None, Some(unmanaged_decoders(settings)))?;
+    let frame = receiver
+        .recv()
+        .context("The decoder did not produce an image")?;
+    let is_still = matches!(frame, Frame::Still(_));
+    let mut image = frame.get_image().context("Can't get buffer")?;
+    let oriented = is_still && rotate_dynimage(&mut image, path).is_ok();
+    Ok((image, oriented))
+}
+
+/// Load all frames with their delays if `animated` is set and the image has more than one,
+/// otherwise the first frame like [`load_first_frame`]. Also tells if the EXIF orientation was
+/// applied to the pixels, which is only done for still images.
+fn load_frames(
+    path: &Path,
+    settings: &PersistentSettings,
+    animated: bool,
+) -> Result<(Vec<(DynamicImage, u16)>, bool)> {
+    if !animated {
+        let (image, oriented) = load_oriented_frame(path, settings)?;
+        return Ok((vec![(image, 0)], oriented));
+    }
+    let mut frames = open_animation(path, Some(unmanaged_decoders(settings)))?;
+    let oriented = match frames.as_mut_slice() {
+        [(image, _)] => rotate_dynimage(image, path).is_ok(),
+        _ => false,
+    };
+    Ok((frames, oriented))
+}
+
+/// Tone map float images the way the viewer shows them, unless `encoder` keeps float samples
+fn to_display_range(
+    image: DynamicImage,
+    encoder: &FileEncoder,
+    settings: &PersistentSettings,
+) -> DynamicImage {
+    if encoder.supports_float() || !is_scene_linear(image.color()) {
+        image
+    } else {
+        settings.tone_mapping.bake(&image)
+    }
+}
+
+/// Expand folders into the supported images they contain
+fn collect_inputs(matches: &ArgMatches) -> Vec<PathBuf> {
+    let mut inputs = vec![];
+    for input in matches.values_of("INPUT").into_iter().flatten() {
+        let path = PathBuf::from(input);
+        if path.is_dir() {
+            match get_image_filenames_for_directory(&path) {
+                Ok(entries) => inputs.extend(entries),
+                Err(e) => eprintln!("{}: {e}", path.display()),
+            }
+        } else {
+            inputs.push(path);
+        }
+    }
+    inputs
+}
+
+/// The encoder for the extension of `path`, or an error listing the supported ones
+fn encoder_for(path: &Path, encoders: &[FileEncoder]) -> Result<FileEncoder> {
+    let ext = FileEncoder::normalized_ext(path);
+    let encoder = FileEncoder::matching_variant(path, encoders);
+    if encoder.ext() != ext {
+        bail!(
+            "Can't write '{ext}' files. Supported are: {}",
+            encoders
+                .iter()
+                .map(|e| e.ext())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(encoder)
+}
+
+fn batch(
+    matches: &ArgMatches,
+    settings: &PersistentSettings,
+    encoders: &[FileEncoder],
+) -> Result<()> {
+    let edits_path = PathBuf::from(matches.value_of("edits").context("Edits are required")?);
+    let edit_state = EditState::load(&edits_path)
+        .with_context(|| format!("Can't read edits from {}", edits_path.display()))?;
+
+    let format = matches.value_of("format");
+    if let Some(format) = format {
+        encoder_for(&Path::new("output").with_extension(format), encoders)?;
+    }
+
+    let output_dir = PathBuf::from(matches.value_of("output").context("Output is required")?);
+    create_dir_all(&output_dir)?;
+    let output_dir = output_dir.canonicalize()?;
+
+    let strip_location = matches.is_present("strip-location") || settings.strip_location;
+    let inputs = collect_inputs(matches);
+    // Results must neither replace an input nor each other
+    let originals = inputs
+        .iter()
+        .filter_map(|input| input.canonicalize().ok())
+        .collect::<HashSet<_>>();
+    let mut written: HashMap<PathBuf, &Path> = HashMap::new();
+    let mut failed = 0;
+
+    for input in &inputs {
+        let ext = format
+            .map(|f| f.to_string())
+            .or_else(|| input.extension().map(|e| e.to_string_lossy().to_string()))
+            .unwrap_or_default();
+        let stem = input.file_stem().unwrap_or_default();
+        let encoder =
+            FileEncoder::matching_variant(&output_dir.join(stem).with_extension(&ext), encoders);
+        let dest = output_dir.join(stem).with_extension(encoder.ext());
+
+        let result = if originals.contains(&dest) {
+            Err(anyhow!(
+                "Refusing to overwrite the input {}",
+                dest.display()
+            ))
+        } else if let Some(other) = written.get(&dest) {
+            Err(anyhow!(
+                "{} was already written for {}",
+                dest.display(),
+                other.display()
+            ))
+        } else {
+            written.insert(dest.clone(), input);
+            batch_single(
+                input,
+                &dest,
+                &edit_state,
+                &encoder,
+                settings,
+                strip_location,
+            )
+        };
+        match result {
+            Ok(_) => println!("{} -> {}", input.display(), dest.display()),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {e:#}", input.display());
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} images could not be processed", inputs.len());
+    }
+    Ok(())
+}
+
+fn batch_single(
+    input: &Path,
+    dest: &Path,
+    edit_state: &EditState,
+    encoder: &FileEncoder,
+    settings: &PersistentSettings,
+    strip_location: bool,
+) -> Result<()> {
+    if !is_ext_compatible(input) {
+        bail!("Unsupported file");
+    }
+    let (mut frames, oriented) = load_frames(input, settings, encoder.supports_animation())?;
+    debug!("Applying edits to {}", input.display());
+    if frames.len() > 1 {
+        return encoder.save_animation(&edit_state.apply_to_frames(&frames)?, dest);
+    }
+    let (image, _) = frames.remove(0);
+    let result = edit_state.apply(&to_display_range(image, encoder, settings))?;
+    let metadata = ImageMetadata {
+        oriented,
+        ..ImageMetadata::read(input).unwrap_or_default()
+    };
+    encoder.save_with_metadata(&result, dest, &metadata, strip_location)
+}
+
+/// Everything the info panel shows about an image
+fn image_info(input: &Path, settings: &PersistentSettings) -> Result<ExtendedImageInfo> {
+    let image = load_first_frame(input, settings)?;
+    let mut e_info = ExtendedImageInfo::from_image(&image.to_rgba8());
+    e_info.with_value_range(&image);
+    // Both are optional, most images only have one or none of them
+    _ = e_info.with_exif(input);
+    _ = e_info.with_dicom(input, 0);
+    e_info.with_color_profile(input);
+    e_info.name = input.to_string_lossy().to_string();
+    Ok(e_info)
+}
+
+fn info(matches: &ArgMatches, settings: &PersistentSettings) -> Result<()> {
+    let inputs = collect_inputs(matches);
+    let mut infos = vec![];
+    let mut failed = 0;
+
+    for input in &inputs {
+        match image_info(input, settings) {
+            Ok(e_info) => infos.push(e_info),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {e:#}", input.display());
+            }
+        }
+    }
+
+    serde_json::to_writer_pretty(std::io::stdout().lock(), &infos)?;
+    println!();
+
+    if failed > 0 {
+        bail!("{failed} of {} images could not be read", inputs.len());
+    }
+    Ok(())
+}
+
+fn convert(
+    matches: &ArgMatches,
+    settings: &PersistentSettings,
+    encoders: &[FileEncoder],
+) -> Result<()> {
+    let input = PathBuf::from(matches.value_of("INPUT").context("Input is required")?);
+    let output = PathBuf::from(matches.value_of("OUTPUT").context("Output is required")?);
+
+    let mut encoder = encoder_for(&output, encoders)?;
+    if let Some(quality) = matches.value_of("quality") {
+        encoder.set_quality(quality.parse().context("Quality must be a number")?);
+    }
+
+    let strip_location = matches.is_present("strip-location") || settings.strip_location;
+    let metadata = ImageMetadata::read(&input).unwrap_or_default();
+
+    if let Some(page) = matches.value_of("page") {
+        let page = page
+            .parse::<usize>()
+            .ok()
+            .filter(|page| *page > 0)
+            .context("Page must be a number from 1")?;
+        let (image, oriented) = load_oriented_page(&input, page - 1)?;
+        let image = to_display_range(image, &encoder, settings);
+        let metadata = ImageMetadata {
+            oriented,
+            ..metadata
+        };
+        encoder.save_with_metadata(&image, &output, &metadata, strip_location)?;
+        println!("{} -> {}", input.display(), output.display());
+        return Ok(());
+    }
+
+    let page_count = page_count(&input).unwrap_or(1);
+    if page_count > 1 && matches.is_present("all-frames") {
+        for page in 0..page_count {
+            let (image, oriented) = load_oriented_page(&input, page)?;
+            let image = to_display_range(image, &encoder, settings);
+            let dest = numbered_path(&output, page + 1);
+            let metadata = ImageMetadata {
+                oriented,
+                ..metadata.clone()
+            };
+            encoder.save_with_metadata(&image, &dest, &metadata, strip_location)?;
+            println!("{} -> {}", input.display(), dest.display());
+        }
+        return Ok(());
+    }
+
+    if !matches.is_present("all-frames") {
+        let (mut frames, oriented) = load_frames(&input, settings, encoder.supports_animation())?;
+        if frames.len() > 1 {
+            encoder.save_animation(&frames, &output)?;
+        } else {
+            let (image, _) = frames.remove(0);
+            let image = to_display_range(image, &encoder, settings);
+            let metadata = ImageMetadata {
+                oriented,
+                ..metadata
+            };
+            encoder.save_with_metadata(&image, &output, &metadata, strip_location)?;
+        }
+        println!("{} -> {}", input.display(), output.display());
+        return Ok(());
+    }
+
+    let receiver = open_image(&input, None, Some(unmanaged_decoders(settings)))?;
+    let mut num_frames = 0;
+    for frame in receiver.iter() {
+        let Some(image) = frame.get_image() else {
+            continue;
+        };
+        let image = to_display_range(image, &encoder, settings);
+        num_frames += 1;
+        let dest = numbered_path(&output, num_frames);
+        encoder.save_with_metadata(&image, &dest, &metadata, strip_location)?;
+        println!("{} -> {}", input.display(), dest.display());
+    }
+    if num_frames == 0 {
+        bail!("The decoder did not produce an image");
+    }
+    Ok(())
+}
+
+/// Insert a frame number into a path, either at a `{}` placeholder or after the file stem.
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let number = format!("{number:04}");
+    let path_string = path.to_string_lossy();
+    if path_string.contains("{}") {
+        return PathBuf::from(path_string.replace("{}", &number));
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}_{number}"))
+        .with_extension(path.extension().unwrap_or_default())
+}
+
+fn remote_control(matches: &ArgMatches) -> Result<()> {
+    let mut commands = vec![];
+    if let Some(path) = matches.value_of("open") {
+        let path = Path::new(path)
+            .canonicalize()
+            .with_context(|| format!("Can't find {path}"))?;
+        commands.push(RemoteCommand::Open(path));
+    }
+    for name in matches.values_of("COMMAND").into_iter().flatten() {
+        commands.push(RemoteCommand::Input(remote::parse_input_event(name)?));
+    }
+    if commands.is_empty() {
+        bail!("Nothing to send. Pass --open or at least one command");
+    }
+    remote::send(&commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_args(args: &[&str]) -> Result<()> {
+        let matches =
+            command().get_matches_from(std::iter::once("oculante").chain(args.iter().copied()));
+        let encoders = VolatileSettings::default().encoding_options;
+        run_with(&matches, &PersistentSettings::default(), &encoders)
+            .expect("This is a headless subcommand")
+    }
+
+    #[test]
+    fn opens_files_named_like_subcommands() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let is_info = |path: &Path| path == Path::new("info");
+
+        let matches = matches_from(args(&["oculante", "info"]), is_info);
+        assert!(matches.subcommand().is_none());
+        assert_eq!(matches.value_of("INPUT"), Some("info"));
+
+        let matches = matches_from(args(&["oculante", "info", "a.png"]), |_| false);
+        assert_eq!(matches.subcommand_name(), Some("info"));
+    }
+
+    #[test]
+    fn reports_image_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("gray.png");
+        let pixels = vec![1000u16, 2000, 2000];
+        DynamicImage::ImageLuma16(image::ImageBuffer::from_raw(3, 1, pixels).unwrap())
+            .save(&input)
+            .unwrap();
+
+        let e_info = image_info(&input, &PersistentSettings::default()).unwrap();
+        assert_eq!(e_info.dimensions, (3, 1));
+        assert_eq!(e_info.num_pixels, 3);
+        assert_eq!(e_info.num_colors, 2);
+        assert_eq!(e_info.value_range, Some((1000., 2000.)));
+        assert_eq!(e_info.name, input.to_string_lossy());
+
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, b"not an image").unwrap();
+        let error =
+            run_args(&["info", &input.to_string_lossy(), &broken.to_string_lossy()]).unwrap_err();
+        assert!(error.to_string().contains("1 of 2"), "{error}");
+    }
+
+    #[test]
+    fn converts_images_and_pages() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        let mut encoder =
+            TiffEncoder::new(std::fs::File::create(path("pages.tif")).unwrap()).unwrap();
+        for value in [1000u16, 2000] {
+            encoder
+                .write_image::<colortype::Gray16>(2, 2, &[value; 4])
+                .unwrap();
+        }
+
+        run_args(&["convert", &path("pages.tif"), &path("first.png")]).unwrap();
+        let first = image::open(path("first.png")).unwrap();
+        assert_eq!(first.as_luma16().unwrap().get_pixel(0, 0).0, [1000]);
+
+        run_args(&[
+            "convert",
+            "-p",
+            "2",
+            &path("pages.tif"),
+            &path("second.png"),
+        ])
+        .unwrap();
+        let second = image::open(path("second.png")).unwrap();
+        assert_eq!(second.as_luma16().unwrap().get_pixel(0, 0).0, [2000]);
+
+        run_args(&["convert", "-a", &path("pages.tif"), &path("page.png")]).unwrap();
+        assert!(dir.path().join("page_0001.png").is_file());
+        assert!(dir.path().join("page_0002.png").is_file());
+
+        let error =
+            run_args(&["convert", "-p", "2", &path("first.png"), &path("x.png")]).unwrap_err();
+        assert!(error.to_string().contains("only one page"), "{error}");
+        let error = run_args(&["convert", &path("first.png"), &path("x.nope")]).unwrap_err();
+        assert!(error.to_string().contains("Can't write 'nope'"), "{error}");
+    }
+
+    #[test]
+    fn batch_never_overwrites_inputs_or_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        let image = DynamicImage::new_rgba8(4, 4);
+        image.save(path("a.png")).unwrap();
+        image.save(path("a.bmp")).unwrap();
+        std::fs::write(
+            path("edits.oculante"),
+            serde_json::to_vec(&EditState::default()).unwrap(),
+        )
+        .unwrap();
+        let before = std::fs::read(path("a.png")).unwrap();
+
+        // Both results would replace a.png
+        let error = run_args(&[
+            "batch",
+            "-e",
+            &path("edits.oculante"),
+            "-o",
+            &path(""),
+            "-f",
+            "png",
+            &path("a.png"),
+            &path("a.bmp"),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("2 of 2"), "{error}");
+        assert_eq!(std::fs::read(path("a.png")).unwrap(), before);
+
+        // Only the first one is written
+        let error = run_args(&[
+            "batch",
+            "-e",
+            &path("edits.oculante"),
+            "-o",
+            &path("out"),
+            "-f",
+            "png",
+            &path("a.png"),
+            &path("a.bmp"),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("1 of 2"), "{error}");
+        assert!(dir.path().join("out/a.png").is_file());
+
+        let error = run_args(&[
+            "batch",
+            "-e",
+            &path("edits.oculante"),
+            "-o",
+            &path("out"),
+            "-f",
+            "nope",
+            &path("a.png"),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("Can't write 'nope'"), "{error}");
+    }
+}
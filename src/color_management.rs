@@ -0,0 +1,390 @@
+//! Color management: Convert images with an embedded ICC profile to the display profile.
+//!
+//! Without this, pixels are shown as if they were sRGB, which makes wide gamut images
+//! look washed out and others oversaturated.
+
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use lcms2::{
+    CIExyY, CIExyYTRIPLE, ColorSpaceSignature, InfoType, Intent, Locale, PixelFormat, Profile,
+    ToneCurve, Transform,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use strum::{Display, EnumIter};
+
+/// The profile pixels are converted to before they are displayed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter, Display)]
+pub enum DisplayProfile {
+    #[default]
+    #[strum(to_string = "sRGB")]
+    Srgb,
+    #[strum(to_string = "Display P3")]
+    DisplayP3,
+    #[strum(to_string = "Adobe RGB")]
+    AdobeRgb,
+}
+
+impl DisplayProfile {
+    fn profile(&self) -> Result<Profile> {
+        let d65 = CIExyY {
+            x: 0.3127,
+            y: 0.3290,
+            Y: 1.0,
+        };
+        let primary = |x, y| CIExyY { x, y, Y: 1.0 };
+        let (primaries, curve) = match self {
+            DisplayProfile::Srgb => return Ok(Profile::new_srgb()),
+            DisplayProfile::DisplayP3 => (
+                CIExyYTRIPLE {
+                    Red: primary(0.680, 0.320),
+                    Green: primary(0.265, 0.690),
+                    Blue: primary(0.150, 0.060),
+                },
+                // The sRGB transfer function
+                ToneCurve::new_parametric(
+                    4,
+                    &[2.4, 1. / 1.055, 0.055 / 1.055, 1. / 12.92, 0.04045],
+                )?,
+            ),
+            DisplayProfile::AdobeRgb => (
+                CIExyYTRIPLE {
+                    Red: primary(0.640, 0.330),
+                    Green: primary(0.210, 0.710),
+                    Blue: primary(0.150, 0.060),
+                },
+                ToneCurve::new(563. / 256.),
+            ),
+        };
+        Ok(Profile::new_rgb(
+            &d65,
+            &primaries,
+            &[&curve, &curve, &curve],
+        )?)
+    }
+}
+
+impl DisplayProfile {
+    /// The profile as ICC data, to embed it in saved images
+    pub fn icc(&self) -> Result<Vec<u8>> {
+        Ok(self.profile()?.icc()?)
+    }
+}
+
+/// Read the ICC profile embedded in an image file. Only the headers are read, not the pixels.
+pub fn read_profile(path: &Path) -> Option<Vec<u8>> {
+    #[cfg(feature = "heif")]
+    if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("heif") || e.eq_ignore_ascii_case("heic"))
+    {
+        let ctx = libheif_rs::HeifContext::read_from_file(&path.to_string_lossy()).ok()?;
+        let handle = ctx.primary_image_handle().ok()?;
+        return handle.color_profile_raw().map(|profile| profile.data);
+    }
+
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let header = reader.fill_buf().ok()?;
+    if header.get(4..8) == Some(b"ftyp") {
+        return read_isobmff_profile(&mut reader).ok().flatten();
+    }
+    if header.starts_with(&[0xFF, 0xD8]) {
+        return read_jpeg_profile(&mut reader).ok().flatten();
+    }
+    if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+        // The generic decoder does not hand out the profile of TIFF files
+        return tiff::decoder::Decoder::new(reader)
+            .ok()?
+            .get_tag_u8_vec(tiff::tags::Tag::IccProfile)
+            .ok();
+    }
+    let mut decoder = ImageReader::new(reader)
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    decoder.icc_profile().ok().flatten()
+}
+
+/// JPEG splits the profile into numbered `ICC_PROFILE` APP2 segments before the image data
+fn read_jpeg_profile(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut chunks = vec![];
+    let mut marker = [0; 2];
+    reader.read_exact(&mut marker)?;
+    loop {
+        reader.read_exact(&mut marker)?;
+        // Start of scan, the image data follows
+        if marker == [0xFF, 0xDA] {
+            break;
+        }
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length)
+            .checked_sub(2)
+            .context("Invalid JPEG segment")?;
+        let mut segment = vec![0; length as usize];
+        reader.read_exact(&mut segment)?;
+        if marker == [0xFF, 0xE2] {
+            if let Some([number, _, chunk @ ..]) = segment.strip_prefix(b"ICC_PROFILE\0") {
+                chunks.push((*number, chunk.to_vec()));
+            }
+        }
+    }
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+    chunks.sort_by_key(|(number, _)| *number);
+    Ok(Some(
+        chunks.into_iter().flat_map(|(_, chunk)| chunk).collect(),
+    ))
+}
+
+/// AVIF and HEIF store the profile in a `colr` box of type `prof` or `rICC` in `meta/iprp/ipco`
+fn read_isobmff_profile(reader: &mut (impl Read + Seek)) -> Result<Option<Vec<u8>>> {
+    // Skip the top level boxes up to the metadata, which usually precedes the image data
+    let meta = loop {
+        let mut header = [0; 8];
+        if reader.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let size = match u32::from_be_bytes(header[..4].try_into()?) {
+            1 => {
+                let mut size = [0; 8];
+                reader.read_exact(&mut size)?;
+                u64::from_be_bytes(size).checked_sub(16)
+            }
+            // The box extends to the end of the file
+            0 => None,
+            size => (size as u64).checked_sub(8),
+        };
+        if &header[4..] == b"meta" {
+            let mut meta = vec![];
+            reader
+                .take(size.unwrap_or(u64::MAX))
+                .read_to_end(&mut meta)?;
+            break meta;
+        }
+        let Some(size) = size else {
+            return Ok(None);
+        };
+        reader.seek(SeekFrom::Current(size.try_into()?))?;
+    };
+    // The version and flags of the metadata box precede its children
+    let profile = meta
+        .get(4..)
+        .and_then(|children| find_box(children, b"iprp"))
+        .and_then(|iprp| find_box(iprp, b"ipco"))
+        .and_then(|ipco| {
+            boxes(ipco)
+                .filter(|(kind, _)| kind == b"colr")
+                .find_map(|(_, colr)| match colr.split_at_checked(4)? {
+                    (b"prof" | b"rICC", icc) => Some(icc.to_vec()),
+                    _ => None,
+                })
+        });
+    Ok(profile)
+}
+
+/// The type and content of each box in `data`
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let (header, size) = match u32::from_be_bytes(data.get(..4)?.try_into().ok()?) {
+            1 => (
+                16,
+                u64::from_be_bytes(data.get(8..16)?.try_into().ok()?) as usize,
+            ),
+            0 => (8, data.len()),
+            size => (8, size as usize),
+        };
+        let kind = data.get(4..8)?;
+        let content = data.get(header..size)?;
+        data = &data[size..];
+        Some((kind, content))
+    })
+}
+
+/// The content of the first box of type `kind` in `data`
+fn find_box<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    boxes(data).find_map(|(k, content)| (k == kind).then_some(content))
+}
+
+/// The human readable name of a profile
+pub fn profile_description(icc: &[u8]) -> Option<String> {
+    Profile::new_icc(icc)
+        .ok()?
+        .info(InfoType::Description, Locale::none())
+}
+
+fn transform_in_place<T: Copy + bytemuck::Pod>(
+    input: &Profile,
+    output: &Profile,
+    format: PixelFormat,
+    pixels: &mut [T],
+) -> Result<()> {
+    let transform: Transform<T, T> =
+        Transform::new(input, format, output, format, Intent::Perceptual)?;
+    transform.transform_in_place(pixels);
+    Ok(())
+}
+
+/// Convert an image from its embedded `icc` profile to `display`
+pub fn convert(image: &mut DynamicImage, icc: &[u8], display: DisplayProfile) -> Result<()> {
+    let input = Profile::new_icc(icc)?;
+    let output = display.profile()?;
+
+    match input.color_space() {
+        ColorSpaceSignature::RgbData => {}
+        ColorSpaceSignature::GrayData => {
+            // Gray profiles produce color output, so the image changes its type
+            let converted = match image {
+                DynamicImage::ImageLuma8(buf) => {
+                    let transform: Transform<u8, [u8; 3]> = Transform::new(
+                        &input,
+                        PixelFormat::GRAY_8,
+                        &output,
+                        PixelFormat::RGB_8,
+                        Intent::Perceptual,
+                    )?;
+                    let mut rgb = image::RgbImage::new(buf.width(), buf.height());
+                    transform.transform_pixels(buf.as_raw(), bytemuck::cast_slice_mut(&mut rgb));
+                    DynamicImage::ImageRgb8(rgb)
+                }
+                DynamicImage::ImageLuma16(buf) => {
+                    let transform: Transform<u16, [u16; 3]> = Transform::new(
+                        &input,
+                        PixelFormat::GRAY_16,
+                        &output,
+                        PixelFormat::RGB_16,
+                        Intent::Perceptual,
+                    )?;
+                    let mut rgb = image::ImageBuffer::new(buf.width(), buf.height());
+                    transform.transform_pixels(buf.as_raw(), bytemuck::cast_slice_mut(&mut rgb));
+                    DynamicImage::ImageRgb16(rgb)
+                }
+                _ => bail!("Gray profiles are only supported for gray images"),
+            };
+            *image = converted;
+            return Ok(());
+        }
+        other => bail!("Unsupported color space {other:?}"),
+    }
+
+    // RGB profiles
+    if matches!(
+        image,
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_)
+    ) {
+        *image = DynamicImage::ImageRgba8(image.to_rgba8());
+    } else if matches!(
+        image,
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_)
+    ) {
+        *image = DynamicImage::ImageRgba16(image.to_rgba16());
+    }
+
+    // Alpha is an extra channel that lcms leaves untouched
+    match image {
+        DynamicImage::ImageRgb8(buf) => transform_in_place::<[u8; 3]>(
+            &input,
+            &output,
+            PixelFormat::RGB_8,
+            bytemuck::cast_slice_mut(buf),
+        ),
+        DynamicImage::ImageRgba8(buf) => transform_in_place::<[u8; 4]>(
+            &input,
+            &output,
+            PixelFormat::RGBA_8,
+            bytemuck::cast_slice_mut(buf),
+        ),
+        DynamicImage::ImageRgb16(buf) => transform_in_place::<[u16; 3]>(
+            &input,
+            &output,
+            PixelFormat::RGB_16,
+            bytemuck::cast_slice_mut(buf),
+        ),
+        DynamicImage::ImageRgba16(buf) => transform_in_place::<[u16; 4]>(
+            &input,
+            &output,
+            PixelFormat::RGBA_16,
+            bytemuck::cast_slice_mut(buf),
+        ),
+        DynamicImage::ImageRgb32F(buf) => transform_in_place::<[f32; 3]>(
+            &input,
+            &output,
+            PixelFormat::RGB_FLT,
+            bytemuck::cast_slice_mut(buf),
+        ),
+        DynamicImage::ImageRgba32F(buf) => transform_in_place::<[f32; 4]>(
+            &input,
+            &output,
+            PixelFormat::RGBA_FLT,
+            bytemuck::cast_slice_mut(buf),
+        ),
+        _ => bail!("Unsupported pixel format {:?}", image.color()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_avif_profile() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/red-at-12-oclock-with-color-profile-8bpc.avif");
+        let icc = read_profile(&path).unwrap();
+        assert!(profile_description(&icc).is_some());
+    }
+
+    #[test]
+    fn reads_embedded_profiles() {
+        use image::codecs::{jpeg::JpegEncoder, png::PngEncoder};
+        use image::{ExtendedColorType, ImageEncoder};
+
+        let dir = tempfile::tempdir().unwrap();
+        let icc = DisplayProfile::DisplayP3.icc().unwrap();
+        let image = DynamicImage::new_rgb8(4, 4);
+
+        let jpeg = dir.path().join("profile.jpg");
+        let mut encoder = JpegEncoder::new(File::create(&jpeg).unwrap());
+        encoder.set_icc_profile(icc.clone()).unwrap();
+        encoder
+            .write_image(image.as_bytes(), 4, 4, ExtendedColorType::Rgb8)
+            .unwrap();
+        assert_eq!(read_profile(&jpeg), Some(icc.clone()));
+
+        let png = dir.path().join("profile.png");
+        let mut encoder = PngEncoder::new(File::create(&png).unwrap());
+        encoder.set_icc_profile(icc.clone()).unwrap();
+        encoder
+            .write_image(image.as_bytes(), 4, 4, ExtendedColorType::Rgb8)
+            .unwrap();
+        assert_eq!(read_profile(&png), Some(icc.clone()));
+
+        let tiff = dir.path().join("profile.tif");
+        let metadata = crate::metadata::ImageMetadata {
+            icc: Some(icc.clone().into()),
+            ..Default::default()
+        };
+        crate::metadata::write_tiff(&image, &tiff, &metadata, false).unwrap();
+        assert_eq!(read_profile(&tiff), Some(icc));
+
+        image.save(dir.path().join("plain.png")).unwrap();
+        assert_eq!(read_profile(&dir.path().join("plain.png")), None);
+    }
+
+    #[test]
+    fn srgb_is_unchanged() {
+        let srgb = Profile::new_srgb().icc().unwrap();
+        let original = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(16, 16, |x, y| {
+            image::Rgba([x as u8 * 16, y as u8 * 16, 128, 200])
+        }));
+        let mut image = original.clone();
+        convert(&mut image, &srgb, DisplayProfile::Srgb).unwrap();
+        for (a, b) in image.as_bytes().iter().zip(original.as_bytes()) {
+            assert!(a.abs_diff(*b) <= 1);
+        }
+    }
+}
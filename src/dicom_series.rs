@@ -0,0 +1,242 @@
+//! DICOM series: Browse the slices of a study folder in anatomical order.
+//!
+//! The [`crate::scrubber::Scrubber`] sorts a folder by file name. Here, the headers of
+//! all DICOM files next to the current one are read, the files of its series
+//! (SeriesInstanceUID) are kept and sorted by slice position or instance number.
+
+use crate::dicom_window::is_dicom;
+use anyhow::{Context, Result};
+use dicom_object::{DefaultDicomObject, OpenFileOptions, Tag};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// What is needed to sort a slice into its series
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SliceInfo {
+    pub path: PathBuf,
+    pub series_uid: String,
+    pub instance_number: Option<i32>,
+    /// ImagePositionPatient
+    pub position: Option<[f64; 3]>,
+    /// ImageOrientationPatient: The row and column direction
+    pub orientation: Option<[f64; 6]>,
+}
+
+fn text(obj: &DefaultDicomObject, name: &str) -> Option<String> {
+    obj.element_by_name(name)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn floats<const N: usize>(obj: &DefaultDicomObject, name: &str) -> Option<[f64; N]> {
+    obj.element_by_name(name)
+        .ok()?
+        .to_multi_float64()
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// Read the headers of a file, stopping before the pixel data
+fn read_header(path: &Path) -> Result<DefaultDicomObject> {
+    Ok(OpenFileOptions::new()
+        .read_until(Tag(0x7FE0, 0x0010))
+        .open_file(path)?)
+}
+
+impl SliceInfo {
+    fn from_object(path: &Path, obj: &DefaultDicomObject) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            series_uid: text(obj, "SeriesInstanceUID").context("No SeriesInstanceUID")?,
+            instance_number: text(obj, "InstanceNumber").and_then(|n| n.parse().ok()),
+            position: floats(obj, "ImagePositionPatient"),
+            orientation: floats(obj, "ImageOrientationPatient"),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::from_object(path, &read_header(path)?)
+    }
+
+    /// The distance along the slice normal
+    fn slice_location(&self, normal: [f64; 3]) -> Option<f64> {
+        let [x, y, z] = self.position?;
+        Some(x * normal[0] + y * normal[1] + z * normal[2])
+    }
+}
+
+/// The cross product of the row and column direction
+fn normal(orientation: [f64; 6]) -> [f64; 3] {
+    let [rx, ry, rz, cx, cy, cz] = orientation;
+    [ry * cz - rz * cy, rz * cx - rx * cz, rx * cy - ry * cx]
+}
+
+/// Sort slices by their position along the slice normal if all have one,
+/// otherwise by instance number and file name
+pub fn sort_slices(slices: &mut [SliceInfo]) {
+    let normal = slices
+        .first()
+        .and_then(|s| s.orientation)
+        .map(normal)
+        .filter(|n| slices.iter().all(|s| s.slice_location(*n).is_some()));
+    match normal {
+        Some(normal) => slices.sort_by(|a, b| {
+            a.slice_location(normal)
+                .partial_cmp(&b.slice_location(normal))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.path.cmp(&b.path))
+        }),
+        None => slices.sort_by(|a, b| {
+            a.instance_number
+                .cmp(&b.instance_number)
+                .then_with(|| a.path.cmp(&b.path))
+        }),
+    }
+}
+
+/// The slices of one series in anatomical order
+#[derive(Debug, Clone, Default)]
+pub struct Series {
+    pub uid: String,
+    pub description: Option<String>,
+    pub modality: Option<String>,
+    pub entries: Vec<PathBuf>,
+    /// The average distance of neighbouring slices in mm
+    pub spacing: Option<f64>,
+}
+
+impl Series {
+    pub fn contains(&self, path: &Path) -> bool {
+        self.entries.iter().any(|p| p == path)
+    }
+}
+
+/// Find the series of `path` among the DICOM files in its folder
+pub fn find_series(path: &Path) -> Result<Series> {
+    let obj = read_header(path)?;
+    let current = SliceInfo::from_object(path, &obj)?;
+    let folder = path.parent().context("Can't get parent")?;
+    let files = std::fs::read_dir(folder)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.is_file() && is_dicom(p))
+        .collect::<Vec<_>>();
+    let mut slices = files
+        .par_iter()
+        .filter_map(|p| SliceInfo::read(p).ok())
+        .filter(|s| s.series_uid == current.series_uid)
+        .collect::<Vec<_>>();
+    sort_slices(&mut slices);
+
+    let spacing = current.orientation.map(normal).and_then(|normal| {
+        let locations = slices
+            .iter()
+            .map(|s| s.slice_location(normal))
+            .collect::<Option<Vec<_>>>()?;
+        let (first, last) = (locations.first()?, locations.last()?);
+        (locations.len() > 1).then(|| (last - first) / (locations.len() - 1) as f64)
+    });
+
+    Ok(Series {
+        uid: current.series_uid,
+        description: text(&obj, "SeriesDescription"),
+        modality: text(&obj, "Modality"),
+        entries: slices.into_iter().map(|s| s.path).collect(),
+        spacing,
+    })
+}
+
+/// The series of the current DICOM image, when series mode is on
+#[derive(Debug, Default)]
+pub struct DicomSeriesState {
+    pub series: Option<Series>,
+    /// The file the last scan started from
+    pub source: Option<PathBuf>,
+    pub error: Option<String>,
+    pending: Option<Receiver<Result<Series>>>,
+}
+
+impl DicomSeriesState {
+    /// Scan the folder of `path` in the background, unless it belongs to the known series
+    pub fn load(&mut self, path: &Path) {
+        if self.contains(path) || self.source.as_deref() == Some(path) {
+            return;
+        }
+        self.source = Some(path.to_path_buf());
+        let (sender, receiver) = channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            _ = sender.send(find_series(&path));
+        });
+        self.error = None;
+        self.pending = Some(receiver);
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.series.as_ref().is_some_and(|s| s.contains(path))
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Pick up a scanned series. Returns it once it arrived.
+    pub fn poll(&mut self) -> Option<&Series> {
+        let result = self.pending.as_ref()?.try_recv().ok()?;
+        self.pending = None;
+        match result {
+            Ok(series) => {
+                self.series = Some(series);
+                self.series.as_ref()
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slice(name: &str, instance_number: i32, z: f64) -> SliceInfo {
+        SliceInfo {
+            path: PathBuf::from(name),
+            series_uid: "1.2.3".into(),
+            instance_number: Some(instance_number),
+            position: Some([0., 0., z]),
+            orientation: Some([1., 0., 0., 0., 1., 0.]),
+        }
+    }
+
+    #[test]
+    fn sorts_by_position() {
+        let mut slices = vec![slice("a", 1, 10.), slice("b", 2, -5.), slice("c", 3, 2.5)];
+        sort_slices(&mut slices);
+        let names = slices
+            .iter()
+            .map(|s| s.path.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b", "c", "a"]);
+
+        // Without positions, the instance number decides
+        slices[0].position = None;
+        sort_slices(&mut slices);
+        let names = slices
+            .iter()
+            .map(|s| s.path.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+}
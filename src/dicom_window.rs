@@ -0,0 +1,445 @@
+//! Window/level of monochrome DICOM images.
+//!
+//! The loader converts DICOM pixels to 8 bit once. Here, the values after the modality
+//! LUT (rescale slope and intercept) are kept so the window or VOI LUT can be changed
+//! interactively.
+
+use crate::utils::{dicom_modality_values, value_range};
+use anyhow::{bail, Result};
+use dicom_object::DefaultDicomObject;
+use image::{DynamicImage, GrayImage};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use strum::{Display, EnumIter};
+
+/// The range of values that is spread over black to white
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowLevel {
+    pub center: f32,
+    pub width: f32,
+}
+
+impl WindowLevel {
+    /// A window covering `min..max`
+    pub fn from_range(min: f32, max: f32) -> Self {
+        Self {
+            center: (min + max) / 2.,
+            width: (max - min).max(1.),
+        }
+    }
+
+    /// The linear VOI function of the DICOM standard (PS3.3 C.11.2.1.2), mapping to 0..1
+    pub fn apply(&self, value: f32) -> f32 {
+        let width = self.width.max(1.);
+        ((value - (self.center - 0.5)) / (width - 1.).max(f32::EPSILON) + 0.5).clamp(0., 1.)
+    }
+}
+
+/// Common CT windows. Their values are in Hounsfield units.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, EnumIter, Display)]
+pub enum WindowPreset {
+    /// The window or VOI LUT stored in the file
+    #[default]
+    #[strum(to_string = "From file")]
+    File,
+    #[strum(to_string = "Full range")]
+    FullRange,
+    #[strum(to_string = "Soft tissue")]
+    SoftTissue,
+    Lung,
+    Bone,
+    Brain,
+    Custom,
+}
+
+impl WindowPreset {
+    /// The fixed window of a preset
+    pub fn window(&self) -> Option<WindowLevel> {
+        let (center, width) = match self {
+            WindowPreset::SoftTissue => (40., 400.),
+            WindowPreset::Lung => (-600., 1500.),
+            WindowPreset::Bone => (400., 1800.),
+            WindowPreset::Brain => (40., 80.),
+            _ => return None,
+        };
+        Some(WindowLevel { center, width })
+    }
+}
+
+/// A VOI LUT table from the VOI LUT Sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiLut {
+    /// The value that maps to the first entry
+    pub first_mapped: i32,
+    pub bits: u32,
+    pub data: Vec<u16>,
+    pub explanation: Option<String>,
+}
+
+impl VoiLut {
+    /// Look up a value, mapping to 0..1
+    pub fn apply(&self, value: f32) -> f32 {
+        let max_index = self.data.len().saturating_sub(1);
+        let index = (value.round() as i64 - self.first_mapped as i64).clamp(0, max_index as i64);
+        let max_value = ((1u32 << self.bits.clamp(1, 16)) - 1) as f32;
+        self.data
+            .get(index as usize)
+            .map(|v| *v as f32 / max_value)
+            .unwrap_or_default()
+    }
+}
+
+/// How the file wants its values to be displayed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoiSettings {
+    pub windows: Vec<WindowLevel>,
+    pub lut: Option<VoiLut>,
+    /// MONOCHROME1 shows low values as white
+    pub inverted: bool,
+}
+
+impl VoiSettings {
+    pub fn read(obj: &DefaultDicomObject) -> Self {
+        let floats = |name| {
+            obj.element_by_name(name)
+                .ok()
+                .and_then(|e| e.to_multi_float32().ok())
+                .unwrap_or_default()
+        };
+        let windows = floats("WindowCenter")
+            .into_iter()
+            .zip(floats("WindowWidth"))
+            .map(|(center, width)| WindowLevel { center, width })
+            .filter(|w| w.width >= 1.)
+            .collect();
+
+        let lut = obj
+            .element_by_name("VOILUTSequence")
+            .ok()
+            .and_then(|e| e.items())
+            .and_then(|items| items.first())
+            .and_then(|item| {
+                let descriptor = item
+                    .element_by_name("LUTDescriptor")
+                    .ok()?
+                    .to_multi_int::<i32>()
+                    .ok()?;
+                let [_, first_mapped, bits, ..] = descriptor[..] else {
+                    return None;
+                };
+                Some(VoiLut {
+                    first_mapped,
+                    bits: bits as u32,
+                    data: item
+                        .element_by_name("LUTData")
+                        .ok()?
+                        .to_multi_int::<u16>()
+                        .ok()?,
+                    explanation: item
+                        .element_by_name("LUTExplanation")
+                        .ok()
+                        .and_then(|e| e.to_str().ok())
+                        .map(|s| s.trim().to_string()),
+                })
+            })
+            .filter(|lut| !lut.data.is_empty());
+
+        let inverted = obj
+            .element_by_name("PhotometricInterpretation")
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .is_some_and(|p| p.trim() == "MONOCHROME1");
+
+        Self {
+            windows,
+            lut,
+            inverted,
+        }
+    }
+}
+
+/// The modality values of one frame
+#[derive(Debug, Clone)]
+pub struct DicomFrame {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f32>,
+    pub voi: VoiSettings,
+}
+
+/// Whether `path` is a DICOM file, by its extension or the `DICM` marker after the preamble
+pub fn is_dicom(path: &Path) -> bool {
+    if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("dcm") || e.eq_ignore_ascii_case("ima"))
+    {
+        return true;
+    }
+    let mut header = [0; 132];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| &header[128..] == b"DICM")
+}
+
+/// Decode a frame of a monochrome DICOM image
+pub fn read_frame(path: &Path, frame: u32) -> Result<DicomFrame> {
+    let obj = dicom_object::open_file(path)?;
+    let (values, width) = dicom_modality_values(&obj, frame)?;
+    if width == 0 {
+        bail!("The image is empty");
+    }
+    Ok(DicomFrame {
+        width,
+        height: (values.len() / width as usize) as u32,
+        values,
+        voi: VoiSettings::read(&obj),
+    })
+}
+
+impl DicomFrame {
+    /// Apply the VOI LUT of the file or a window
+    pub fn render(&self, window: Option<&WindowLevel>) -> Option<DynamicImage> {
+        let inverted = self.voi.inverted;
+        let lut = self.voi.lut.as_ref();
+        let data = self
+            .values
+            .par_iter()
+            .map(|v| {
+                let gray = match (window, lut) {
+                    (Some(window), _) => window.apply(*v),
+                    (None, Some(lut)) => lut.apply(*v),
+                    (None, None) => 0.,
+                };
+                let gray = if inverted { 1. - gray } else { gray };
+                (gray * 255. + 0.5) as u8
+            })
+            .collect::<Vec<_>>();
+        GrayImage::from_raw(self.width, self.height, data).map(DynamicImage::ImageLuma8)
+    }
+}
+
+/// The window of the current DICOM image, adjusted in the info panel or by dragging
+/// with the right mouse button
+#[derive(Debug, Default)]
+pub struct DicomWindowState {
+    /// The file and frame the values belong to
+    pub source: Option<(PathBuf, u32)>,
+    pub frame: Option<DicomFrame>,
+    pub preset: WindowPreset,
+    /// The window on display. `None` means the VOI LUT of the file.
+    pub window: Option<WindowLevel>,
+    /// Smallest and largest value of the frame
+    pub range: Option<(f32, f32)>,
+    pub error: Option<String>,
+    /// The right mouse button went down on the image
+    pub dragging: bool,
+    changed: bool,
+    pending: Option<Receiver<Result<DicomFrame>>>,
+    /// The last path checked by [`Self::is_dicom`] and the answer
+    checked: Option<(PathBuf, bool)>,
+}
+
+impl DicomWindowState {
+    /// Like [`is_dicom`], but only reads the file when the path changes
+    pub fn is_dicom(&mut self, path: &Path) -> bool {
+        match &self.checked {
+            Some((checked, answer)) if checked == path => *answer,
+            _ => {
+                let answer = is_dicom(path);
+                self.checked = Some((path.to_path_buf(), answer));
+                answer
+            }
+        }
+    }
+
+    /// Decode the values of `frame` in the background, unless they are known already.
+    /// The window is kept when only the frame changes.
+    pub fn load(&mut self, path: &Path, frame: u32) {
+        if self
+            .source
+            .as_ref()
+            .is_some_and(|(p, f)| p == path && *f == frame)
+        {
+            return;
+        }
+        if self.source.as_ref().is_some_and(|(p, _)| p != path) {
+            self.clear(false);
+        }
+        self.source = Some((path.to_path_buf(), frame));
+        let (sender, receiver) = channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            _ = sender.send(read_frame(&path, frame));
+        });
+        self.error = None;
+        self.pending = Some(receiver);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Whether the current image can be windowed
+    pub fn is_active(&self) -> bool {
+        self.frame.is_some()
+    }
+
+    /// Pick up the values decoded by [`DicomWindowState::load`]
+    pub fn poll(&mut self) {
+        let Some(result) = self.pending.as_ref().and_then(|r| r.try_recv().ok()) else {
+            return;
+        };
+        self.pending = None;
+        match result {
+            Ok(frame) => {
+                self.range = value_range(frame.values.iter().copied());
+                self.frame = Some(frame);
+                self.set_preset(self.preset);
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// The window of the file: Its first window, then its VOI LUT, then the full range
+    fn file_window(&self) -> Option<WindowLevel> {
+        let frame = self.frame.as_ref()?;
+        if let Some(window) = frame.voi.windows.first() {
+            return Some(*window);
+        }
+        if frame.voi.lut.is_some() {
+            return None;
+        }
+        self.range
+            .map(|(min, max)| WindowLevel::from_range(min, max))
+    }
+
+    pub fn set_preset(&mut self, preset: WindowPreset) {
+        self.preset = preset;
+        self.window = match preset {
+            WindowPreset::File => self.file_window(),
+            WindowPreset::FullRange => self
+                .range
+                .map(|(min, max)| WindowLevel::from_range(min, max)),
+            WindowPreset::Custom => self.window.or_else(|| self.file_window()),
+            _ => preset.window(),
+        };
+        self.changed = true;
+    }
+
+    pub fn set_window(&mut self, window: WindowLevel) {
+        self.preset = WindowPreset::Custom;
+        self.window = Some(WindowLevel {
+            center: window.center,
+            width: window.width.max(1.),
+        });
+        self.changed = true;
+    }
+
+    /// Adjust the window by a mouse movement: Horizontal changes the width, vertical the center
+    pub fn drag(&mut self, dx: f32, dy: f32) {
+        if dx == 0. && dy == 0. {
+            return;
+        }
+        let Some((min, max)) = self.range else {
+            return;
+        };
+        let window = self
+            .window
+            .unwrap_or_else(|| WindowLevel::from_range(min, max));
+        // Dragging across a few hundred pixels covers the whole range
+        let speed = (max - min).max(1.) / 500.;
+        self.set_window(WindowLevel {
+            center: window.center - dy * speed,
+            width: window.width + dx * speed,
+        });
+    }
+
+    /// The image to display, if the window changed since the last call
+    pub fn take_image(&mut self) -> Option<DynamicImage> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        self.frame.as_ref()?.render(self.window.as_ref())
+    }
+
+    /// Whether the values of a new image are being decoded to show it with a kept window
+    pub fn keeps_window(&self) -> bool {
+        self.pending.is_some() && self.window.is_some()
+    }
+
+    /// Forget everything, for example because another image was loaded.
+    /// With `keep_window`, such as for the next slice of a series, the window stays.
+    pub fn clear(&mut self, keep_window: bool) {
+        *self = Self {
+            preset: match self.preset {
+                WindowPreset::Custom if !keep_window => WindowPreset::File,
+                preset => preset,
+            },
+            window: self.window.filter(|_| keep_window),
+            checked: self.checked.take(),
+            ..Default::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_window() {
+        let window = WindowLevel {
+            center: 40.,
+            width: 400.,
+        };
+        assert_eq!(window.apply(-1000.), 0.);
+        assert_eq!(window.apply(1000.), 1.);
+        assert!((window.apply(40.) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn applies_voi_lut() {
+        let frame = DicomFrame {
+            width: 3,
+            height: 1,
+            values: vec![-10., 101., 1000.],
+            voi: VoiSettings {
+                windows: vec![],
+                lut: Some(VoiLut {
+                    first_mapped: 100,
+                    bits: 8,
+                    data: vec![0, 128, 255],
+                    explanation: None,
+                }),
+                inverted: true,
+            },
+        };
+        let image = frame.render(None).unwrap().into_luma8();
+        assert_eq!(image.as_raw(), &[255, 127, 0]);
+    }
+
+    #[test]
+    fn detects_dicom_without_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut preamble = vec![0; 128];
+        preamble.extend_from_slice(b"DICM");
+        let cases = [
+            ("IM0001", preamble.clone(), true),
+            ("slice.1", preamble, true),
+            ("empty.dcm", vec![], true),
+            ("IM0002", vec![0; 200], false),
+            ("short", b"DICM".to_vec(), false),
+        ];
+        for (name, data, expected) in cases {
+            let path = dir.path().join(name);
+            std::fs::write(&path, data).unwrap();
+            assert_eq!(is_dicom(&path), expected, "{name}");
+        }
+        assert!(!is_dicom(&dir.path().join("missing")));
+    }
+}
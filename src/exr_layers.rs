@@ -0,0 +1,365 @@
+//! Browse the layers and channels of OpenEXR files, such as depth, normals or light passes.
+//!
+//! The loader only shows the first RGBA layer. Here, the headers are read to list all
+//! layers and channels, and the chosen one is decoded in the background.
+
+use crate::utils::value_range;
+use anyhow::{bail, Context, Result};
+use exr::meta::MetaData;
+use exr::prelude::read_all_flat_layers_from_file;
+use image::{DynamicImage, ImageBuffer, Rgba32FImage};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// The channel names of one layer (an EXR "part")
+#[derive(Debug, Clone)]
+pub struct ExrLayerInfo {
+    pub name: Option<String>,
+    pub channels: Vec<String>,
+}
+
+/// What to display of an EXR file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExrView {
+    /// The R, G, B and, if present, A channels that share a prefix such as `diffuse.`
+    Color { layer: usize, prefix: String },
+    /// A single channel, shown as grayscale
+    Channel { layer: usize, channel: String },
+}
+
+impl ExrView {
+    pub fn label(&self, layers: &[ExrLayerInfo]) -> String {
+        let (layer, part) = match self {
+            ExrView::Color { layer, prefix } => (layer, format!("{prefix}RGB")),
+            ExrView::Channel { layer, channel } => (layer, channel.clone()),
+        };
+        match layers.get(*layer).and_then(|l| l.name.as_ref()) {
+            Some(name) => format!("{name} / {part}"),
+            None => part,
+        }
+    }
+}
+
+/// Read the layers and their channels from the headers, without decoding pixels
+pub fn read_layers(path: &Path) -> Result<Vec<ExrLayerInfo>> {
+    let meta = MetaData::read_from_file(path, false)?;
+    Ok(meta
+        .headers
+        .iter()
+        .map(|header| ExrLayerInfo {
+            name: header
+                .own_attributes
+                .layer_name
+                .as_ref()
+                .map(|n| n.to_string()),
+            channels: header
+                .channels
+                .list
+                .iter()
+                .map(|c| c.name.to_string())
+                .collect(),
+        })
+        .collect())
+}
+
+/// All views of the layers: Color groups first, then every single channel
+pub fn views(layers: &[ExrLayerInfo]) -> Vec<ExrView> {
+    let mut views = vec![];
+    for (index, layer) in layers.iter().enumerate() {
+        let has = |name: String| layer.channels.contains(&name);
+        for channel in &layer.channels {
+            let Some(prefix) = channel.strip_suffix('R') else {
+                continue;
+            };
+            if (prefix.is_empty() || prefix.ends_with('.'))
+                && has(format!("{prefix}G"))
+                && has(format!("{prefix}B"))
+            {
+                views.push(ExrView::Color {
+                    layer: index,
+                    prefix: prefix.to_string(),
+                });
+            }
+        }
+    }
+    for (index, layer) in layers.iter().enumerate() {
+        for channel in &layer.channels {
+            views.push(ExrView::Channel {
+                layer: index,
+                channel: channel.clone(),
+            });
+        }
+    }
+    views
+}
+
+/// The samples of a view, one plane per channel
+#[derive(Debug, Clone)]
+pub struct DecodedView {
+    pub view: ExrView,
+    pub width: u32,
+    pub height: u32,
+    planes: Vec<Vec<f32>>,
+}
+
+/// Decode the channels of `view`
+pub fn decode_view(path: &Path, view: &ExrView) -> Result<DecodedView> {
+    let image = read_all_flat_layers_from_file(path)?;
+    let (layer_index, names) = match view {
+        ExrView::Color { layer, prefix } => (
+            *layer,
+            ["R", "G", "B", "A"]
+                .iter()
+                .map(|c| format!("{prefix}{c}"))
+                .collect::<Vec<_>>(),
+        ),
+        ExrView::Channel { layer, channel } => (*layer, vec![channel.clone()]),
+    };
+    let layer = image
+        .layer_data
+        .get(layer_index)
+        .context("The layer does not exist")?;
+    let (width, height) = (layer.size.width(), layer.size.height());
+
+    let mut planes = vec![];
+    for (index, name) in names.iter().enumerate() {
+        let Some(channel) = layer
+            .channel_data
+            .list
+            .iter()
+            .find(|c| c.name.to_string() == *name)
+        else {
+            // Alpha is optional
+            if index == 3 {
+                break;
+            }
+            bail!("Channel {name} not found");
+        };
+        let values = channel.sample_data.values_as_f32().collect::<Vec<_>>();
+        if values.len() != width * height {
+            bail!("Subsampled channel {name} is not supported");
+        }
+        planes.push(values);
+    }
+
+    Ok(DecodedView {
+        view: view.clone(),
+        width: width as u32,
+        height: height as u32,
+        planes,
+    })
+}
+
+/// How single channels are mapped to gray values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalization {
+    /// Use the smallest and largest value of the channel
+    pub auto: bool,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            auto: true,
+            min: 0.0,
+            max: 1.0,
+        }
+    }
+}
+
+impl DecodedView {
+    /// The smallest and largest finite value of a single channel
+    pub fn range(&self) -> Option<(f32, f32)> {
+        match self.planes.as_slice() {
+            [gray] => value_range(gray.iter().copied()),
+            _ => None,
+        }
+    }
+
+    /// A float image for color views, or a 16 bit grayscale image normalized to `min..max`
+    pub fn to_image(&self, normalization: &Normalization) -> Option<DynamicImage> {
+        match self.planes.as_slice() {
+            [gray] => {
+                let (min, max) = (normalization.min, normalization.max);
+                let scale = if max > min { 1.0 / (max - min) } else { 0.0 };
+                let data = gray
+                    .par_iter()
+                    .map(|v| (((v - min) * scale).clamp(0.0, 1.0) * 65535.0 + 0.5) as u16)
+                    .collect::<Vec<_>>();
+                ImageBuffer::from_raw(self.width, self.height, data).map(DynamicImage::ImageLuma16)
+            }
+            [r, g, b, alpha @ ..] => {
+                let alpha = alpha.first();
+                let data = (0..r.len())
+                    .flat_map(|i| [r[i], g[i], b[i], alpha.map(|a| a[i]).unwrap_or(1.0)])
+                    .collect::<Vec<_>>();
+                Rgba32FImage::from_raw(self.width, self.height, data)
+                    .map(DynamicImage::ImageRgba32F)
+            }
+            _ => None,
+        }
+    }
+
+    /// The channel values at a position
+    pub fn value_at(&self, x: u32, y: u32) -> Option<String> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let index = y as usize * self.width as usize + x as usize;
+        Some(
+            self.planes
+                .iter()
+                .map(|plane| format!("{:.4}", plane[index]))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+    }
+}
+
+/// Layers of the current EXR image and the view picked in the info panel
+#[derive(Debug, Default)]
+pub struct ExrLayerState {
+    /// The file the layers were read from
+    pub path: Option<PathBuf>,
+    pub layers: Vec<ExrLayerInfo>,
+    pub views: Vec<ExrView>,
+    pub decoded: Option<DecodedView>,
+    pub normalization: Normalization,
+    pub error: Option<String>,
+    pending: Option<Receiver<Result<DecodedView>>>,
+}
+
+impl ExrLayerState {
+    /// Read the layers of `path`, unless they are known already
+    pub fn load(&mut self, path: &Path) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+        self.clear();
+        self.path = Some(path.to_path_buf());
+        match read_layers(path) {
+            Ok(layers) => {
+                self.views = views(&layers);
+                self.layers = layers;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Decode a view in the background
+    pub fn select(&mut self, view: ExrView) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            _ = sender.send(decode_view(&path, &view));
+        });
+        self.error = None;
+        self.pending = Some(receiver);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Pick up the result of [`ExrLayerState::select`]. Returns true once it arrived.
+    pub fn poll(&mut self) -> bool {
+        let Some(result) = self.pending.as_ref().and_then(|r| r.try_recv().ok()) else {
+            return false;
+        };
+        self.pending = None;
+        match result {
+            Ok(decoded) => self.decoded = Some(decoded),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+        true
+    }
+
+    /// The image to display for the decoded view
+    pub fn render(&mut self) -> Option<DynamicImage> {
+        let decoded = self.decoded.as_ref()?;
+        if self.normalization.auto {
+            if let Some((min, max)) = decoded.range() {
+                self.normalization.min = min;
+                self.normalization.max = max;
+            }
+        }
+        decoded.to_image(&self.normalization)
+    }
+
+    /// Forget everything, for example because another image was loaded
+    pub fn clear(&mut self) {
+        *self = Self {
+            normalization: self.normalization,
+            ..Default::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_color_channels() {
+        let layers = vec![ExrLayerInfo {
+            name: None,
+            channels: [
+                "A",
+                "B",
+                "G",
+                "R",
+                "Z",
+                "diffuse.B",
+                "diffuse.G",
+                "diffuse.R",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }];
+        let views = views(&layers);
+        assert_eq!(
+            views[..2],
+            [
+                ExrView::Color {
+                    layer: 0,
+                    prefix: "".into()
+                },
+                ExrView::Color {
+                    layer: 0,
+                    prefix: "diffuse.".into()
+                }
+            ]
+        );
+        assert_eq!(views.len(), 2 + 8);
+        assert_eq!(views[1].label(&layers), "diffuse.RGB");
+    }
+
+    #[test]
+    fn normalizes_single_channels() {
+        let decoded = DecodedView {
+            view: ExrView::Channel {
+                layer: 0,
+                channel: "Z".into(),
+            },
+            width: 2,
+            height: 2,
+            planes: vec![vec![10.0, 20.0, f32::INFINITY, 15.0]],
+        };
+        assert_eq!(decoded.range(), Some((10.0, 20.0)));
+        let image = decoded
+            .to_image(&Normalization {
+                auto: false,
+                min: 10.0,
+                max: 20.0,
+            })
+            .unwrap()
+            .into_luma16();
+        assert_eq!(image.as_raw(), &[0, 65535, 65535, 32768]);
+    }
+}
@@ -2,11 +2,15 @@
 //!
 //! To add more formats, add a variant to the `[FileEncoder]` struct.
 
+use crate::metadata::{write_tiff, ImageMetadata};
 use crate::ui::EguiExt;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::gif::{GifEncoder, Repeat};
 use image::codecs::jpeg::JpegEncoder;
+use image::codecs::openexr::OpenExrEncoder;
 use image::codecs::png::{CompressionType, PngEncoder};
-use image::{DynamicImage, ImageEncoder};
+use image::{ColorType, Delay, DynamicImage, GenericImageView, ImageEncoder};
 use notan::egui::Ui;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
@@ -23,12 +27,34 @@ pub enum CompressionLevel {
     Fast,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Display, EnumIter)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Display, EnumIter)]
 pub enum FileEncoder {
-    Jpg { quality: u32 },
-    Png { compressionlevel: CompressionLevel },
+    Jpg {
+        quality: u32,
+    },
+    Png {
+        compressionlevel: CompressionLevel,
+    },
     Bmp,
-    WebP,
+    Gif,
+    WebP {
+        lossless: bool,
+        quality: u32,
+    },
+    Tiff,
+    Exr,
+    Avif {
+        quality: u32,
+        /// 1 is the slowest and smallest, 10 the fastest
+        speed: u32,
+    },
+    #[cfg(feature = "jxl_encode")]
+    Jxl {
+        /// Visual distance to the original. 0 is lossless, 1 visually lossless.
+        distance: f32,
+        /// 1 is the fastest, 9 the slowest and smallest
+        effort: u32,
+    },
 }
 
 impl Default for FileEncoder {
@@ -40,13 +66,22 @@ impl Default for FileEncoder {
 }
 
 impl FileEncoder {
-    pub fn matching_variant(path: &Path, variants: &Vec<Self>) -> Self {
+    /// The extension of `path` as returned by [`FileEncoder::ext`], with aliases resolved
+    pub fn normalized_ext(path: &Path) -> String {
         let ext = path
             .extension()
             .map(|e| e.to_string_lossy().to_string())
             .unwrap_or_default()
-            .to_lowercase()
-            .replace("jpeg", "jpg");
+            .to_lowercase();
+        match ext.as_str() {
+            "jpeg" => "jpg".into(),
+            "tif" => "tiff".into(),
+            _ => ext,
+        }
+    }
+
+    pub fn matching_variant(path: &Path, variants: &[Self]) -> Self {
+        let ext = Self::normalized_ext(path);
 
         for v in variants {
             if v.ext() == ext {
@@ -59,15 +94,59 @@ impl FileEncoder {
         }
     }
 
+    /// Override the quality of encoders that have a quality setting
+    pub fn set_quality(&mut self, new_quality: u32) {
+        let new_quality = new_quality.min(100);
+        match self {
+            FileEncoder::Jpg { quality } | FileEncoder::Avif { quality, .. } => {
+                *quality = new_quality
+            }
+            FileEncoder::WebP { lossless, quality } => {
+                *lossless = false;
+                *quality = new_quality;
+            }
+            #[cfg(feature = "jxl_encode")]
+            FileEncoder::Jxl { distance, .. } => *distance = jxl_distance(new_quality),
+            FileEncoder::Png { .. }
+            | FileEncoder::Bmp
+            | FileEncoder::Gif
+            | FileEncoder::Tiff
+            | FileEncoder::Exr => {}
+        }
+    }
+
+    /// Whether [`FileEncoder::save_animation`] can write more than one frame
+    pub fn supports_animation(&self) -> bool {
+        matches!(
+            self,
+            FileEncoder::Gif | FileEncoder::Png { .. } | FileEncoder::WebP { .. }
+        )
+    }
+
+    /// Whether float images are written without losing their range
+    pub fn supports_float(&self) -> bool {
+        matches!(self, FileEncoder::Exr | FileEncoder::Tiff)
+    }
+
     pub fn ext(&self) -> String {
         self.to_string().to_lowercase()
     }
 
     pub fn save(&self, image: &DynamicImage, path: &Path) -> Result<()> {
+        self.save_with_metadata(image, path, &ImageMetadata::default(), false)
+    }
+
+    /// Save and carry over metadata, as far as the format can hold it
+    pub fn save_with_metadata(
+        &self,
+        image: &DynamicImage,
+        path: &Path,
+        metadata: &ImageMetadata,
+        strip_location: bool,
+    ) -> Result<()> {
         match self {
-            FileEncoder::Jpg { quality } => {
-                let w = File::create(path)?;
-                JpegEncoder::new_with_quality(w, *quality as u8).encode_image(image)?;
+            FileEncoder::Jpg { .. } | FileEncoder::WebP { .. } => {
+                std::fs::write(path, self.encode_to_memory(image)?)?;
             }
             FileEncoder::Png { compressionlevel } => {
                 let file = File::create(path)?;
@@ -81,21 +160,199 @@ impl FileEncoder {
                     },
                     image::codecs::png::FilterType::default(),
                 );
+                // PNG has no float samples, 16 bit keeps most of the precision
+                let image = match image.color() {
+                    ColorType::Rgb32F => &DynamicImage::ImageRgb16(image.to_rgb16()),
+                    ColorType::Rgba32F => &DynamicImage::ImageRgba16(image.to_rgba16()),
+                    _ => image,
+                };
                 encoder.write_image(
                     image.as_bytes(),
                     image.width(),
                     image.height(),
-                    image::ExtendedColorType::Rgba8,
+                    image.color().into(),
                 )?;
             }
             FileEncoder::Bmp => {
                 image.save_with_format(path, image::ImageFormat::Bmp)?;
             }
-            FileEncoder::WebP => {
-                image.save_with_format(path, image::ImageFormat::WebP)?;
+            FileEncoder::Gif => {
+                DynamicImage::ImageRgba8(image.to_rgba8())
+                    .save_with_format(path, image::ImageFormat::Gif)?;
+            }
+            FileEncoder::Tiff => {
+                write_tiff(image, path, metadata, strip_location)?;
+            }
+            FileEncoder::Exr => {
+                let writer = BufWriter::new(File::create(path)?);
+                // OpenEXR only stores float samples
+                let image = match image.color() {
+                    ColorType::Rgb32F | ColorType::Rgba32F => image,
+                    ColorType::L8 | ColorType::L16 | ColorType::Rgb8 | ColorType::Rgb16 => {
+                        &DynamicImage::ImageRgb32F(image.to_rgb32f())
+                    }
+                    _ => &DynamicImage::ImageRgba32F(image.to_rgba32f()),
+                };
+                OpenExrEncoder::new(writer).write_image(
+                    image.as_bytes(),
+                    image.width(),
+                    image.height(),
+                    image.color().into(),
+                )?;
+            }
+            FileEncoder::Avif { quality, speed } => {
+                let writer = BufWriter::new(File::create(path)?);
+                let rgba = image.to_rgba8();
+                AvifEncoder::new_with_speed_quality(
+                    writer,
+                    (*speed).clamp(1, 10) as u8,
+                    (*quality).clamp(1, 100) as u8,
+                )
+                .write_image(
+                    rgba.as_raw(),
+                    rgba.width(),
+                    rgba.height(),
+                    image::ExtendedColorType::Rgba8,
+                )?;
+            }
+            #[cfg(feature = "jxl_encode")]
+            FileEncoder::Jxl { distance, effort } => {
+                use jpegxl_rs::encode::{EncoderResult, EncoderSpeed};
+                let speed = match effort {
+                    0 | 1 => EncoderSpeed::Lightning,
+                    2 => EncoderSpeed::Thunder,
+                    3 => EncoderSpeed::Falcon,
+                    4 => EncoderSpeed::Cheetah,
+                    5 => EncoderSpeed::Hare,
+                    6 => EncoderSpeed::Wombat,
+                    7 => EncoderSpeed::Squirrel,
+                    8 => EncoderSpeed::Kitten,
+                    _ => EncoderSpeed::Tortoise,
+                };
+                let has_alpha = image.color().has_alpha();
+                let samples = if has_alpha {
+                    image.to_rgba8().into_raw()
+                } else {
+                    image.to_rgb8().into_raw()
+                };
+                let mut encoder = jpegxl_rs::encoder_builder()
+                    .has_alpha(has_alpha)
+                    .lossless(*distance == 0.0)
+                    .quality(distance.clamp(0.0, 15.0))
+                    .speed(speed)
+                    .build()?;
+                let result: EncoderResult<u8> =
+                    encoder.encode::<u8, u8>(&samples, image.width(), image.height())?;
+                std::fs::write(path, result.data)?;
+            }
+        }
+
+        if !metadata.is_empty()
+            && matches!(
+                self,
+                FileEncoder::Jpg { .. } | FileEncoder::Png { .. } | FileEncoder::WebP { .. }
+            )
+        {
+            metadata.apply(path, strip_location)?;
+        }
+        Ok(())
+    }
+
+    /// Encode a still image without writing it, to find out its size.
+    /// Only supported by the lossy encoders JPEG and WebP.
+    pub fn encode_to_memory(&self, image: &DynamicImage) -> Result<Vec<u8>> {
+        match self {
+            FileEncoder::Jpg { quality } => {
+                let mut data = vec![];
+                // JPEG only holds 8 bit gray or color samples
+                let image = match image.color() {
+                    ColorType::L8 | ColorType::Rgb8 => image,
+                    ColorType::L16 => &DynamicImage::ImageLuma8(image.to_luma8()),
+                    _ => &DynamicImage::ImageRgb8(image.to_rgb8()),
+                };
+                JpegEncoder::new_with_quality(&mut data, *quality as u8).encode_image(image)?;
+                Ok(data)
+            }
+            FileEncoder::WebP { lossless, quality } => {
+                let rgba = image.to_rgba8();
+                let mut encoder = webp_encoder(rgba.dimensions(), *lossless, *quality)?;
+                encoder
+                    .add_frame(rgba.as_raw(), 0)
+                    .map_err(|e| anyhow!("{:?}", e))?;
+                let data = encoder.finalize(0).map_err(|e| anyhow!("{:?}", e))?;
+                Ok(data.to_vec())
             }
+            _ => bail!("{self} can't be encoded in memory"),
         }
+    }
 
+    /// Save frames with their delay in ms as an animation. Encoders without animation support
+    /// write the first frame only.
+    pub fn save_animation(&self, frames: &[(DynamicImage, u16)], path: &Path) -> Result<()> {
+        let Some((first, _)) = frames.first() else {
+            bail!("There are no frames to save");
+        };
+        if frames.len() == 1 || !self.supports_animation() {
+            return self.save(first, path);
+        }
+        let (width, height) = first.dimensions();
+        if frames
+            .iter()
+            .any(|(frame, _)| frame.dimensions() != (width, height))
+        {
+            bail!("All frames of an animation must have the same size");
+        }
+
+        match self {
+            FileEncoder::Gif => {
+                let writer = BufWriter::new(File::create(path)?);
+                let mut encoder = GifEncoder::new_with_speed(writer, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                encoder.encode_frames(frames.iter().map(|(frame, delay)| {
+                    image::Frame::from_parts(
+                        frame.to_rgba8(),
+                        0,
+                        0,
+                        Delay::from_numer_denom_ms(*delay as u32, 1),
+                    )
+                }))?;
+            }
+            FileEncoder::Png { compressionlevel } => {
+                let writer = BufWriter::new(File::create(path)?);
+                let mut encoder = png::Encoder::new(writer, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_compression(match compressionlevel {
+                    CompressionLevel::Best => png::Compression::Best,
+                    CompressionLevel::Default => png::Compression::Default,
+                    CompressionLevel::Fast => png::Compression::Fast,
+                });
+                // Loop forever, like the viewer does
+                encoder.set_animated(frames.len() as u32, 0)?;
+                let mut writer = encoder.write_header()?;
+                for (frame, delay) in frames {
+                    writer.set_frame_delay(*delay, 1000)?;
+                    writer.write_image_data(frame.to_rgba8().as_raw())?;
+                }
+                writer.finish()?;
+            }
+            FileEncoder::WebP { lossless, quality } => {
+                let mut encoder = webp_encoder((width, height), *lossless, *quality)?;
+                let mut timestamp = 0;
+                for (frame, delay) in frames {
+                    encoder
+                        .add_frame(frame.to_rgba8().as_raw(), timestamp)
+                        .map_err(|e| anyhow!("{:?}", e))?;
+                    // Timestamps have to increase, even for frames without delay
+                    timestamp += (*delay).max(1) as i32;
+                }
+                let data = encoder
+                    .finalize(timestamp)
+                    .map_err(|e| anyhow!("{:?}", e))?;
+                std::fs::write(path, &*data)?;
+            }
+            _ => return self.save(first, path),
+        }
         Ok(())
     }
 
@@ -109,7 +366,153 @@ impl FileEncoder {
                 compressionlevel: _,
             } => {}
             FileEncoder::Bmp => {}
-            FileEncoder::WebP => {}
+            FileEncoder::Gif => {}
+            FileEncoder::WebP { lossless, quality } => {
+                ui.styled_checkbox(lossless, "Lossless");
+                if !*lossless {
+                    ui.label("Quality");
+                    ui.styled_slider(quality, 0..=100);
+                }
+            }
+            FileEncoder::Tiff => {}
+            FileEncoder::Exr => {}
+            FileEncoder::Avif { quality, speed } => {
+                ui.label("Quality");
+                ui.styled_slider(quality, 1..=100);
+                ui.label("Speed");
+                ui.styled_slider(speed, 1..=10);
+            }
+            #[cfg(feature = "jxl_encode")]
+            FileEncoder::Jxl { distance, effort } => {
+                ui.label("Distance")
+                    .on_hover_text("0 is lossless, 1 is visually lossless");
+                ui.styled_slider(distance, 0.0..=15.0);
+                ui.label("Effort");
+                ui.styled_slider(effort, 1..=9);
+            }
+        }
+    }
+}
+
+fn webp_encoder(
+    dimensions: (u32, u32),
+    lossless: bool,
+    quality: u32,
+) -> Result<webp_animation::Encoder> {
+    use webp_animation::prelude::*;
+    let encoding_type = if lossless {
+        EncodingType::Lossless
+    } else {
+        EncodingType::Lossy(LossyEncodingConfig::default())
+    };
+    Encoder::new_with_options(
+        dimensions,
+        EncoderOptions {
+            encoding_config: Some(EncodingConfig {
+                encoding_type,
+                quality: quality as f32,
+                method: 4,
+            }),
+            ..Default::default()
+        },
+    )
+    .map_err(|e| anyhow!("{:?}", e))
+}
+
+/// Map a 0-100 quality to a JPEG XL distance, the same way libjxl does
+#[cfg(feature = "jxl_encode")]
+fn jxl_distance(quality: u32) -> f32 {
+    let quality = quality as f32;
+    if quality >= 100. {
+        0.
+    } else if quality >= 30. {
+        0.1 + (100. - quality) * 0.09
+    } else {
+        53. / 3000. * quality * quality - 23. / 20. * quality + 25.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_bit_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let png_16 =
+            image::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/pngtest_16bit.png"))
+                .unwrap();
+        let float = DynamicImage::ImageRgba32F(png_16.to_rgba32f());
+
+        for (encoder, image, expected) in [
+            (FileEncoder::default(), &png_16, png_16.color()),
+            (FileEncoder::Tiff, &png_16, png_16.color()),
+            (FileEncoder::Tiff, &float, ColorType::Rgba32F),
+            (FileEncoder::Exr, &float, ColorType::Rgba32F),
+            (FileEncoder::default(), &float, ColorType::Rgba16),
+        ] {
+            let path = dir.path().join(format!("bit_depth.{}", encoder.ext()));
+            encoder.save(image, &path).unwrap();
+            // Read back the way the viewer does
+            let saved = crate::image_loader::open_image(&path, None, None)
+                .unwrap()
+                .recv()
+                .unwrap()
+                .get_image()
+                .unwrap();
+            assert_eq!(saved.color(), expected, "{encoder}");
+            assert_eq!(
+                (saved.width(), saved.height()),
+                (image.width(), image.height())
+            );
+        }
+    }
+
+    #[test]
+    fn writes_jpeg_from_any_depth() {
+        let png_16 =
+            image::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/pngtest_16bit.png"))
+                .unwrap();
+        let encoder = FileEncoder::Jpg { quality: 80 };
+        for image in [
+            png_16.clone(),
+            DynamicImage::ImageRgba32F(png_16.to_rgba32f()),
+            DynamicImage::ImageLuma16(png_16.to_luma16()),
+        ] {
+            let data = encoder.encode_to_memory(&image).unwrap();
+            let saved = image::load_from_memory(&data).unwrap();
+            assert_eq!(
+                saved.dimensions(),
+                image.dimensions(),
+                "{:?}",
+                image.color()
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_animation_frames() {
+        let frames = (0..3u8)
+            .map(|i| {
+                let image = image::RgbaImage::from_pixel(4, 3, image::Rgba([i * 80, 0, 0, 255]));
+                (DynamicImage::ImageRgba8(image), 100)
+            })
+            .collect::<Vec<_>>();
+        let dir = tempfile::tempdir().unwrap();
+
+        for encoder in [
+            FileEncoder::Gif,
+            FileEncoder::default(),
+            FileEncoder::WebP {
+                lossless: true,
+                quality: 80,
+            },
+        ] {
+            let path = dir.path().join(format!("animation.{}", encoder.ext()));
+            encoder.save_animation(&frames, &path).unwrap();
+            let saved = crate::image_loader::open_animation(&path, None).unwrap();
+            assert_eq!(saved.len(), frames.len(), "{encoder}");
+            assert!(saved.iter().all(|(_, delay)| *delay == 100), "{encoder}");
         }
     }
 }
@@ -703,6 +703,14 @@ pub fn browse_for_image_path(state: &mut OculanteState) {
                 .iter()
                 .map(|e| e.to_ascii_uppercase())
                 .collect::<Vec<_>>(),
+            crate::archive::ARCHIVE_EXTENSIONS
+                .iter()
+                .map(|e| e.to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+            crate::archive::ARCHIVE_EXTENSIONS
+                .iter()
+                .map(|e| e.to_ascii_uppercase())
+                .collect::<Vec<_>>(),
         ]
         .concat();
         let file_dialog_result = rfd::FileDialog::new()
@@ -24,12 +24,16 @@ use notan::egui::{
 use notan::egui::{Response, Ui};
 use palette::{rgb::Rgb, Hsl, IntoColor};
 use rand::{thread_rng, Rng};
-use rayon::{iter::ParallelIterator, slice::ParallelSliceMut};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumIter, IntoEnumIterator};
 use num_integer::gcd;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct EditState {
     #[serde(skip)]
     /// The final result of image modifications
@@ -97,6 +101,68 @@ impl Default for EditState {
     }
 }
 
+impl EditState {
+    /// Load edits from a `.oculante` file. Legacy edit stacks are upgraded.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)?;
+        match serde_json::from_slice::<EditState>(&contents) {
+            Ok(edit_state) => Ok(edit_state),
+            Err(e) => match serde_json::from_slice::<LegacyEditState>(&contents) {
+                Ok(legacy_edit_state) => Ok(legacy_edit_state.upgrade()),
+                Err(_) => Err(e.into()),
+            },
+        }
+    }
+
+    /// Run all active operators and paint strokes on a copy of an image,
+    /// the same way the edit panel does.
+    pub fn apply(&self, img: &DynamicImage) -> Result<DynamicImage> {
+        let mut result = img.clone();
+
+        // The editor converts to RGBA8 unless a conversion operator is first on the stack
+        let has_converter = self
+            .image_op_stack
+            .first()
+            .map(|op| matches!(op.operation, ImageOperation::ColorConverter(_)))
+            .unwrap_or_default();
+        if !has_converter && result.color() != ColorType::Rgba8 {
+            result = DynamicImage::ImageRgba8(result.to_rgba8());
+        }
+
+        for operation in self.image_op_stack.iter().filter(|op| op.active) {
+            operation.operation.process_image(&mut result)?;
+        }
+
+        let ops = self
+            .pixel_op_stack
+            .iter()
+            .filter(|op| op.active)
+            .map(|op| op.operation.clone())
+            .collect::<Vec<_>>();
+        if !ops.is_empty() {
+            process_pixels(&mut result, &ops)?;
+        }
+
+        if let Some(buffer) = result.as_mut_rgba8() {
+            for stroke in self.paint_strokes.iter().filter(|s| !s.is_empty()) {
+                stroke.render(buffer, &self.brushes);
+            }
+        }
+        Ok(result)
+    }
+
+    /// [`EditState::apply`] to every frame of an animation, keeping the delays
+    pub fn apply_to_frames(
+        &self,
+        frames: &[(DynamicImage, u16)],
+    ) -> Result<Vec<(DynamicImage, u16)>> {
+        frames
+            .par_iter()
+            .map(|(img, delay)| Ok((self.apply(img)?, *delay)))
+            .collect()
+    }
+}
+
 fn default_brushes() -> Vec<RgbaImage> {
     vec![
         image::load_from_memory(include_bytes!("../res/brushes/brush1.png"))
@@ -2013,3 +2079,65 @@ impl ColorTypeExt {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> DynamicImage {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([100, 100, 100, 255]));
+        DynamicImage::ImageRgba8(image)
+    }
+
+    #[test]
+    fn loads_and_applies_edit_stacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("edits.oculante");
+        let mut disabled = ImgOpItem::new(ImageOperation::Fill([255, 0, 0, 255]));
+        disabled.active = false;
+        let edits = EditState {
+            image_op_stack: vec![ImgOpItem::new(ImageOperation::Flip(false))],
+            pixel_op_stack: vec![ImgOpItem::new(ImageOperation::Invert), disabled],
+            ..Default::default()
+        };
+        std::fs::write(&path, serde_json::to_vec(&edits).unwrap()).unwrap();
+
+        let loaded = EditState::load(&path).unwrap();
+        let result = loaded.apply(&gradient()).unwrap().into_rgba8();
+        // Flipped, then inverted. The inactive fill is skipped.
+        assert_eq!(result.get_pixel(0, 0).0, [155, 155, 155, 255]);
+        assert_eq!(result.get_pixel(1, 0).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn upgrades_legacy_edit_stacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy.oculante");
+        let legacy = LegacyEditState {
+            painting: false,
+            non_destructive_painting: false,
+            paint_strokes: vec![],
+            paint_fade: false,
+            pixel_op_stack: vec![ImageOperation::Invert],
+            image_op_stack: vec![],
+            export_extension: "png".into(),
+        };
+        std::fs::write(&path, serde_json::to_vec(&legacy).unwrap()).unwrap();
+
+        let loaded = EditState::load(&path).unwrap();
+        assert_eq!(loaded.pixel_op_stack.len(), 1);
+        assert!(loaded.pixel_op_stack[0].active);
+        let result = loaded.apply(&gradient()).unwrap().into_rgba8();
+        assert_eq!(result.get_pixel(0, 0).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn rejects_unreadable_edit_stacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.oculante");
+        std::fs::write(&path, b"{ not json").unwrap();
+        assert!(EditState::load(&path).is_err());
+    }
+}
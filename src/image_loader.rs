@@ -1,24 +1,26 @@
+use crate::archive;
+use crate::color_management;
 use crate::ktx2_loader::CompressedImageFormats;
+use crate::raw_develop::{self, RawSettings};
 use crate::settings::DecoderSettings;
 use crate::utils::{fit, Frame};
-use crate::{appstate::Message, ktx2_loader, FONT};
+use crate::{appstate::Message, ktx2_loader, texture_inspector, FONT};
 use log::{debug, error, info};
 use psd::Psd;
 
 use anyhow::{anyhow, bail, Context, Result};
-use dds::DDS;
 use exr::prelude as exrs;
 use exr::prelude::*;
+use image::metadata::Orientation;
 use image::{
-    DynamicImage, EncodableLayout, GrayAlphaImage, GrayImage, ImageDecoder, ImageReader, RgbImage,
-    RgbaImage,
+    DynamicImage, EncodableLayout, GrayAlphaImage, GrayImage, ImageDecoder, ImageReader,
+    Rgb32FImage, RgbImage, Rgba32FImage, RgbaImage,
 };
 use jxl_oxide::{JxlImage, PixelFormat};
-use quickraw::Export;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use rgb::*;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Cursor, Read, Write};
 use std::path::Path;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use tiff::decoder::Limits;
@@ -26,11 +28,74 @@ use webp_animation::prelude::*;
 use zune_png::zune_core::options::DecoderOptions;
 use zune_png::zune_core::result::DecodingResult;
 
-/// Open an image from disk and send it somewhere
+/// Open an image from disk and send it somewhere.
+/// Images with an embedded ICC profile are converted to the display profile, unless turned off.
 pub fn open_image(
     img_location: &Path,
     message_sender: Option<Sender<Message>>,
     decoder_opts: Option<DecoderSettings>,
+) -> Result<Receiver<Frame>> {
+    // Entries of archives are extracted and loaded like any other file
+    if archive::split_path(img_location).is_some() {
+        let data = archive::read_entry(img_location)?;
+        let extension = img_location
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        // Created exclusively under a random name, and removed when dropped
+        let mut file = tempfile::Builder::new()
+            .prefix("oculante_")
+            .suffix(&extension)
+            .tempfile()?;
+        file.write_all(&data)?;
+        let entry_receiver = open_image(file.path(), message_sender, decoder_opts)?;
+        let (sender, receiver) = channel();
+        // Some decoders read lazily, so the file is kept until all frames are sent
+        std::thread::spawn(move || {
+            for frame in entry_receiver.iter() {
+                if sender.send(frame).is_err() {
+                    break;
+                }
+            }
+            drop(file);
+        });
+        return Ok(receiver);
+    }
+    let receiver = decode_image(img_location, message_sender, decoder_opts)?;
+    let decoder_opts = decoder_opts.unwrap_or_default();
+    // jxl-oxide applies the profile itself
+    let is_jxl = img_location
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("jxl"));
+    if !decoder_opts.color_managed || is_jxl {
+        return Ok(receiver);
+    }
+    let Some(icc) = color_management::read_profile(img_location) else {
+        return Ok(receiver);
+    };
+
+    let display_profile = decoder_opts.display_profile;
+    let (sender, managed_receiver) = channel();
+    std::thread::spawn(move || {
+        for mut frame in receiver.iter() {
+            if let Some(image) = frame.image_mut() {
+                if let Err(e) = color_management::convert(image, &icc, display_profile) {
+                    debug!("Color profile not applied: {e}");
+                }
+            }
+            if sender.send(frame).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(managed_receiver)
+}
+
+/// Decode an image without color management
+fn decode_image(
+    img_location: &Path,
+    message_sender: Option<Sender<Message>>,
+    decoder_opts: Option<DecoderSettings>,
 ) -> Result<Receiver<Frame>> {
     let (sender, receiver): (Sender<Frame>, Receiver<Frame>) = channel();
     let img_location = (*img_location).to_owned();
@@ -82,24 +147,13 @@ pub fn open_image(
 
     match extension.as_str() {
         "dds" => {
-            let file = File::open(img_location)?;
-            let mut reader = BufReader::new(file);
-            let dds = DDS::decode(&mut reader).map_err(|e| anyhow!("{:?}", e))?;
-            if let Some(main_layer) = dds.layers.first() {
-                let buf = main_layer.as_bytes();
-                let buf =
-                    image::ImageBuffer::from_raw(dds.header.width, dds.header.height, buf.into())
-                        .context("Can't create DDS ImageBuffer with given res")?;
-                let d = DynamicImage::ImageRgba8(buf);
-                _ = sender.send(Frame::new_still(d));
-                return Ok(receiver);
-            }
+            // The first mip level of the first face. The texture inspector shows the rest.
+            let texture = texture_inspector::read_texture(&img_location)?;
+            _ = sender.send(Frame::new_still(texture.subresource(0, 0, 0)?));
+            return Ok(receiver);
         }
         "dcm" | "ima" => {
-            use dicom_pixeldata::PixelDecoder;
-            let obj = dicom_object::open_file(img_location)?;
-            let image = obj.decode_pixel_data()?;
-            let dynamic_image = image.to_dynamic_image(0)?;
+            let dynamic_image = load_dicom_frame(&img_location, 0)?;
             _ = sender.send(Frame::new_still(dynamic_image));
         }
         "ktx2" => {
@@ -167,7 +221,7 @@ pub fn open_image(
                 ctx.set_max_decoding_threads(num_threads.get() as u32);
             }
             if let Some(limits) = decoder_opts.and_then(|decoders| {
-                let DecoderSettings { heif } = decoders;
+                let DecoderSettings { heif, .. } = decoders;
                 heif.maybe_limits()
             }) {
                 ctx.set_security_limits(&limits)?;
@@ -325,35 +379,33 @@ pub fn open_image(
                 .no_deep_data()
                 .largest_resolution_level()
                 .rgba_channels(
-                    |resolution, _channels: &RgbaChannels| -> RgbaImage {
+                    |resolution, _channels: &RgbaChannels| -> Rgba32FImage {
                         image::ImageBuffer::new(
                             resolution.width() as u32,
                             resolution.height() as u32,
                         )
                     },
-                    // set each pixel in the png buffer from the exr file
-                    |png_pixels, position, (r, g, b, a): (f32, f32, f32, f32)| {
-                        png_pixels.put_pixel(
+                    // keep the linear values, the viewer tone maps them
+                    |pixels, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                        pixels.put_pixel(
                             position.x() as u32,
                             position.y() as u32,
-                            // exr's tonemap:
-                            // image::Rgba([tone_map(r), tone_map(g), tone_map(b), (a * 255.0) as u8]),
-                            image::Rgba(tonemap_rgba([r, g, b, a])),
+                            image::Rgba([r, g, b, a]),
                         );
                     },
                 )
                 .first_valid_layer()
                 .all_attributes();
 
-            // an image that contains a single layer containing an png rgba buffer
+            // an image that contains a single layer containing a float rgba buffer
             let maybe_image: Result<
-                Image<Layer<SpecificChannels<RgbaImage, RgbaChannels>>>,
+                Image<Layer<SpecificChannels<Rgba32FImage, RgbaChannels>>>,
                 exrs::Error,
             > = reader.from_file(&img_location);
             match maybe_image {
                 Ok(image) => {
                     let buf = image.layer_data.channel_data.pixels;
-                    let i = DynamicImage::ImageRgba8(buf);
+                    let i = DynamicImage::ImageRgba32F(buf);
 
                     _ = sender.send(Frame::new_still(i));
                     return Ok(receiver);
@@ -367,16 +419,17 @@ pub fn open_image(
                         match d {
                             FlatSamples::F16(_) => bail!("F16 color mode not supported"),
                             FlatSamples::F32(f) => {
-                                let Some(gray_image) = GrayImage::from_raw(
+                                // There is no float gray image type, so the value is repeated
+                                let Some(gray_image) = Rgb32FImage::from_raw(
                                     size.width() as u32,
                                     size.height() as u32,
-                                    f.par_iter().map(|x| tonemap_f32(*x)).collect::<Vec<_>>(),
+                                    f.par_iter().flat_map_iter(|x| [*x; 3]).collect::<Vec<_>>(),
                                 ) else {
                                     error!("Can't decode gray alpha buffer");
                                     continue;
                                 };
 
-                                let d = DynamicImage::ImageLuma8(gray_image);
+                                let d = DynamicImage::ImageRgb32F(gray_image);
                                 _ = sender.send(Frame::new_still(d));
                                 return Ok(receiver);
                             }
@@ -391,8 +444,8 @@ pub fn open_image(
         "nef" | "cr2" | "dng" | "mos" | "erf" | "raf" | "arw" | "3fr" | "ari" | "srf" | "sr2"
         | "braw" | "r3d" | "nrw" | "raw" => {
             debug!("Loading RAW");
-            let buf = load_raw(&img_location)?;
-            let i = DynamicImage::ImageRgba8(buf);
+            let raw_settings = decoder_opts.map(|d| d.raw).unwrap_or_default();
+            let i = load_raw(&img_location, &raw_settings)?;
 
             _ = sender.send(Frame::new_still(i));
             return Ok(receiver);
@@ -409,29 +462,9 @@ pub fn open_image(
             let f = File::open(img_location)?;
             let reader = BufReader::new(f);
             let hdr_decoder = image::codecs::hdr::HdrDecoder::new(reader)?;
-
-            #[cfg(feature = "hdr")]
-            {
-                let d = DynamicImage::from_decoder(hdr_decoder)?;
-                _ = sender.send(Frame::new_still(d));
-                return Ok(receiver);
-            }
-
-            #[cfg(not(feature = "hdr"))]
-            {
-                let meta = hdr_decoder.metadata();
-                let hdr_img: image::Rgb32FImage = match DynamicImage::from_decoder(hdr_decoder)? {
-                    DynamicImage::ImageRgb32F(image) => image,
-                    _ => bail!("expected rgb32f image"),
-                };
-                let buf = RgbaImage::from_fn(meta.width, meta.height, |x, y| {
-                    let pixel = hdr_img.get_pixel(x, y);
-                    image::Rgba(tonemap_rgb(pixel.0))
-                });
-                let i = DynamicImage::ImageRgba8(buf);
-                _ = sender.send(Frame::new_still(i));
-                return Ok(receiver);
-            }
+            let d = DynamicImage::from_decoder(hdr_decoder)?;
+            _ = sender.send(Frame::new_still(d));
+            return Ok(receiver);
         }
         "psd" => {
             let contents = std::fs::read(img_location)?;
@@ -561,41 +594,25 @@ pub fn open_image(
             match decoder.decode().map_err(|e| anyhow!("{:?}", e))? {
                 // 16 bpp data
                 DecodingResult::U16(imgdata) => {
-                    //convert to 8bpp
-                    let imgdata_8bpp = imgdata
-                        .par_iter()
-                        .map(|x| *x as f32 / u16::MAX as f32)
-                        .map(|p| p.powf(2.2))
-                        .map(tonemap_f32)
-                        // .map(|x| x as u8)
-                        .collect::<Vec<_>>();
-
                     let (width, height) =
                         decoder.dimensions().context("Can't get png dimensions")?;
+                    let (width, height) = (width as u32, height as u32);
                     let colorspace = decoder.colorspace().context("Can't get colorspace")?;
 
-                    if colorspace.is_grayscale() {
-                        let buf: GrayImage =
-                            image::ImageBuffer::from_raw(width as u32, height as u32, imgdata_8bpp)
-                                .context("Can't interpret image as grayscale")?;
-                        let image_result = DynamicImage::ImageLuma8(buf);
-                        _ = sender.send(Frame::new_still(image_result));
-                        return Ok(receiver);
-                    }
-
-                    if colorspace.has_alpha() {
-                        let float_image =
-                            RgbaImage::from_raw(width as u32, height as u32, imgdata_8bpp)
-                                .context("Can't decode rgba buffer")?;
-                        _ = sender.send(Frame::new_still(DynamicImage::ImageRgba8(float_image)));
-                        return Ok(receiver);
-                    } else {
-                        let float_image =
-                            RgbImage::from_raw(width as u32, height as u32, imgdata_8bpp)
-                                .context("Can't decode rgba buffer")?;
-                        _ = sender.send(Frame::new_still(DynamicImage::ImageRgb8(float_image)));
-                        return Ok(receiver);
+                    // Keep all 16 bits, the display converts them
+                    let image_result = match (colorspace.is_grayscale(), colorspace.has_alpha()) {
+                        (true, false) => image::ImageBuffer::from_raw(width, height, imgdata)
+                            .map(DynamicImage::ImageLuma16),
+                        (true, true) => image::ImageBuffer::from_raw(width, height, imgdata)
+                            .map(DynamicImage::ImageLumaA16),
+                        (false, false) => image::ImageBuffer::from_raw(width, height, imgdata)
+                            .map(DynamicImage::ImageRgb16),
+                        (false, true) => image::ImageBuffer::from_raw(width, height, imgdata)
+                            .map(DynamicImage::ImageRgba16),
                     }
+                    .context("Can't interpret 16 bit image")?;
+                    _ = sender.send(Frame::new_still(image_result));
+                    return Ok(receiver);
                 }
                 // 8bpp
                 DecodingResult::U8(value) => {
@@ -724,15 +741,16 @@ pub fn open_image(
 
             bail!("No valid icons in {}", img_location.display());
         }
-        "tif" | "tiff" => match load_tiff(&img_location) {
+        "tif" | "tiff" => match load_tiff_page(&img_location, 0) {
             Ok(buf) => {
                 _ = sender.send(Frame::new_still(buf));
                 return Ok(receiver);
             }
-            Err(tiff_error) => match load_raw(&img_location) {
-                Ok(buf) => {
-                    let i = DynamicImage::ImageRgba8(buf);
-
+            Err(tiff_error) => match load_raw(
+                &img_location,
+                &decoder_opts.map(|d| d.raw).unwrap_or_default(),
+            ) {
+                Ok(i) => {
                     info!("This image is a raw image with tiff format.");
                     _ = sender.send(Frame::new_still(i));
                     return Ok(receiver);
@@ -756,13 +774,57 @@ pub fn open_image(
     Ok(receiver)
 }
 
-fn tonemap_rgba(px: [f32; 4]) -> [u8; 4] {
-    [
-        tonemap_f32(px[0]),
-        tonemap_f32(px[1]),
-        tonemap_f32(px[2]),
-        tonemap_f32(px[3]),
-    ]
+/// Decode an encoded image held in memory, detecting the format from its content.
+/// Formats the image crate can't read are handed to [`open_image`] through a temporary file.
+pub fn open_image_from_memory(
+    data: &[u8],
+    message_sender: Option<Sender<Message>>,
+    decoder_opts: Option<DecoderSettings>,
+) -> Result<Vec<Frame>> {
+    use file_format::FileFormat;
+
+    let fmt = FileFormat::from_bytes(data);
+    debug!("Detected as {:?} {}", fmt.name(), fmt.extension());
+
+    if let Some(format) =
+        image::ImageFormat::from_extension(fmt.extension()).filter(|f| f.reading_enabled())
+    {
+        return Ok(vec![Frame::new_still(image::load_from_memory_with_format(
+            data, format,
+        )?)]);
+    }
+
+    // Created exclusively under a random name, and removed when dropped
+    let mut file = tempfile::Builder::new()
+        .prefix("oculante_")
+        .suffix(&format!(".{}", fmt.extension()))
+        .tempfile()?;
+    file.write_all(data)?;
+    // Collect everything before removing the file, some decoders read it lazily
+    let frames = open_image(file.path(), message_sender, decoder_opts)?
+        .iter()
+        .collect();
+    Ok(frames)
+}
+
+/// Decode all frames of an image with their delay in ms.
+/// Still images result in a single frame without delay.
+pub fn open_animation(
+    img_location: &Path,
+    decoder_opts: Option<DecoderSettings>,
+) -> Result<Vec<(DynamicImage, u16)>> {
+    let mut frames = vec![];
+    for frame in open_image(img_location, None, decoder_opts)?.iter() {
+        match frame {
+            Frame::Animation(img, delay) => frames.push((img, delay)),
+            Frame::Still(img) if frames.is_empty() => return Ok(vec![(img, 0)]),
+            _ => {}
+        }
+    }
+    if frames.is_empty() {
+        bail!("No frames could be decoded");
+    }
+    Ok(frames)
 }
 
 pub fn tonemap_f32(px: f32) -> u8 {
@@ -770,142 +832,128 @@ pub fn tonemap_f32(px: f32) -> u8 {
     // (px.filmic() * 255.) as u8
 }
 
-fn tonemap_rgb(px: [f32; 3]) -> [u8; 4] {
-    let mut tm = tonemap_rgba([px[0], px[1], px[2], 1.0]);
-    tm[3] = 255;
-    tm
-}
-
 #[allow(unused)]
 fn u16_to_u8(p: u16) -> u8 {
     ((p as f32 / u16::MAX as f32) * u8::MAX as f32) as u8
 }
 
-fn load_raw(img_location: &Path) -> Result<RgbaImage> {
-    let raw_data = std::fs::read(img_location)?;
-    let (thumbnail_data, _orientation) = Export::export_thumbnail_data(&raw_data)?;
-
-    let i = image::load_from_memory(thumbnail_data)?;
-    Ok(i.to_rgba8())
-
-    // let export_job = Export::new(
-    //     Input::ByFile(&img_location.to_string_lossy()),
-    //     Output::new(
-    //         DemosaicingMethod::SuperPixel,
-    //         data::XYZ2SRGB,
-    //         data::GAMMA_SRGB,
-    //         OutputType::Raw16,
-    //         true,
-    //         true,
-    //     ),
-    // )?;
-
-    // let mut buf = vec![];
-    // let x = quickraw::Export::export_thumbnail_data(&buf)?;
-    // image::load_from_memory(&buf)?;
-
-    // let (image, width, height) = export_job.export_16bit_image();
-    // let image = image
-    //     .into_par_iter()
-    //     .map(|x| tonemap_f32(x as f32 / 65536.))
-    //     .collect::<Vec<_>>();
-
-    // // Construct rgb image
-    // let x = RgbImage::from_raw(width as u32, height as u32, image)
-    //     .context("can't decode raw output as image")?;
-    // // make it a Dynamic image
-    // Ok(DynamicImage::ImageRgb8(x).to_rgba8())
+fn load_raw(img_location: &Path, settings: &RawSettings) -> Result<DynamicImage> {
+    raw_develop::develop_file(img_location, settings)
+}
+
+/// Decode one frame of a DICOM image
+pub fn load_dicom_frame(img_location: &Path, frame: u32) -> Result<DynamicImage> {
+    use dicom_pixeldata::PixelDecoder;
+    let obj = dicom_object::open_file(img_location)?;
+    let image = obj.decode_pixel_data_frame(frame)?;
+    Ok(image.to_dynamic_image(0)?)
 }
 
-fn load_tiff(img_location: &Path) -> Result<DynamicImage> {
+/// Decode one page (image file directory) of a TIFF file
+pub fn load_tiff_page(img_location: &Path, page: usize) -> Result<DynamicImage> {
     // TODO: Probe if dng
     let data = File::open(img_location)?;
 
     let mut decoder = tiff::decoder::Decoder::new(&data)?.with_limits(Limits::unlimited());
+    if page > 0 {
+        decoder.seek_to_image(page)?;
+    }
     let dim = decoder.dimensions()?;
     debug!("Color type: {:?}", decoder.colortype());
     let result = decoder.read_image()?;
-    // A container for the low dynamic range image
-    let ldr_img: Vec<u8> = match result {
+    // 16 bit and float samples are kept, the display converts them.
+    // Other sample types have no matching image buffer and are scaled to 8 bit.
+    // Float samples that are not radiance data are normalized, see `TiffSamples::float`.
+    let samples = match result {
         tiff::decoder::DecodingResult::U8(contents) => {
             debug!("TIFF U8");
-            contents
+            TiffSamples::U8(contents)
         }
         tiff::decoder::DecodingResult::U16(contents) => {
             debug!("TIFF U16");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::U16(contents)
         }
         tiff::decoder::DecodingResult::F16(contents) => {
             debug!("TIFF F16");
-            let values = contents.par_iter().map(|p| f32::from(*p)).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
-        }
-        tiff::decoder::DecodingResult::U32(contents) => {
-            debug!("TIFF U32");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
-        }
-        tiff::decoder::DecodingResult::U64(contents) => {
-            debug!("TIFF U64");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::float(contents.par_iter().map(|p| f32::from(*p)).collect())
         }
         tiff::decoder::DecodingResult::F32(contents) => {
             debug!("TIFF F32");
-            autoscale(&contents).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::float(contents)
         }
         tiff::decoder::DecodingResult::F64(contents) => {
             debug!("TIFF F64");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::float(contents.par_iter().map(|p| *p as f32).collect())
+        }
+        tiff::decoder::DecodingResult::U32(contents) => {
+            debug!("TIFF U32");
+            TiffSamples::scaled(contents.par_iter().map(|p| *p as f32).collect())
+        }
+        tiff::decoder::DecodingResult::U64(contents) => {
+            debug!("TIFF U64");
+            TiffSamples::scaled(contents.par_iter().map(|p| *p as f32).collect())
         }
         tiff::decoder::DecodingResult::I8(contents) => {
             debug!("TIFF I8");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::scaled(contents.par_iter().map(|p| *p as f32).collect())
         }
         tiff::decoder::DecodingResult::I16(contents) => {
             debug!("TIFF I16");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::scaled(contents.par_iter().map(|p| *p as f32).collect())
         }
         tiff::decoder::DecodingResult::I32(contents) => {
             debug!("TIFF I32");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::scaled(contents.par_iter().map(|p| *p as f32).collect())
         }
         tiff::decoder::DecodingResult::I64(contents) => {
             debug!("TIFF I64");
-            let values = contents.par_iter().map(|p| *p as f32).collect::<Vec<_>>();
-            autoscale(&values).par_iter().map(|x| *x as u8).collect()
+            TiffSamples::scaled(contents.par_iter().map(|p| *p as f32).collect())
         }
     };
 
-    match decoder.colortype()? {
-        tiff::ColorType::Gray(_) => {
-            debug!("Loading gray color");
-            let i =
-                image::GrayImage::from_raw(dim.0, dim.1, ldr_img).context("Can't load gray img")?;
-            Ok(DynamicImage::ImageLuma8(i))
+    let (width, height) = dim;
+    let image = match (decoder.colortype()?, samples) {
+        (tiff::ColorType::Gray(_), TiffSamples::U8(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageLuma8)
+        }
+        (tiff::ColorType::Gray(_), TiffSamples::U16(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageLuma16)
+        }
+        // There are no float gray buffers
+        (tiff::ColorType::Gray(_), TiffSamples::F32(s)) => {
+            let s = s.iter().flat_map(|v| [*v; 3]).collect();
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgb32F)
+        }
+        (tiff::ColorType::GrayA(_), TiffSamples::U8(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageLumaA8)
+        }
+        (tiff::ColorType::GrayA(_), TiffSamples::U16(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageLumaA16)
         }
-        tiff::ColorType::RGB(_) => {
-            debug!("Loading rgb color");
-            let i =
-                image::RgbImage::from_raw(dim.0, dim.1, ldr_img).context("Can't load RGB img")?;
-            Ok(DynamicImage::ImageRgb8(i))
+        (tiff::ColorType::GrayA(_), TiffSamples::F32(s)) => {
+            let s = s
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect();
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgba32F)
         }
-        tiff::ColorType::RGBA(_) => {
-            debug!("Loading rgba color");
-            let i =
-                image::RgbaImage::from_raw(dim.0, dim.1, ldr_img).context("Can't load RGBA img")?;
-            Ok(image::DynamicImage::ImageRgba8(i))
+        (tiff::ColorType::RGB(_), TiffSamples::U8(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgb8)
         }
-        tiff::ColorType::GrayA(_) => {
-            debug!("Loading gray color with alpha");
-            let i = image::GrayAlphaImage::from_raw(dim.0, dim.1, ldr_img)
-                .context("Can't load gray alpha img")?;
-            Ok(image::DynamicImage::ImageLumaA8(i))
+        (tiff::ColorType::RGB(_), TiffSamples::U16(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgb16)
+        }
+        (tiff::ColorType::RGB(_), TiffSamples::F32(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgb32F)
+        }
+        (tiff::ColorType::RGBA(_), TiffSamples::U8(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgba8)
+        }
+        (tiff::ColorType::RGBA(_), TiffSamples::U16(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgba16)
+        }
+        (tiff::ColorType::RGBA(_), TiffSamples::F32(s)) => {
+            image::ImageBuffer::from_raw(width, height, s).map(DynamicImage::ImageRgba32F)
         }
         _ => {
             bail!(
@@ -913,6 +961,43 @@ fn load_tiff(img_location: &Path) -> Result<DynamicImage> {
                 decoder.colortype()
             )
         }
+    };
+    image.context("Can't interpret TIFF samples")
+}
+
+/// Decoded TIFF samples in the types an image buffer can hold
+enum TiffSamples {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+/// Largest sample a float TIFF may hold to still be treated as radiance data
+const TIFF_RADIANCE_MAX: f32 = 64.;
+
+impl TiffSamples {
+    fn scaled(values: Vec<f32>) -> Self {
+        Self::U8(autoscale(&values).par_iter().map(|x| *x as u8).collect())
+    }
+
+    /// Keeps float samples that look like radiance data, that is non-negative
+    /// and at most a few stops above white. Anything else (depth maps, elevations,
+    /// scientific data) is stretched from its min/max to 0..1 as before.
+    fn float(values: Vec<f32>) -> Self {
+        let (lowest, highest) = values
+            .iter()
+            .filter(|v| v.is_finite())
+            .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+        if lowest >= 0. && highest <= TIFF_RADIANCE_MAX || lowest >= highest {
+            return Self::F32(values);
+        }
+        debug!("Normalizing float TIFF from {lowest}..{highest}");
+        Self::F32(
+            values
+                .par_iter()
+                .map(|v| fit(*v, lowest, highest, 0., 1.))
+                .collect(),
+        )
     }
 }
 
@@ -1044,9 +1129,14 @@ fn load_jxl(img_location: &Path, frame_sender: Sender<Frame>) -> Result<()> {
     Ok(())
 }
 
-pub fn rotate_dynimage(di: &mut DynamicImage, path: &Path) -> Result<()> {
+/// The orientation stored in an image file, as its decoder reports it
+pub fn read_orientation(path: &Path) -> Result<Orientation> {
     let mut decoder = ImageReader::open(path)?.into_decoder()?;
-    di.apply_orientation(decoder.orientation()?);
+    Ok(decoder.orientation()?)
+}
+
+pub fn rotate_dynimage(di: &mut DynamicImage, path: &Path) -> Result<()> {
+    di.apply_orientation(read_orientation(path)?);
     Ok(())
 }
 
@@ -1063,6 +1153,57 @@ pub fn rotate_rgbaimage(di: &RgbaImage, path: &Path) -> Result<RgbaImage> {
     }
 }
 
+/// Load the JPEG preview a camera embeds in a RAW file, or the EXIF thumbnail of a JPEG.
+/// This is much faster than a full decode. The preview is oriented like the full image.
+pub fn load_embedded_preview(img_location: &Path) -> Result<DynamicImage> {
+    if raw_develop::is_raw(img_location) {
+        let raw_data = std::fs::read(img_location)?;
+        let (preview_data, _orientation) = quickraw::Export::export_thumbnail_data(&raw_data)?;
+        let mut preview = image::load_from_memory(preview_data)?;
+        // CR3 and other containers without EXIF are left as they are
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(&raw_data)) {
+            preview.apply_orientation(exif_orientation(&exif));
+        }
+        return Ok(preview);
+    }
+    let extension = img_location
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if !matches!(extension.as_str(), "jpg" | "jpeg") {
+        bail!("Only RAW and JPEG files have embedded previews");
+    }
+    // Only the EXIF segment at the start of the file is read
+    let mut reader = BufReader::new(File::open(img_location)?);
+    load_exif_thumbnail(&exif::Reader::new().read_from_container(&mut reader)?)
+}
+
+fn exif_orientation(exif: &exif::Exif) -> Orientation {
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .and_then(|value| Orientation::from_exif(value as u8))
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+/// Decode the JPEG thumbnail stored in the second IFD of `exif`
+fn load_exif_thumbnail(exif: &exif::Exif) -> Result<DynamicImage> {
+    let position = |tag| {
+        exif.get_field(tag, exif::In::THUMBNAIL)
+            .and_then(|field| field.value.get_uint(0))
+            .map(|value| value as usize)
+            .context("No EXIF thumbnail")
+    };
+    let offset = position(exif::Tag::JPEGInterchangeFormat)?;
+    let length = position(exif::Tag::JPEGInterchangeFormatLength)?;
+    let jpeg = exif
+        .buf()
+        .get(offset..offset + length)
+        .context("The EXIF thumbnail is truncated")?;
+    let mut thumbnail = image::load_from_memory(jpeg)?;
+    thumbnail.apply_orientation(exif_orientation(exif));
+    Ok(thumbnail)
+}
+
 fn load_kra(path: &Path) -> Result<DynamicImage> {
     let f = File::open(path)?;
     let mut archive = zip::ZipArchive::new(f)?;
@@ -1114,6 +1255,7 @@ mod tests {
                 image_size_pixels: Limit::U64(50),
                 ..Default::default()
             },
+            ..Default::default()
         });
 
         let actual = open_image(&image_location, None, decoder_opts).unwrap_err();
@@ -1141,6 +1283,7 @@ mod tests {
                 image_size_pixels: Limit::NoLimit,
                 ..Default::default()
             },
+            ..Default::default()
         });
 
         open_image(&image_location, None, decoder_opts)
@@ -1148,4 +1291,83 @@ mod tests {
             .recv()
             .expect("Decoded image should be have sent");
     }
+
+    #[test]
+    fn reads_exif_thumbnail() {
+        use exif::{experimental::Writer, Field, In, Tag, Value};
+        use image::{DynamicImage, GenericImageView, ImageFormat};
+        use std::io::Cursor;
+
+        let mut jpeg = Cursor::new(vec![]);
+        DynamicImage::new_rgb8(4, 2)
+            .write_to(&mut jpeg, ImageFormat::Jpeg)
+            .unwrap();
+        let jpeg = jpeg.into_inner();
+        // Rotated 90 degrees clockwise
+        let orientation = Field {
+            tag: Tag::Orientation,
+            ifd_num: In::PRIMARY,
+            value: Value::Short(vec![6]),
+        };
+        let mut writer = Writer::new();
+        writer.push_field(&orientation);
+        writer.set_jpeg(&jpeg, In::THUMBNAIL);
+        let mut buf = Cursor::new(vec![]);
+        writer.write(&mut buf, false).unwrap();
+
+        let exif = exif::Reader::new().read_raw(buf.into_inner()).unwrap();
+        let thumbnail = super::load_exif_thumbnail(&exif).unwrap();
+        assert_eq!(thumbnail.dimensions(), (2, 4));
+    }
+
+    #[test]
+    fn loads_archive_entries_by_extension() {
+        use image::{DynamicImage, GenericImageView, ImageFormat};
+        use std::io::{Cursor, Write};
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("comic.cbz");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        // Targa has no magic bytes, only the name tells what it is
+        let mut tga = Cursor::new(vec![]);
+        DynamicImage::new_rgb8(3, 2)
+            .write_to(&mut tga, ImageFormat::Tga)
+            .unwrap();
+        zip.start_file("page.tga", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&tga.into_inner()).unwrap();
+        zip.finish().unwrap();
+
+        let frame = open_image(&archive.join("page.tga"), None, None)
+            .unwrap()
+            .recv()
+            .unwrap();
+        assert_eq!(frame.get_image().unwrap().dimensions(), (3, 2));
+    }
+
+    #[test]
+    fn normalizes_non_radiance_float_tiffs() {
+        use image::{DynamicImage, GenericImageView, ImageFormat, Rgb32FImage};
+
+        let dir = tempfile::tempdir().unwrap();
+        let cases = [
+            ("radiance.tif", [0., 0.5, 8.], [0., 0.5, 8.]),
+            ("elevation.tif", [100., 300., 500.], [0., 0.5, 1.]),
+            ("signed.tif", [-1., 0., 1.], [0., 0.5, 1.]),
+        ];
+        for (name, samples, expected) in cases {
+            let path = dir.path().join(name);
+            let image = Rgb32FImage::from_fn(3, 1, |x, _| [samples[x as usize]; 3].into());
+            DynamicImage::ImageRgb32F(image)
+                .save_with_format(&path, ImageFormat::Tiff)
+                .unwrap();
+
+            let loaded = super::load_tiff_page(&path, 0).unwrap();
+            assert_eq!(loaded.dimensions(), (3, 1));
+            let loaded = loaded.into_rgb32f();
+            for (x, value) in expected.into_iter().enumerate() {
+                assert_eq!(loaded.get_pixel(x as u32, 0).0, [value; 3], "{name}");
+            }
+        }
+    }
 }
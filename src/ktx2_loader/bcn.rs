@@ -0,0 +1,143 @@
+//! CPU decoding of block compressed (BC1 to BC7) textures, so they can be shown
+//! without uploading them to the GPU in their compressed form.
+
+use image::{DynamicImage, GrayImage, Rgb32FImage, RgbImage, RgbaImage};
+use rayon::prelude::*;
+use wgpu::TextureFormat;
+
+/// Decode the 4x4 blocks of `data`. `decode_block` writes `channels` values per pixel,
+/// rows `pitch` values apart. The result is cropped to `width` x `height`.
+fn decode_blocks<T, F>(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    block_bytes: usize,
+    channels: usize,
+    decode_block: F,
+) -> Option<Vec<T>>
+where
+    T: Copy + Default + Send + Sync,
+    F: Fn(&[u8], &mut [T], usize) + Sync,
+{
+    let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+    if blocks_x == 0 || data.len() < blocks_x * blocks_y * block_bytes {
+        return None;
+    }
+    // Decode to a buffer padded to whole blocks, one row of blocks at a time
+    let pitch = blocks_x * 4 * channels;
+    let mut padded = vec![T::default(); pitch * blocks_y * 4];
+    padded
+        .par_chunks_exact_mut(pitch * 4)
+        .zip(data.par_chunks_exact(blocks_x * block_bytes))
+        .for_each(|(rows, blocks)| {
+            for (x, block) in blocks.chunks_exact(block_bytes).enumerate() {
+                decode_block(block, &mut rows[x * 4 * channels..], pitch);
+            }
+        });
+    Some(
+        padded
+            .chunks_exact(pitch)
+            .take(height)
+            .flat_map(|row| row[..width * channels].iter().copied())
+            .collect(),
+    )
+}
+
+/// Signed BC4 and BC5 values are decoded to two's complement. Shift them so that
+/// zero is mid gray.
+fn unsigned(values: Vec<u8>) -> Vec<u8> {
+    values
+        .into_iter()
+        .map(|v| (v as i8 as i16 + 128) as u8)
+        .collect()
+}
+
+/// BC5 stores two channels, typically of a normal map. They become red and green.
+fn rg_to_rgb(values: Vec<u8>) -> Vec<u8> {
+    values
+        .chunks_exact(2)
+        .flat_map(|rg| [rg[0], rg[1], 0])
+        .collect()
+}
+
+/// Decode a BCn texture. Returns `None` for other formats or if `data` is too short.
+/// BC6H is decoded to float, the others to 8 bit.
+pub fn decode_bcn(
+    format: TextureFormat,
+    data: &[u8],
+    width: u32,
+    height: u32,
+) -> Option<DynamicImage> {
+    let (w, h) = (width as usize, height as usize);
+    Some(match format {
+        TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => {
+            let rgba = decode_blocks(data, w, h, 8, 4, bcdec_rs::bc1)?;
+            DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, rgba)?)
+        }
+        TextureFormat::Bc2RgbaUnorm | TextureFormat::Bc2RgbaUnormSrgb => {
+            let rgba = decode_blocks(data, w, h, 16, 4, bcdec_rs::bc2)?;
+            DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, rgba)?)
+        }
+        TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => {
+            let rgba = decode_blocks(data, w, h, 16, 4, bcdec_rs::bc3)?;
+            DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, rgba)?)
+        }
+        TextureFormat::Bc4RUnorm | TextureFormat::Bc4RSnorm => {
+            let signed = format == TextureFormat::Bc4RSnorm;
+            let gray = decode_blocks(data, w, h, 8, 1, |block, out, pitch| {
+                bcdec_rs::bc4(block, out, pitch, signed)
+            })?;
+            let gray = if signed { unsigned(gray) } else { gray };
+            DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, gray)?)
+        }
+        TextureFormat::Bc5RgUnorm | TextureFormat::Bc5RgSnorm => {
+            let signed = format == TextureFormat::Bc5RgSnorm;
+            let rg = decode_blocks(data, w, h, 16, 2, |block, out, pitch| {
+                bcdec_rs::bc5(block, out, pitch, signed)
+            })?;
+            let rg = if signed { unsigned(rg) } else { rg };
+            DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, rg_to_rgb(rg))?)
+        }
+        TextureFormat::Bc6hRgbUfloat | TextureFormat::Bc6hRgbFloat => {
+            let signed = format == TextureFormat::Bc6hRgbFloat;
+            let rgb = decode_blocks(data, w, h, 16, 3, |block, out, pitch| {
+                bcdec_rs::bc6h_float(block, out, pitch, signed)
+            })?;
+            DynamicImage::ImageRgb32F(Rgb32FImage::from_raw(width, height, rgb)?)
+        }
+        TextureFormat::Bc7RgbaUnorm | TextureFormat::Bc7RgbaUnormSrgb => {
+            let rgba = decode_blocks(data, w, h, 16, 4, bcdec_rs::bc7)?;
+            DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, rgba)?)
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_bc1() {
+        // Two blocks: Pure red and pure blue, all pixels using the first endpoint
+        let red = [0x00, 0xF8, 0x00, 0xF8, 0, 0, 0, 0];
+        let blue = [0x1F, 0x00, 0x1F, 0x00, 0, 0, 0, 0];
+        let data = [red, blue].concat();
+
+        // Not a multiple of the block size, so the second block gets cropped
+        let image = decode_bcn(TextureFormat::Bc1RgbaUnorm, &data, 6, 3)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(image.dimensions(), (6, 3));
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(5, 2).0, [0, 0, 255, 255]);
+
+        assert!(decode_bcn(TextureFormat::Bc1RgbaUnorm, &red, 6, 3).is_none());
+        assert!(decode_bcn(TextureFormat::Rgba8Unorm, &data, 6, 3).is_none());
+    }
+
+    #[test]
+    fn shifts_signed_values() {
+        assert_eq!(unsigned(vec![0, 127, 0x81]), [128, 255, 1]);
+    }
+}
@@ -1,19 +1,25 @@
 use ddsfile::{Caps2, D3DFormat, Dds, DxgiFormat};
 use std::io::Cursor;
-use wgpu::{
-    Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
-};
+use wgpu::{Extent3d, TextureDimension, TextureFormat};
 
 use super::{CompressedImageFormats, Image, TextureError};
 
+/// Read a DDS file. Legacy headers don't store a color space, `is_srgb` is used for them.
 pub fn dds_buffer_to_image(
     buffer: &[u8],
     supported_compressed_formats: CompressedImageFormats,
     is_srgb: bool,
 ) -> Result<Image, TextureError> {
     let mut cursor = Cursor::new(buffer);
-    let dds = Dds::read(&mut cursor).expect("Failed to parse DDS file");
+    let mut dds = Dds::read(&mut cursor)
+        .map_err(|err| TextureError::InvalidData(format!("Failed to parse DDS file: {err}")))?;
     let texture_format = dds_format_to_texture_format(&dds, is_srgb)?;
+    if let Some(data) = dds
+        .get_d3d_format()
+        .and_then(|f| expand_to_bgra8(f, &dds.data))
+    {
+        dds.data = data;
+    }
     if !supported_compressed_formats.supports(texture_format) {
         return Err(TextureError::UnsupportedTextureFormat(format!(
             "Format not supported by this GPU: {texture_format:?}",
@@ -54,17 +60,6 @@ pub fn dds_buffer_to_image(
     } else {
         TextureDimension::D1
     };
-    if is_cubemap {
-        let dimension = if image.texture_descriptor.size.depth_or_array_layers > 6 {
-            TextureViewDimension::CubeArray
-        } else {
-            TextureViewDimension::Cube
-        };
-        image.texture_view_descriptor = Some(TextureViewDescriptor {
-            dimension: Some(dimension),
-            ..Default::default()
-        });
-    }
     image.data = dds.data;
     Ok(image)
 }
@@ -90,11 +85,26 @@ pub fn dds_format_to_texture_format(
                     TextureFormat::Bgra8Unorm
                 }
             }
+            // These are expanded by `expand_to_bgra8`
+            D3DFormat::X8R8G8B8
+            | D3DFormat::X8B8G8R8
+            | D3DFormat::R8G8B8
+            | D3DFormat::R5G6B5
+            | D3DFormat::A1R5G5B5
+            | D3DFormat::X1R5G5B5
+            | D3DFormat::A4R4G4B4
+            | D3DFormat::X4R4G4B4 => {
+                if is_srgb {
+                    TextureFormat::Bgra8UnormSrgb
+                } else {
+                    TextureFormat::Bgra8Unorm
+                }
+            }
             D3DFormat::G16R16 => TextureFormat::Rg16Uint,
             D3DFormat::A2B10G10R10 => TextureFormat::Rgb10a2Unorm,
-            D3DFormat::A8L8 => TextureFormat::Rg8Uint,
-            D3DFormat::L16 => TextureFormat::R16Uint,
-            D3DFormat::L8 => TextureFormat::R8Uint,
+            D3DFormat::A8L8 => TextureFormat::Rg8Unorm,
+            D3DFormat::L16 => TextureFormat::R16Unorm,
+            D3DFormat::L8 => TextureFormat::R8Unorm,
             D3DFormat::DXT1 => {
                 if is_srgb {
                     TextureFormat::Bc1RgbaUnormSrgb
@@ -124,17 +134,7 @@ pub fn dds_format_to_texture_format(
             D3DFormat::R32F => TextureFormat::R32Float,
             D3DFormat::G32R32F => TextureFormat::Rg32Float,
             D3DFormat::A32B32G32R32F => TextureFormat::Rgba32Float,
-            D3DFormat::A1R5G5B5
-            | D3DFormat::R5G6B5
-            // FIXME: Map to argb format and user has to know to ignore the alpha channel?
-            | D3DFormat::X8R8G8B8
-            // FIXME: Map to argb format and user has to know to ignore the alpha channel?
-            | D3DFormat::X8B8G8R8
-            | D3DFormat::A2R10G10B10
-            | D3DFormat::R8G8B8
-            | D3DFormat::X1R5G5B5
-            | D3DFormat::A4R4G4B4
-            | D3DFormat::X4R4G4B4
+            D3DFormat::A2R10G10B10
             | D3DFormat::A8R3G3B2
             | D3DFormat::A4L4
             | D3DFormat::R8G8_B8G8
@@ -167,16 +167,9 @@ pub fn dds_format_to_texture_format(
             DxgiFormat::R10G10B10A2_Typeless | DxgiFormat::R10G10B10A2_UNorm => {
                 TextureFormat::Rgb10a2Unorm
             }
-            DxgiFormat::R11G11B10_Float => TextureFormat::Rg11b10Float,
-            DxgiFormat::R8G8B8A8_Typeless
-            | DxgiFormat::R8G8B8A8_UNorm
-            | DxgiFormat::R8G8B8A8_UNorm_sRGB => {
-                if is_srgb {
-                    TextureFormat::Rgba8UnormSrgb
-                } else {
-                    TextureFormat::Rgba8Unorm
-                }
-            }
+            DxgiFormat::R11G11B10_Float => TextureFormat::Rg11b10Ufloat,
+            DxgiFormat::R8G8B8A8_Typeless | DxgiFormat::R8G8B8A8_UNorm => TextureFormat::Rgba8Unorm,
+            DxgiFormat::R8G8B8A8_UNorm_sRGB => TextureFormat::Rgba8UnormSrgb,
             DxgiFormat::R8G8B8A8_UInt => TextureFormat::Rgba8Uint,
             DxgiFormat::R8G8B8A8_SNorm => TextureFormat::Rgba8Snorm,
             DxgiFormat::R8G8B8A8_SInt => TextureFormat::Rgba8Sint,
@@ -207,50 +200,23 @@ pub fn dds_format_to_texture_format(
             DxgiFormat::R8_SNorm => TextureFormat::R8Snorm,
             DxgiFormat::R8_SInt => TextureFormat::R8Sint,
             DxgiFormat::R9G9B9E5_SharedExp => TextureFormat::Rgb9e5Ufloat,
-            DxgiFormat::BC1_Typeless | DxgiFormat::BC1_UNorm | DxgiFormat::BC1_UNorm_sRGB => {
-                if is_srgb {
-                    TextureFormat::Bc1RgbaUnormSrgb
-                } else {
-                    TextureFormat::Bc1RgbaUnorm
-                }
-            }
-            DxgiFormat::BC2_Typeless | DxgiFormat::BC2_UNorm | DxgiFormat::BC2_UNorm_sRGB => {
-                if is_srgb {
-                    TextureFormat::Bc2RgbaUnormSrgb
-                } else {
-                    TextureFormat::Bc2RgbaUnorm
-                }
-            }
-            DxgiFormat::BC3_Typeless | DxgiFormat::BC3_UNorm | DxgiFormat::BC3_UNorm_sRGB => {
-                if is_srgb {
-                    TextureFormat::Bc3RgbaUnormSrgb
-                } else {
-                    TextureFormat::Bc3RgbaUnorm
-                }
-            }
+            DxgiFormat::BC1_Typeless | DxgiFormat::BC1_UNorm => TextureFormat::Bc1RgbaUnorm,
+            DxgiFormat::BC1_UNorm_sRGB => TextureFormat::Bc1RgbaUnormSrgb,
+            DxgiFormat::BC2_Typeless | DxgiFormat::BC2_UNorm => TextureFormat::Bc2RgbaUnorm,
+            DxgiFormat::BC2_UNorm_sRGB => TextureFormat::Bc2RgbaUnormSrgb,
+            DxgiFormat::BC3_Typeless | DxgiFormat::BC3_UNorm => TextureFormat::Bc3RgbaUnorm,
+            DxgiFormat::BC3_UNorm_sRGB => TextureFormat::Bc3RgbaUnormSrgb,
             DxgiFormat::BC4_Typeless | DxgiFormat::BC4_UNorm => TextureFormat::Bc4RUnorm,
             DxgiFormat::BC4_SNorm => TextureFormat::Bc4RSnorm,
             DxgiFormat::BC5_Typeless | DxgiFormat::BC5_UNorm => TextureFormat::Bc5RgUnorm,
             DxgiFormat::BC5_SNorm => TextureFormat::Bc5RgSnorm,
-            DxgiFormat::B8G8R8A8_UNorm
-            | DxgiFormat::B8G8R8A8_Typeless
-            | DxgiFormat::B8G8R8A8_UNorm_sRGB => {
-                if is_srgb {
-                    TextureFormat::Bgra8UnormSrgb
-                } else {
-                    TextureFormat::Bgra8Unorm
-                }
-            }
+            DxgiFormat::B8G8R8A8_UNorm | DxgiFormat::B8G8R8A8_Typeless => TextureFormat::Bgra8Unorm,
+            DxgiFormat::B8G8R8A8_UNorm_sRGB => TextureFormat::Bgra8UnormSrgb,
 
             DxgiFormat::BC6H_Typeless | DxgiFormat::BC6H_UF16 => TextureFormat::Bc6hRgbUfloat,
             DxgiFormat::BC6H_SF16 => TextureFormat::Bc6hRgbFloat,
-            DxgiFormat::BC7_Typeless | DxgiFormat::BC7_UNorm | DxgiFormat::BC7_UNorm_sRGB => {
-                if is_srgb {
-                    TextureFormat::Bc7RgbaUnormSrgb
-                } else {
-                    TextureFormat::Bc7RgbaUnorm
-                }
-            }
+            DxgiFormat::BC7_Typeless | DxgiFormat::BC7_UNorm => TextureFormat::Bc7RgbaUnorm,
+            DxgiFormat::BC7_UNorm_sRGB => TextureFormat::Bc7RgbaUnormSrgb,
             _ => {
                 return Err(TextureError::UnsupportedTextureFormat(format!(
                     "{dxgi_format:?}",
@@ -263,3 +229,82 @@ pub fn dds_format_to_texture_format(
         ));
     })
 }
+
+/// Convert legacy formats without a matching texture format to opaque or alpha BGRA with 8 bits
+/// per channel. Returns `None` for all other formats.
+fn expand_to_bgra8(format: D3DFormat, data: &[u8]) -> Option<Vec<u8>> {
+    // Scale a channel of `bits` bits to 8 bits
+    fn scale(value: u16, bits: u32) -> u8 {
+        let max = (1u32 << bits) - 1;
+        ((value as u32 & max) * 255 / max) as u8
+    }
+    // Unpack 16 bit pixels given the bit counts of alpha, red, green and blue
+    fn unpack(data: &[u8], [a, r, g, b]: [u32; 4]) -> Vec<u8> {
+        data.chunks_exact(2)
+            .flat_map(|p| {
+                let v = u16::from_le_bytes([p[0], p[1]]);
+                let alpha = if a == 0 {
+                    255
+                } else {
+                    scale(v >> (r + g + b), a)
+                };
+                [scale(v, b), scale(v >> b, g), scale(v >> (b + g), r), alpha]
+            })
+            .collect()
+    }
+    Some(match format {
+        D3DFormat::X8R8G8B8 => data
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        D3DFormat::X8B8G8R8 => data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], 255])
+            .collect(),
+        D3DFormat::R8G8B8 => data
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        D3DFormat::R5G6B5 => unpack(data, [0, 5, 6, 5]),
+        D3DFormat::A1R5G5B5 => unpack(data, [1, 5, 5, 5]),
+        D3DFormat::X1R5G5B5 => unpack(data, [0, 5, 5, 5]),
+        D3DFormat::A4R4G4B4 => unpack(data, [4, 4, 4, 4]),
+        D3DFormat::X4R4G4B4 => unpack(data, [0, 4, 4, 4]),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_legacy_formats() {
+        // Pure red in each format
+        assert_eq!(
+            expand_to_bgra8(D3DFormat::R8G8B8, &[0, 0, 255]),
+            Some(vec![0, 0, 255, 255])
+        );
+        assert_eq!(
+            expand_to_bgra8(D3DFormat::X8R8G8B8, &[0, 0, 255, 0]),
+            Some(vec![0, 0, 255, 255])
+        );
+        assert_eq!(
+            expand_to_bgra8(D3DFormat::X8B8G8R8, &[255, 0, 0, 0]),
+            Some(vec![0, 0, 255, 255])
+        );
+        assert_eq!(
+            expand_to_bgra8(D3DFormat::R5G6B5, &0xF800u16.to_le_bytes()),
+            Some(vec![0, 0, 255, 255])
+        );
+        assert_eq!(
+            expand_to_bgra8(D3DFormat::A1R5G5B5, &0x7C00u16.to_le_bytes()),
+            Some(vec![0, 0, 255, 0])
+        );
+        assert_eq!(
+            expand_to_bgra8(D3DFormat::A4R4G4B4, &0x8F00u16.to_le_bytes()),
+            Some(vec![0, 0, 255, 136])
+        );
+        assert_eq!(expand_to_bgra8(D3DFormat::DXT1, &[0; 8]), None);
+    }
+}
@@ -1,10 +1,11 @@
 use std::convert::TryInto;
 
-use crate::ktx2_loader::Image;
+use crate::ktx2_loader::{bcn::decode_bcn, Image};
 
 use exr::prelude::f16;
 use image::{DynamicImage, ImageBuffer, Rgba32FImage};
 use log::debug;
+use thiserror::Error;
 use wgpu::TextureFormat;
 
 impl Image {
@@ -12,9 +13,13 @@ impl Image {
     /// data. Not all [`TextureFormat`] are covered, therefore it will return an
     /// error if the format is unsupported. Supported formats are:
     /// - `TextureFormat::R8Unorm`
+    /// - `TextureFormat::R16Unorm`
     /// - `TextureFormat::Rg8Unorm`
-    /// - `TextureFormat::Rgba8UnormSrgb`
-    /// - `TextureFormat::Bgra8UnormSrgb`
+    /// - `TextureFormat::Rgba8Unorm` and `TextureFormat::Rgba8UnormSrgb`
+    /// - `TextureFormat::Bgra8Unorm` and `TextureFormat::Bgra8UnormSrgb`
+    /// - `TextureFormat::Rgba16Float`
+    /// - `TextureFormat::Rgba32Float`
+    /// - BC1 to BC7, decoded on the CPU. BC6H becomes a float image.
     ///
     /// To convert [`Image`] to a different format see: [`Image::convert`].
     pub fn try_into_dynamic(self) -> Result<DynamicImage, IntoDynamicImageError> {
@@ -32,11 +37,20 @@ impl Image {
         match self.texture_descriptor.format {
             TextureFormat::R8Unorm => ImageBuffer::from_raw(self.width(), self.height(), self.data)
                 .map(DynamicImage::ImageLuma8),
+            TextureFormat::R16Unorm => ImageBuffer::from_raw(
+                self.width(),
+                self.height(),
+                self.data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect(),
+            )
+            .map(DynamicImage::ImageLuma16),
             TextureFormat::Rg8Unorm => {
                 ImageBuffer::from_raw(self.width(), self.height(), self.data)
                     .map(DynamicImage::ImageLumaA8)
             }
-            TextureFormat::Rgba8UnormSrgb => {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {
                 ImageBuffer::from_raw(self.width(), self.height(), self.data)
                     .map(DynamicImage::ImageRgba8)
             }
@@ -72,6 +86,25 @@ impl Image {
                 Rgba32FImage::from_vec(self.width(), self.height(), d)
                     .map(|i| DynamicImage::ImageRgba8(DynamicImage::ImageRgba32F(i).to_rgba8()))
             }
+            TextureFormat::Bc1RgbaUnorm
+            | TextureFormat::Bc1RgbaUnormSrgb
+            | TextureFormat::Bc2RgbaUnorm
+            | TextureFormat::Bc2RgbaUnormSrgb
+            | TextureFormat::Bc3RgbaUnorm
+            | TextureFormat::Bc3RgbaUnormSrgb
+            | TextureFormat::Bc4RUnorm
+            | TextureFormat::Bc4RSnorm
+            | TextureFormat::Bc5RgUnorm
+            | TextureFormat::Bc5RgSnorm
+            | TextureFormat::Bc6hRgbUfloat
+            | TextureFormat::Bc6hRgbFloat
+            | TextureFormat::Bc7RgbaUnorm
+            | TextureFormat::Bc7RgbaUnormSrgb => decode_bcn(
+                self.texture_descriptor.format,
+                &self.data,
+                self.width(),
+                self.height(),
+            ),
             // Throw and error if conversion isn't supported
             texture_format => return Err(IntoDynamicImageError::UnsupportedFormat(texture_format)),
         }
@@ -83,11 +116,13 @@ impl Image {
 
 /// Errors that occur while converting an [`Image`] into a [`DynamicImage`]
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Error, Debug)]
 pub enum IntoDynamicImageError {
     /// Conversion into dynamic image not supported for source format.
+    #[error("conversion into dynamic image not supported for {0:?}")]
     UnsupportedFormat(TextureFormat),
 
     /// Encountered an unknown error during conversion.
+    #[error("failed to convert into {0:?}")]
     UnknownConversionError(TextureFormat),
 }
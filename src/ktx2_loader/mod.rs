@@ -1,6 +1,6 @@
 mod basis;
-// #[cfg(feature = "dds")]
-// mod dds;
+mod bcn;
+mod dds;
 #[allow(clippy::module_inception)]
 mod image;
 // mod image_loader;
@@ -11,5 +11,4 @@ pub(crate) mod image_texture_conversion;
 
 pub use self::image::*;
 pub use self::ktx2::*;
-// #[cfg(feature = "dds")]
-// pub use dds::*;
+pub use dds::*;
@@ -0,0 +1,440 @@
+//! Layers of Photoshop (PSD) and Krita (KRA) documents.
+//!
+//! The loader only shows the flattened image. Here, the layers are read so they can be
+//! hidden, faded and exported one by one. Visible layers are composited again with
+//! normal blending, bottom to top.
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::{DynamicImage, Rgba, RgbaImage};
+use log::warn;
+use psd::Psd;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use xmltree::{Element, XMLNode};
+
+/// One layer, as large as the document
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    /// 0.0 to 1.0
+    pub opacity: f32,
+    pub image: RgbaImage,
+}
+
+/// The layers of a document, bottom to top
+#[derive(Debug, Clone, Default)]
+pub struct LayeredImage {
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<Layer>,
+}
+
+/// Whether `path` is a format with layers
+pub fn has_layers(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("psd") || e.eq_ignore_ascii_case("kra"))
+}
+
+/// Read the layers of a PSD or KRA file
+pub fn read_layers(path: &Path) -> Result<LayeredImage> {
+    match path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("psd") => read_psd(path),
+        Some("kra") => read_kra(path),
+        _ => bail!("{} has no layers", path.display()),
+    }
+}
+
+fn read_psd(path: &Path) -> Result<LayeredImage> {
+    let contents = std::fs::read(path)?;
+    let psd = Psd::from_bytes(&contents).map_err(|e| anyhow!("{:?}", e))?;
+    let (width, height) = (psd.width(), psd.height());
+    let layers = psd
+        .layers()
+        .iter()
+        .filter_map(|layer| {
+            Some(Layer {
+                name: layer.name().to_string(),
+                visible: layer.visible(),
+                opacity: layer.opacity() as f32 / 255.,
+                image: RgbaImage::from_raw(width, height, layer.rgba())?,
+            })
+        })
+        .collect();
+    Ok(LayeredImage {
+        width,
+        height,
+        layers,
+    })
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+    let mut entry = archive.by_name(name)?;
+    let mut data = vec![];
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn attribute<'a>(element: &'a Element, name: &str) -> Option<&'a str> {
+    element.attributes.get(name).map(|a| a.as_str())
+}
+
+fn child_elements<'a>(element: &'a Element, name: &'a str) -> impl Iterator<Item = &'a Element> {
+    element.children.iter().filter_map(move |node| match node {
+        XMLNode::Element(e) if e.name == name => Some(e),
+        _ => None,
+    })
+}
+
+/// Collect the paint layers below a `<layers>` element. Krita lists them top to bottom,
+/// hidden groups hide their children.
+fn kra_layer_elements<'a>(layers: &'a Element, visible: bool, out: &mut Vec<(&'a Element, bool)>) {
+    for layer in child_elements(layers, "layer") {
+        let layer_visible = visible && attribute(layer, "visible") != Some("0");
+        match attribute(layer, "nodetype") {
+            Some("paintlayer") => out.push((layer, layer_visible)),
+            Some("grouplayer") => {
+                if let Some(children) = child_elements(layer, "layers").next() {
+                    kra_layer_elements(children, layer_visible, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_kra(path: &Path) -> Result<LayeredImage> {
+    // https://docs.krita.org/en/general_concepts/file_formats/file_kra.html
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let maindoc = read_zip_entry(&mut archive, "maindoc.xml")?;
+    let doc = Element::parse(maindoc.as_slice())?;
+    let image = doc.get_child("IMAGE").context("No IMAGE in maindoc.xml")?;
+    let width = attribute(image, "width")
+        .and_then(|w| w.parse().ok())
+        .unwrap_or(0);
+    let height = attribute(image, "height")
+        .and_then(|h| h.parse().ok())
+        .unwrap_or(0);
+    let doc_name = attribute(image, "name").unwrap_or_default();
+
+    let mut elements = vec![];
+    if let Some(layers) = image.get_child("layers") {
+        kra_layer_elements(layers, true, &mut elements);
+    }
+
+    let mut layers = vec![];
+    for (element, visible) in elements.into_iter().rev() {
+        let name = attribute(element, "name").unwrap_or_default().to_string();
+        if attribute(element, "colorspacename") != Some("RGBA") {
+            warn!("Skipping layer {name}: Only 8 bit RGBA layers are supported");
+            continue;
+        }
+        let filename = attribute(element, "filename").context("Layer without file")?;
+        let data = read_zip_entry(&mut archive, &format!("{doc_name}/layers/{filename}"))?;
+        let offset = (
+            attribute(element, "x")
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(0),
+            attribute(element, "y")
+                .and_then(|y| y.parse().ok())
+                .unwrap_or(0),
+        );
+        layers.push(Layer {
+            name,
+            visible,
+            opacity: attribute(element, "opacity")
+                .and_then(|o| o.parse::<f32>().ok())
+                .unwrap_or(255.)
+                / 255.,
+            image: decode_kra_tiles(&data, width, height, offset)?,
+        });
+    }
+    Ok(LayeredImage {
+        width,
+        height,
+        layers,
+    })
+}
+
+/// Decompress LZF data, as used by Krita tiles
+fn lzf_decompress(input: &[u8], output_len: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            let literal = input
+                .get(i..i + ctrl + 1)
+                .context("Truncated LZF literal")?;
+            output.extend_from_slice(literal);
+            i += ctrl + 1;
+        } else {
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += *input.get(i).context("Truncated LZF length")? as usize;
+                i += 1;
+            }
+            let back =
+                ((ctrl & 0x1f) << 8) + *input.get(i).context("Truncated LZF offset")? as usize + 1;
+            i += 1;
+            let start = output
+                .len()
+                .checked_sub(back)
+                .context("Invalid LZF back reference")?;
+            // The ranges can overlap, so copy byte by byte
+            for j in 0..len + 2 {
+                output.push(output[start + j]);
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn read_line<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a str> {
+    let rest = data.get(*pos..).unwrap_or_default();
+    let end = rest
+        .iter()
+        .position(|b| *b == b'\n')
+        .context("Truncated tile header")?;
+    *pos += end + 1;
+    Ok(std::str::from_utf8(&rest[..end])?)
+}
+
+fn read_header_value(data: &[u8], pos: &mut usize, key: &str) -> Result<i64> {
+    let line = read_line(data, pos)?;
+    line.strip_prefix(key)
+        .and_then(|v| v.trim().parse().ok())
+        .with_context(|| format!("Expected {key}, got {line}"))
+}
+
+/// Krita stores paint layers as 64x64 tiles with LZF compressed, planar BGRA pixels
+fn decode_kra_tiles(data: &[u8], width: u32, height: u32, offset: (i64, i64)) -> Result<RgbaImage> {
+    let mut image = RgbaImage::new(width, height);
+    let mut pos = 0;
+    let version = read_header_value(data, &mut pos, "VERSION")?;
+    if version != 2 {
+        bail!("Unsupported tile version {version}");
+    }
+    let tile_width = read_header_value(data, &mut pos, "TILEWIDTH")? as usize;
+    let tile_height = read_header_value(data, &mut pos, "TILEHEIGHT")? as usize;
+    let pixel_size = read_header_value(data, &mut pos, "PIXELSIZE")? as usize;
+    let tiles = read_header_value(data, &mut pos, "DATA")?;
+    if pixel_size != 4 {
+        bail!("Unsupported pixel size {pixel_size}");
+    }
+    let tile_bytes = tile_width * tile_height * pixel_size;
+
+    for _ in 0..tiles {
+        let line = read_line(data, &mut pos)?;
+        let [x, y, _, size] = line.split(',').collect::<Vec<_>>()[..] else {
+            bail!("Invalid tile header {line}");
+        };
+        let (x, y) = (x.parse::<i64>()?, y.parse::<i64>()?);
+        let size = size.trim().parse::<usize>()?;
+        let compressed = data.get(pos..pos + size).context("Truncated tile data")?;
+        pos += size;
+
+        // The first byte tells if the tile is compressed at all
+        let planar = match compressed.split_first() {
+            Some((1, rest)) => lzf_decompress(rest, tile_bytes)?,
+            Some((_, rest)) => rest.to_vec(),
+            None => continue,
+        };
+        if planar.len() < tile_bytes {
+            bail!("Tile at {x},{y} is too small");
+        }
+        let plane = tile_width * tile_height;
+        for ty in 0..tile_height {
+            for tx in 0..tile_width {
+                let px = x + tx as i64 + offset.0;
+                let py = y + ty as i64 + offset.1;
+                if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+                    continue;
+                }
+                let i = ty * tile_width + tx;
+                let [b, g, r, a] = [0, 1, 2, 3].map(|c| planar[c * plane + i]);
+                image.put_pixel(px as u32, py as u32, Rgba([r, g, b, a]));
+            }
+        }
+    }
+    Ok(image)
+}
+
+impl LayeredImage {
+    /// Blend the visible layers over each other
+    pub fn composite(&self) -> RgbaImage {
+        let visible = self
+            .layers
+            .iter()
+            .filter(|l| l.visible && l.opacity > 0.)
+            .collect::<Vec<_>>();
+        let mut result = vec![0u8; self.width as usize * self.height as usize * 4];
+        result.par_chunks_mut(4).enumerate().for_each(|(i, pixel)| {
+            let mut out = [0f32; 4];
+            for layer in &visible {
+                let src = &layer.image.as_raw()[i * 4..i * 4 + 4];
+                let alpha = src[3] as f32 / 255. * layer.opacity;
+                let out_alpha = alpha + out[3] * (1. - alpha);
+                if out_alpha <= 0. {
+                    continue;
+                }
+                let blend =
+                    |c: usize| (src[c] as f32 * alpha + out[c] * out[3] * (1. - alpha)) / out_alpha;
+                out = [blend(0), blend(1), blend(2), out_alpha];
+            }
+            let [r, g, b, a] = out;
+            pixel.copy_from_slice(&[
+                r.round() as u8,
+                g.round() as u8,
+                b.round() as u8,
+                (a * 255.).round() as u8,
+            ]);
+        });
+        RgbaImage::from_raw(self.width, self.height, result).unwrap_or_default()
+    }
+}
+
+/// A file name for a layer: Its position and name, without characters that are not allowed
+fn layer_file_name(index: usize, name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("{:02}_{name}.png", index + 1)
+}
+
+/// The layers of the current document and which of them are shown
+#[derive(Debug, Default)]
+pub struct LayerState {
+    /// The file the layers were read from
+    pub path: Option<PathBuf>,
+    pub image: Option<LayeredImage>,
+    pub error: Option<String>,
+    pending: Option<Receiver<Result<LayeredImage>>>,
+}
+
+impl LayerState {
+    /// Read the layers of `path` in the background, unless they are known already
+    pub fn load(&mut self, path: &Path) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+        self.clear();
+        self.path = Some(path.to_path_buf());
+        let (sender, receiver) = channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            _ = sender.send(read_layers(&path));
+        });
+        self.pending = Some(receiver);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Pick up the result of [`LayerState::load`]
+    pub fn poll(&mut self) {
+        let Some(result) = self.pending.as_ref().and_then(|r| r.try_recv().ok()) else {
+            return;
+        };
+        self.pending = None;
+        match result {
+            Ok(image) => self.image = Some(image),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// The visible layers, blended
+    pub fn composite(&self) -> Option<DynamicImage> {
+        Some(DynamicImage::ImageRgba8(self.image.as_ref()?.composite()))
+    }
+
+    /// The folder next to the document that exported layers are written to
+    pub fn export_dir(&self) -> Option<PathBuf> {
+        let path = self.path.as_ref()?;
+        let stem = path.file_stem()?.to_string_lossy();
+        Some(path.with_file_name(format!("{stem}_layers")))
+    }
+
+    /// Save a layer as PNG. Returns the file it was written to.
+    pub fn export_layer(&self, index: usize) -> Result<PathBuf> {
+        let layer = self
+            .image
+            .as_ref()
+            .and_then(|i| i.layers.get(index))
+            .context("The layer does not exist")?;
+        let dir = self.export_dir().context("No document")?;
+        std::fs::create_dir_all(&dir)?;
+        let dest = dir.join(layer_file_name(index, &layer.name));
+        layer.image.save(&dest)?;
+        Ok(dest)
+    }
+
+    /// Save every layer as PNG. Returns the folder they were written to.
+    pub fn export_all(&self) -> Result<PathBuf> {
+        let count = self
+            .image
+            .as_ref()
+            .map(|i| i.layers.len())
+            .unwrap_or_default();
+        for index in 0..count {
+            self.export_layer(index)?;
+        }
+        self.export_dir().context("No document")
+    }
+
+    /// Forget everything, for example because another image was loaded
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decompresses_lzf() {
+        // A literal run of "ab", then a back reference repeating it twice
+        let compressed = [1, b'a', b'b', 0b0100_0000, 1];
+        assert_eq!(lzf_decompress(&compressed, 6).unwrap(), b"ababab");
+    }
+
+    #[test]
+    fn composites_visible_layers() {
+        let layer = |color: [u8; 4], visible: bool, opacity: f32| Layer {
+            name: "layer".into(),
+            visible,
+            opacity,
+            image: RgbaImage::from_pixel(1, 1, Rgba(color)),
+        };
+        let mut image = LayeredImage {
+            width: 1,
+            height: 1,
+            layers: vec![
+                layer([255, 0, 0, 255], true, 1.),
+                layer([0, 0, 255, 255], true, 0.5),
+                layer([0, 255, 0, 255], false, 1.),
+            ],
+        };
+        assert_eq!(image.composite().get_pixel(0, 0).0, [128, 0, 128, 255]);
+        image.layers[1].visible = false;
+        assert_eq!(image.composite().get_pixel(0, 0).0, [255, 0, 0, 255]);
+    }
+}
@@ -1,25 +1,40 @@
 pub mod appstate;
+pub mod archive;
 pub mod cache;
+pub mod cli;
+pub mod color_management;
 pub mod comparelist;
 pub mod image_editing;
 pub mod image_loader;
+pub mod layers;
 pub mod ktx2_loader;
 pub mod settings;
 pub mod shortcuts;
 pub mod utils;
 pub const FONT: &[u8; 309828] = include_bytes!("../res/fonts/Inter-Regular.ttf");
 pub const BOLD_FONT: &[u8; 344152] = include_bytes!("../res/fonts/Inter-Bold.ttf");
+pub mod dicom_series;
+pub mod dicom_window;
+pub mod exr_layers;
 pub mod file_encoder;
 pub mod filebrowser;
 pub mod icons;
+pub mod metadata;
 pub mod net;
+pub mod pages;
+pub mod raw_develop;
 pub mod paint;
+pub mod remote;
 pub mod scrubber;
+pub mod stream;
+pub mod texture_inspector;
 pub mod texture_wrapper;
 pub mod thumbnails;
+pub mod tonemapping;
 pub mod ui;
 #[cfg(feature = "update")]
 pub mod update;
+pub mod web_export;
 
 // mod events;
 #[cfg(target_os = "macos")]
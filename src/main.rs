@@ -1,7 +1,5 @@
 #![windows_subsystem = "windows"]
 
-use clap::Arg;
-use clap::Command;
 use image::GenericImageView;
 use image_editing::LegacyEditState;
 use log::debug;
@@ -23,7 +21,7 @@ use notan::egui::FontTweak;
 use notan::egui::Id;
 use notan::prelude::*;
 use oculante::comparelist::CompareItem;
-use std::io::{stdin, IsTerminal, Read};
+use std::io::{stdin, IsTerminal};
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::Arc;
@@ -43,6 +41,7 @@ use ui::*;
 use image_editing::lossless_tx;
 use image_editing::EditState;
 use scrubber::find_first_image_in_directory;
+use shortcuts::InputEvent;
 use shortcuts::InputEvent::*;
 
 #[notan_main]
@@ -53,11 +52,28 @@ fn main() -> Result<(), String> {
     // on debug builds, override log level
     #[cfg(debug_assertions)]
     {
-        println!("Debug");
+        eprintln!("Debug");
         std::env::set_var("RUST_LOG", "debug");
     }
     let _ = env_logger::try_init();
 
+    // Headless subcommands run without creating a window
+    let matches = cli::matches();
+    if matches.subcommand().is_some() {
+        // The window subsystem has no console, print to the one we were started from
+        #[cfg(windows)]
+        unsafe {
+            use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+            AttachConsole(ATTACH_PARENT_PROCESS);
+        }
+    }
+    if let Some(result) = cli::run(&matches) {
+        return result.map_err(|e| format!("{e:#}"));
+    }
+    if cli::forward_to_running_instance(&matches) {
+        return Ok(());
+    }
+
     let icon_data = include_bytes!("../icon.ico");
 
     let mut window_config = WindowConfig::new()
@@ -159,37 +175,7 @@ fn main() -> Result<(), String> {
 
 fn init(_app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteState {
     debug!("Now matching arguments {:?}", std::env::args());
-    // Filter out strange mac args
-    let args: Vec<String> = std::env::args().filter(|a| !a.contains("psn_")).collect();
-
-    let mut matches = Command::new("Oculante")
-        .arg(
-            Arg::new("INPUT")
-                .help("Display this image")
-                .multiple_values(true), // .index(1)
-                                        // )
-        )
-        .arg(
-            Arg::new("l")
-                .short('l')
-                .help("Listen on port")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::new("stdin")
-                .short('s')
-                .id("stdin")
-                .takes_value(false)
-                .help("Load data from STDIN"),
-        )
-        .arg(
-            Arg::new("chainload")
-                .required(false)
-                .takes_value(false)
-                .short('c')
-                .help("Chainload on Mac"),
-        )
-        .get_matches_from(args);
+    let mut matches = cli::matches();
 
     debug!("Completed argument parsing.");
 
@@ -227,7 +213,7 @@ fn init(_app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSt
             .into_iter()
             .next()
             .expect("It should be tested already that exactly one argument was passed.");
-        if location.is_dir() {
+        if location.is_dir() || archive::is_archive(&location) {
             // Folder - Pick first image from the folder...
             if let Ok(first_img_location) = find_first_image_in_directory(&location) {
                 state.is_loaded = false;
@@ -243,7 +229,7 @@ fn init(_app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSt
         let location = paths_to_open
             .first()
             .expect("It should be verified already that exactly one argument was passed.");
-        if location.is_dir() {
+        if location.is_dir() || archive::is_archive(location) {
             // Folder - Pick first image from the folder...
             if let Ok(first_img_location) = find_first_image_in_directory(location) {
                 state.is_loaded = false;
@@ -272,39 +258,51 @@ fn init(_app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSt
     }
 
     if matches.contains_id("stdin") {
-        debug!("Trying to read from pipe");
-        let mut input = vec![];
-        if let Ok(bytes_read) = std::io::stdin().read_to_end(&mut input) {
-            if bytes_read > 0 {
-                debug!("There was stdin");
-
-                match image::load_from_memory(input.as_ref()) {
-                    Ok(i) => {
-                        // println!("got image");
-                        debug!("Sending image!");
-                        let _ = state
-                            .texture_channel
-                            .0
-                            .clone()
-                            .send(utils::Frame::new_reset(i));
-                    }
-                    Err(e) => error!("ERR loading from stdin: {e} - for now, oculante only supports data that can be decoded by the image crate."),
-                }
-            }
-        }
+        debug!("Reading images from stdin");
+        stream::stream(
+            std::io::stdin(),
+            state.texture_channel.0.clone(),
+            state.message_channel.0.clone(),
+            state.persistent_settings.decoders,
+        );
+        state.current_path = Some(PathBuf::from("stdin"));
+        // Frames arrive without a window event, so keep polling for them
+        state.network_mode = true;
     }
 
+    let mut listen_addresses = vec![];
     if let Some(port) = matches.value_of("l") {
-        match port.parse::<i32>() {
-            Ok(p) => {
-                state.send_message_info(&format!("Listening on {p}"));
-                net::recv(p, state.texture_channel.0.clone());
-                state.current_path = Some(PathBuf::from(&format!("network port {p}")));
-                state.network_mode = true;
-            }
+        match port.parse::<u16>() {
+            Ok(port) => listen_addresses.push(net::ListenAddress::Tcp {
+                host: matches.value_of("bind").unwrap_or("127.0.0.1").to_string(),
+                port,
+            }),
             Err(_) => error!("Port must be a number"),
         }
     }
+    #[cfg(unix)]
+    if let Some(socket) = matches.value_of("socket") {
+        listen_addresses.push(net::ListenAddress::Unix(PathBuf::from(socket)));
+    }
+    if state.persistent_settings.single_instance {
+        remote::listen(
+            state.load_channel.0.clone(),
+            state.message_channel.0.clone(),
+        );
+        // Remote commands arrive without a window event, so keep polling for them
+        state.network_mode = true;
+    }
+
+    for address in listen_addresses {
+        state.send_message_info(&format!("Listening on {address}"));
+        state.current_path = Some(PathBuf::from(&format!("network {address}")));
+        net::recv(
+            address,
+            state.texture_channel.0.clone(),
+            state.message_channel.0.clone(),
+        );
+        state.network_mode = true;
+    }
 
     // Set up egui style / theme
     plugins.egui(|ctx| {
@@ -394,203 +392,249 @@ fn init(_app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSt
     state
 }
 
-fn process_events(app: &mut App, state: &mut OculanteState, evt: Event) {
-    if state.key_grab {
-        return;
-    }
-    match evt {
-        Event::KeyUp { .. } => {
-            // Fullscreen needs to be on key up on mac (bug)
-            if key_pressed(app, state, Fullscreen) {
-                toggle_fullscreen(app, state);
-            }
+/// Perform the action bound to a shortcut
+fn run_command(app: &mut App, state: &mut OculanteState, command: InputEvent) {
+    // pan image with keyboard
+    let delta = 40.;
+    match command {
+        Fullscreen => toggle_fullscreen(app, state),
+        PanRight => {
+            state.image_geometry.offset.x -= delta;
+            limit_offset(app, state);
         }
-        Event::KeyDown { .. } => {
-            debug!("key down");
-
-            // return;
-            // pan image with keyboard
-            let delta = 40.;
-            if key_pressed(app, state, PanRight) {
-                state.image_geometry.offset.x -= delta;
-                limit_offset(app, state);
-            }
-            if key_pressed(app, state, PanUp) {
-                state.image_geometry.offset.y += delta;
-                limit_offset(app, state);
-            }
-            if key_pressed(app, state, PanLeft) {
-                state.image_geometry.offset.x += delta;
-                limit_offset(app, state);
-            }
-            if key_pressed(app, state, PanDown) {
-                state.image_geometry.offset.y -= delta;
-                limit_offset(app, state);
-            }
-            if key_pressed(app, state, CompareNext) {
-                compare_next(app, state);
-            }
-            if key_pressed(app, state, ResetView) {
-                state.reset_image = true
-            }
-            if key_pressed(app, state, ZenMode) {
-                toggle_zen_mode(state, app);
-            }
-            if key_pressed(app, state, ZoomActualSize) {
-                set_zoom(1.0, None, state);
-            }
-            if key_pressed(app, state, ZoomDouble) {
-                set_zoom(2.0, None, state);
-            }
-            if key_pressed(app, state, ZoomThree) {
-                set_zoom(3.0, None, state);
-            }
-            if key_pressed(app, state, ZoomFour) {
-                set_zoom(4.0, None, state);
-            }
-            if key_pressed(app, state, ZoomFive) {
-                set_zoom(5.0, None, state);
-            }
-            if key_pressed(app, state, Copy) {
-                if let Some(img) = &state.current_image {
-                    clipboard_copy(img);
-                    state.send_message_info("Image copied");
-                }
+        PanUp => {
+            state.image_geometry.offset.y += delta;
+            limit_offset(app, state);
+        }
+        PanLeft => {
+            state.image_geometry.offset.x += delta;
+            limit_offset(app, state);
+        }
+        PanDown => {
+            state.image_geometry.offset.y -= delta;
+            limit_offset(app, state);
+        }
+        CompareNext => {
+            compare_next(app, state);
+        }
+        ResetView => {
+            state.reset_image = true
+        }
+        ZenMode => {
+            toggle_zen_mode(state, app);
+        }
+        ZoomActualSize => {
+            set_zoom(1.0, None, state);
+        }
+        ZoomDouble => {
+            set_zoom(2.0, None, state);
+        }
+        ZoomThree => {
+            set_zoom(3.0, None, state);
+        }
+        ZoomFour => {
+            set_zoom(4.0, None, state);
+        }
+        ZoomFive => {
+            set_zoom(5.0, None, state);
+        }
+        Copy => {
+            if let Some(img) = &state.current_image {
+                clipboard_copy(img);
+                state.send_message_info("Image copied");
             }
-
-            if key_pressed(app, state, Paste) {
-                match clipboard_to_image() {
-                    Ok(img) => {
-                        state.current_path = None;
-                        // Stop in the even that an animation is running
-                        state.player.stop();
-                        _ = state
-                            .player
-                            .image_sender
-                            .send(crate::utils::Frame::new_still(img));
-                        // Since pasted data has no path, make sure it's not set
-                        state.send_message_info("Image pasted");
-                    }
-                    Err(e) => state.send_message_err(&e.to_string()),
+        }
+        Paste => {
+            match clipboard_to_image() {
+                Ok(img) => {
+                    state.current_path = None;
+                    // Stop in the even that an animation is running
+                    state.player.stop();
+                    _ = state
+                        .player
+                        .image_sender
+                        .send(crate::utils::Frame::new_still(img));
+                    // Since pasted data has no path, make sure it's not set
+                    state.send_message_info("Image pasted");
                 }
+                Err(e) => state.send_message_err(&e.to_string()),
             }
-            if key_pressed(app, state, Quit) {
-                _ = state.persistent_settings.save_blocking();
-                _ = state.volatile_settings.save_blocking();
-                app.backend.exit();
-            }
-            #[cfg(feature = "turbo")]
-            if key_pressed(app, state, LosslessRotateRight) {
-                debug!("Lossless rotate right");
+        }
+        Quit => {
+            _ = state.persistent_settings.save_blocking();
+            _ = state.volatile_settings.save_blocking();
+            app.backend.exit();
+        }
+        #[cfg(feature = "turbo")]
+        LosslessRotateRight => {
+            debug!("Lossless rotate right");
 
-                if let Some(p) = &state.current_path {
-                    if lossless_tx(p, turbojpeg::Transform::op(turbojpeg::TransformOp::Rot90))
-                        .is_ok()
-                    {
-                        state.is_loaded = false;
-                        // This needs "deep" reload
-                        state.player.cache.clear();
-                        state.player.load(p);
-                    }
-                }
-            }
-            #[cfg(feature = "turbo")]
-            if key_pressed(app, state, LosslessRotateLeft) {
-                debug!("Lossless rotate left");
-                if let Some(p) = &state.current_path {
-                    if lossless_tx(p, turbojpeg::Transform::op(turbojpeg::TransformOp::Rot270))
-                        .is_ok()
-                    {
-                        state.is_loaded = false;
-                        // This needs "deep" reload
-                        state.player.cache.clear();
-                        state.player.load(p);
-                    } else {
-                        warn!("rotate left failed")
-                    }
+            if let Some(p) = &state.current_path {
+                if lossless_tx(p, turbojpeg::Transform::op(turbojpeg::TransformOp::Rot90))
+                    .is_ok()
+                {
+                    state.is_loaded = false;
+                    // This needs "deep" reload
+                    state.player.cache.clear();
+                    state.player.load(p);
                 }
             }
-            if key_pressed(app, state, Browse) {
-                state.filebrowser_last_dir = if app.keyboard.shift() {
-                    BrowserDir::CurrentImageDir
-                } else {
-                    BrowserDir::LastOpenDir
-                };
-
-                state.redraw = true;
-                #[cfg(feature = "file_open")]
-                browse_for_image_path(state);
-                #[cfg(not(feature = "file_open"))]
+        }
+        #[cfg(feature = "turbo")]
+        LosslessRotateLeft => {
+            debug!("Lossless rotate left");
+            if let Some(p) = &state.current_path {
+                if lossless_tx(p, turbojpeg::Transform::op(turbojpeg::TransformOp::Rot270))
+                    .is_ok()
                 {
-                    state.filebrowser_id = Some("OPEN".into());
+                    state.is_loaded = false;
+                    // This needs "deep" reload
+                    state.player.cache.clear();
+                    state.player.load(p);
+                } else {
+                    warn!("rotate left failed")
                 }
             }
-
-            if key_pressed(app, state, NextImage) {
-                next_image(state)
-            }
-            if key_pressed(app, state, PreviousImage) {
-                prev_image(state)
-            }
-            if key_pressed(app, state, FirstImage) {
-                first_image(state)
-            }
-            if key_pressed(app, state, LastImage) {
-                last_image(state)
-            }
-            if key_pressed(app, state, AlwaysOnTop) {
-                state.always_on_top = !state.always_on_top;
-                app.window().set_always_on_top(state.always_on_top);
-            }
-            if key_pressed(app, state, InfoMode) {
-                state.persistent_settings.info_enabled = !state.persistent_settings.info_enabled;
-            }
-            if key_pressed(app, state, EditMode) {
-                state.persistent_settings.edit_enabled = !state.persistent_settings.edit_enabled;
+        }
+        Browse => {
+            state.filebrowser_last_dir = if app.keyboard.shift() {
+                BrowserDir::CurrentImageDir
+            } else {
+                BrowserDir::LastOpenDir
+            };
+
+            state.redraw = true;
+            #[cfg(feature = "file_open")]
+            browse_for_image_path(state);
+            #[cfg(not(feature = "file_open"))]
+            {
+                state.filebrowser_id = Some("OPEN".into());
             }
-            if key_pressed(app, state, DeleteFile) {
-                // TODO: needs confirmation
-                delete_file(state);
+        }
+        NextImage => {
+            next_image(state)
+        }
+        PreviousImage => {
+            prev_image(state)
+        }
+        FirstImage => {
+            first_image(state)
+        }
+        LastImage => {
+            last_image(state)
+        }
+        NextPage => {
+            state.pages.next()
+        }
+        PreviousPage => {
+            state.pages.previous()
+        }
+        AlwaysOnTop => {
+            state.always_on_top = !state.always_on_top;
+            app.window().set_always_on_top(state.always_on_top);
+        }
+        InfoMode => {
+            state.persistent_settings.info_enabled = !state.persistent_settings.info_enabled;
+        }
+        EditMode => {
+            state.persistent_settings.edit_enabled = !state.persistent_settings.edit_enabled;
+        }
+        DeleteFile => {
+            // TODO: needs confirmation
+            delete_file(state);
+        }
+        ClearImage => {
+            clear_image(state);
+        }
+        ZoomIn => {
+            let delta = zoomratio(3.5, state.image_geometry.scale);
+            let new_scale = state.image_geometry.scale + delta;
+            // limit scale
+            if new_scale > 0.05 && new_scale < 40. {
+                // We want to zoom towards the center
+                let center: Vector2<f32> = nalgebra::Vector2::new(
+                    app.window().width() as f32 / 2.,
+                    app.window().height() as f32 / 2.,
+                );
+                state.image_geometry.offset -= scale_pt(
+                    state.image_geometry.offset,
+                    center,
+                    state.image_geometry.scale,
+                    delta,
+                );
+                state.image_geometry.scale += delta;
             }
-            if key_pressed(app, state, ClearImage) {
-                clear_image(state);
+        }
+        ZoomOut => {
+            let delta = zoomratio(-3.5, state.image_geometry.scale);
+            let new_scale = state.image_geometry.scale + delta;
+            // limit scale
+            if new_scale > 0.05 && new_scale < 40. {
+                // We want to zoom towards the center
+                let center: Vector2<f32> = nalgebra::Vector2::new(
+                    app.window().width() as f32 / 2.,
+                    app.window().height() as f32 / 2.,
+                );
+                state.image_geometry.offset -= scale_pt(
+                    state.image_geometry.offset,
+                    center,
+                    state.image_geometry.scale,
+                    delta,
+                );
+                state.image_geometry.scale += delta;
             }
-            if key_pressed(app, state, ZoomIn) {
-                let delta = zoomratio(3.5, state.image_geometry.scale);
-                let new_scale = state.image_geometry.scale + delta;
-                // limit scale
-                if new_scale > 0.05 && new_scale < 40. {
-                    // We want to zoom towards the center
-                    let center: Vector2<f32> = nalgebra::Vector2::new(
-                        app.window().width() as f32 / 2.,
-                        app.window().height() as f32 / 2.,
-                    );
-                    state.image_geometry.offset -= scale_pt(
-                        state.image_geometry.offset,
-                        center,
-                        state.image_geometry.scale,
-                        delta,
-                    );
-                    state.image_geometry.scale += delta;
-                }
+        }
+        RedChannel => set_channel(state, ColorChannel::Red),
+        GreenChannel => set_channel(state, ColorChannel::Green),
+        BlueChannel => set_channel(state, ColorChannel::Blue),
+        AlphaChannel => set_channel(state, ColorChannel::Alpha),
+        RGBChannel => set_channel(state, ColorChannel::Rgb),
+        RGBAChannel => set_channel(state, ColorChannel::Rgba),
+        #[cfg(not(feature = "turbo"))]
+        LosslessRotateRight | LosslessRotateLeft => {}
+    }
+}
+
+fn set_channel(state: &mut OculanteState, channel: ColorChannel) {
+    state.persistent_settings.current_channel = channel;
+    state.send_frame(Frame::UpdateTexture);
+}
+
+fn process_events(app: &mut App, state: &mut OculanteState, evt: Event) {
+    if state.key_grab {
+        return;
+    }
+    match evt {
+        Event::KeyUp { .. } => {
+            // Fullscreen needs to be on key up on mac (bug)
+            if key_pressed(app, state, Fullscreen) {
+                run_command(app, state, Fullscreen);
             }
-            if key_pressed(app, state, ZoomOut) {
-                let delta = zoomratio(-3.5, state.image_geometry.scale);
-                let new_scale = state.image_geometry.scale + delta;
-                // limit scale
-                if new_scale > 0.05 && new_scale < 40. {
-                    // We want to zoom towards the center
-                    let center: Vector2<f32> = nalgebra::Vector2::new(
-                        app.window().width() as f32 / 2.,
-                        app.window().height() as f32 / 2.,
-                    );
-                    state.image_geometry.offset -= scale_pt(
-                        state.image_geometry.offset,
-                        center,
-                        state.image_geometry.scale,
-                        delta,
-                    );
-                    state.image_geometry.scale += delta;
+        }
+        Event::KeyDown { .. } => {
+            debug!("key down");
+            let commands = state
+                .persistent_settings
+                .shortcuts
+                .keys()
+                // Fullscreen is handled on key up, channels in the top bar
+                .filter(|command| {
+                    !matches!(
+                        command,
+                        Fullscreen
+                            | RedChannel
+                            | GreenChannel
+                            | BlueChannel
+                            | AlphaChannel
+                            | RGBChannel
+                            | RGBAChannel
+                    )
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            for command in commands {
+                if key_pressed(app, state, command.clone()) {
+                    run_command(app, state, command);
                 }
             }
         }
@@ -661,6 +705,12 @@ fn process_events(app: &mut App, state: &mut OculanteState, evt: Event) {
         Event::Drop(file) => {
             trace!("File drop event");
             if let Some(p) = file.path {
+                // Archives open at their first image
+                let p = if archive::is_archive(&p) {
+                    find_first_image_in_directory(&p).unwrap_or(p)
+                } else {
+                    p
+                };
                 if let Some(ext) = p.extension() {
                     if SUPPORTED_EXTENSIONS
                         .contains(&ext.to_string_lossy().to_string().to_lowercase().as_str())
@@ -684,10 +734,16 @@ fn process_events(app: &mut App, state: &mut OculanteState, evt: Event) {
             MouseButton::Middle => {
                 state.drag_enabled = true;
             }
+            MouseButton::Right => {
+                state.dicom_window.dragging = state.dicom_window.is_active()
+                    && !state.mouse_grab
+                    && !state.pointer_over_ui;
+            }
             _ => {}
         },
         Event::MouseUp { button, .. } => match button {
             MouseButton::Left | MouseButton::Middle => state.drag_enabled = false,
+            MouseButton::Right => state.dicom_window.dragging = false,
             _ => {}
         },
         _ => {
@@ -736,6 +792,11 @@ fn update(app: &mut App, state: &mut OculanteState) {
         state.image_geometry.offset += state.mouse_delta;
         limit_offset(app, state);
     }
+    if state.dicom_window.dragging {
+        state
+            .dicom_window
+            .drag(state.mouse_delta.x, state.mouse_delta.y);
+    }
 
     // Since we can't access the window in the event loop, we store it in the state
     state.window_size = app.window().size().size_vec();
@@ -790,6 +851,12 @@ fn update(app: &mut App, state: &mut OculanteState) {
             Message::Saved(_) => {
                 state.toasts.info("Saved");
             }
+            Message::SourceName(name) => {
+                state.current_path = Some(PathBuf::from(name));
+            }
+            Message::Command(command) => {
+                run_command(app, state, command);
+            }
         }
     }
     state.first_start = false;
@@ -801,14 +868,81 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
     if let Ok(p) = state.load_channel.1.try_recv() {
         state.is_loaded = false;
         state.current_image = None;
-        state.player.load(&p);
         if let Some(dir) = p.parent() {
             state.volatile_settings.last_open_directory = dir.to_path_buf();
         }
+        // Archives open at their first image
+        let p = if archive::is_archive(&p) {
+            find_first_image_in_directory(&p).unwrap_or(p)
+        } else {
+            p
+        };
+        state.player.load(&p);
         state.current_path = Some(p);
         state.scrubber.fixed_paths = false;
     }
 
+    // show a page of a multi-page image once it is decoded
+    if let Some(img) = state.pages.poll() {
+        state.replace_current_image(img);
+    }
+    if state.pages.is_pending() {
+        app.window().request_frame();
+    }
+
+    // order the slices of a DICOM series anatomically instead of by file name
+    if let Some(path) = state.current_path.clone() {
+        let series_mode =
+            state.persistent_settings.dicom_series && state.dicom_window.is_dicom(&path);
+        if series_mode && state.is_loaded {
+            state.dicom_series.load(&path);
+        } else if !series_mode && state.dicom_series.series.is_some() {
+            state.dicom_series.clear();
+            state.scrubber = scrubber::Scrubber::new(&path);
+            state.scrubber.wrap = state.persistent_settings.wrap_folder;
+        }
+    }
+    if let Some(series) = state.dicom_series.poll() {
+        state.scrubber.entries = series.entries.clone();
+        state.scrubber.fixed_paths = true;
+        if let Some(index) = state
+            .scrubber
+            .entries
+            .iter()
+            .position(|p| Some(p) == state.current_path.as_ref())
+        {
+            state.scrubber.index = index;
+        }
+    }
+    if state.dicom_series.is_pending() {
+        app.window().request_frame();
+    }
+
+    // keep the values of DICOM images around to change their window
+    if let Some(path) = &state.current_path {
+        if state.dicom_window.is_dicom(path) && state.is_loaded {
+            state
+                .dicom_window
+                .load(path, state.pages.current as u32);
+        }
+    }
+    state.dicom_window.poll();
+    if state.dicom_window.is_pending() {
+        app.window().request_frame();
+    }
+    // The window changes while dragging, so this only updates the texture
+    if let Some(img) = state.dicom_window.take_image() {
+        state.edit_state.result_image_op = Default::default();
+        state.edit_state.result_pixel_op = Default::default();
+        if let Err(error) = state
+            .current_texture
+            .set_image(&img, gfx, &state.persistent_settings)
+        {
+            state.send_message_warn(&format!("Error while displaying image: {error}"));
+        }
+        state.current_image = Some(img);
+    }
+
     // check if a new loaded image has been sent
     // Drain loop to get latest frame and prevent animation speedup on focus loss
     let latest_frame = state.texture_channel.1.try_iter().last();
@@ -820,7 +954,10 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
 
         if matches!(
             &frame,
-            Frame::AnimationStart(_) | Frame::Still(_) | Frame::ImageCollectionMember(_)
+            Frame::AnimationStart(_)
+                | Frame::Still(_)
+                | Frame::Preview(_)
+                | Frame::ImageCollectionMember(_)
         ) {
             // Something new came in, update scrubber (index slider) and path
             if let Some(path) = &state.current_path {
@@ -859,11 +996,44 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
 
         match &frame {
             Frame::Still(ref img) | Frame::ImageCollectionMember(ref img) => {
+                // The full image replaces its preview without changing the view
+                let replaces_preview = state
+                    .preview_of
+                    .take()
+                    .is_some_and(|p| state.current_path == Some(p))
+                    && matches!(frame, Frame::Still(_));
+                if replaces_preview {
+                    let (preview_width, _) = state.image_geometry.dimensions;
+                    state.image_geometry.scale *=
+                        preview_width as f32 / img.width().max(1) as f32;
+                }
+                state.is_animation = false;
+                state.web_export.clear();
+                state.exr_layers.clear();
+                state.layers.clear();
+                state.texture_inspector.clear();
+                state.raw_develop.clear();
+                // Slices of a series share their window and view
+                let same_series = matches!(frame, Frame::Still(_))
+                    && state
+                        .current_path
+                        .as_deref()
+                        .is_some_and(|p| state.dicom_series.contains(p));
+                state.dicom_window.clear(same_series);
+                if same_series {
+                    if let Some(path) = &state.current_path {
+                        state.dicom_window.load(path, 0);
+                    }
+                }
+                match (&frame, &state.current_path) {
+                    (Frame::Still(_), Some(path)) => state.pages.load(path),
+                    _ => state.pages = Default::default(),
+                }
                 state.edit_state.result_image_op = Default::default();
                 state.edit_state.result_pixel_op = Default::default();
 
                 if !state.persistent_settings.keep_view {
-                    state.reset_image = true;
+                    state.reset_image = !same_series && !replaces_preview;
 
                     if let Some(p) = state.current_path.clone() {
                         if state.persistent_settings.max_cache != 0 {
@@ -947,15 +1117,35 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 state.redraw = false;
                 // state.image_info = None;
             }
+            Frame::Preview(_) => {
+                state.is_animation = false;
+                state.preview_of = state.current_path.clone();
+                state.web_export.clear();
+                state.exr_layers.clear();
+                state.layers.clear();
+                state.texture_inspector.clear();
+                state.raw_develop.clear();
+                state.dicom_window.clear(false);
+                state.pages = Default::default();
+                state.edit_state.result_image_op = Default::default();
+                state.edit_state.result_pixel_op = Default::default();
+                if !state.persistent_settings.keep_view || state.current_texture.get().is_none() {
+                    state.reset_image = true;
+                }
+                state.redraw = false;
+            }
             Frame::EditResult(_) => {
                 state.redraw = false;
             }
             Frame::AnimationStart(_) => {
+                state.preview_of = None;
                 state.redraw = true;
-                state.reset_image = true
+                state.reset_image = true;
+                state.is_animation = false;
             }
             Frame::Animation(_, _) => {
                 state.redraw = true;
+                state.is_animation = true;
             }
             Frame::CompareResult(_, geo) => {
                 debug!("Received compare result");
@@ -978,6 +1168,7 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         match frame {
             Frame::AnimationStart(img)
             | Frame::Still(img)
+            | Frame::Preview(img)
             | Frame::EditResult(img)
             | Frame::CompareResult(img, _)
             | Frame::Animation(img, _)
@@ -985,12 +1176,15 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 debug!("Received image buffer: {:?}", img.dimensions(),);
                 state.image_geometry.dimensions = img.dimensions();
 
-                if let Err(error) =
-                    state
-                        .current_texture
-                        .set_image(&img, gfx, &state.persistent_settings)
-                {
-                    state.send_message_warn(&format!("Error while displaying image: {error}"));
+                // A slice of a DICOM series is shown once its window is applied
+                if !state.dicom_window.keeps_window() {
+                    if let Err(error) =
+                        state
+                            .current_texture
+                            .set_image(&img, gfx, &state.persistent_settings)
+                    {
+                        state.send_message_warn(&format!("Error while displaying image: {error}"));
+                    }
                 }
                 state.current_image = Some(img);
                 state.new_image_loaded = true;
@@ -1033,6 +1227,7 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         send_extended_info(
             &state.current_image,
             &state.current_path,
+            state.pages.current,
             &state.extended_info_channel,
         );
     }
@@ -1180,6 +1375,15 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             }
         }
 
+        // Zooming in on a preview shows its lack of detail, so decode the full image now
+        if state.preview_of.is_some()
+            && state.preview_of == state.current_path
+            && !state.reset_image
+            && state.image_geometry.scale > 1.0
+        {
+            state.player.decode_full();
+        }
+
         // Settings come last, as they block keyboard grab (for hotkey assigment)
         settings_ui(app, ctx, state, gfx);
     });
@@ -0,0 +1,475 @@
+//! Metadata carry-over when saving.
+//!
+//! Encoders only write pixels. EXIF, ICC profiles, XMP and PNG text chunks are read from the
+//! source file and written back into JPEG, PNG, WebP and TIFF output. If the pixels were turned
+//! upright when loading, the EXIF orientation is reset to normal so they are not turned twice.
+
+use anyhow::{Context, Result};
+use exif::experimental::Writer;
+use exif::{Field, In, Tag, Value};
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use img_parts::jpeg::{markers, JpegSegment};
+use img_parts::png::PngChunk;
+use img_parts::riff::{RiffChunk, RiffContent};
+use img_parts::{Bytes, DynImage, ImageEXIF, ImageICC};
+use log::debug;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Seek, Write};
+use std::path::Path;
+use tiff::encoder::colortype::{self, ColorType};
+use tiff::encoder::{DirectoryEncoder, TiffEncoder, TiffKind, TiffValue};
+
+const XMP_JPEG_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_PNG_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+const TIFF_TAG_XMP: u16 = 700;
+const TIFF_TAG_ICC: u16 = 34675;
+const TIFF_TAG_EXIF_IFD: u16 = 34665;
+const TIFF_TAG_GPS_IFD: u16 = 34853;
+
+/// Tags that describe how the pixels of the source were stored. They are written by the
+/// encoder and must not be copied.
+const STRUCTURAL_TAGS: &[Tag] = &[
+    Tag::ImageWidth,
+    Tag::ImageLength,
+    Tag::BitsPerSample,
+    Tag::Compression,
+    Tag::PhotometricInterpretation,
+    Tag::StripOffsets,
+    Tag::SamplesPerPixel,
+    Tag::RowsPerStrip,
+    Tag::StripByteCounts,
+    Tag::PlanarConfiguration,
+    Tag::TileOffsets,
+    Tag::TileByteCounts,
+    Tag::JPEGInterchangeFormat,
+    Tag::JPEGInterchangeFormatLength,
+    Tag::ExifIFDPointer,
+    Tag::GPSInfoIFDPointer,
+    Tag::InteropIFDPointer,
+    Tag::PixelXDimension,
+    Tag::PixelYDimension,
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct ImageMetadata {
+    /// Raw EXIF, starting with the TIFF header
+    pub exif: Option<Bytes>,
+    pub icc: Option<Bytes>,
+    pub xmp: Option<Bytes>,
+    /// tEXt, zTXt and iTXt chunks. These only fit into PNG output.
+    pub png_text: Vec<PngChunk>,
+    /// The pixels to save were already turned according to the EXIF orientation
+    pub oriented: bool,
+}
+
+impl ImageMetadata {
+    /// Read the metadata of an image file
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let mut metadata = Self::default();
+
+        if let Ok(Some(image)) = DynImage::from_bytes(data.clone().into()) {
+            metadata.exif = image.exif();
+            metadata.icc = image.icc_profile();
+            match &image {
+                DynImage::Jpeg(jpeg) => {
+                    metadata.xmp = jpeg
+                        .segments()
+                        .iter()
+                        .filter(|segment| segment.marker() == markers::APP1)
+                        .find_map(|segment| segment.contents().strip_prefix(XMP_JPEG_HEADER))
+                        .map(Bytes::copy_from_slice);
+                }
+                DynImage::Png(png) => {
+                    for chunk in png.chunks() {
+                        match &chunk.kind() {
+                            b"iTXt" if chunk.contents().starts_with(XMP_PNG_KEYWORD) => {
+                                metadata.xmp = parse_xmp_itxt(chunk.contents());
+                            }
+                            b"tEXt" | b"zTXt" | b"iTXt" => metadata.png_text.push(chunk.clone()),
+                            _ => {}
+                        }
+                    }
+                }
+                DynImage::WebP(webp) => {
+                    metadata.xmp = webp
+                        .chunk_by_id(*b"XMP ")
+                        .and_then(|chunk| chunk.content().data())
+                        .cloned();
+                }
+            }
+        }
+
+        // The generic decoder does not hand out the profile of TIFF files
+        if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+            if let Ok(mut decoder) = tiff::decoder::Decoder::new(Cursor::new(&data)) {
+                metadata.icc = decoder
+                    .get_tag_u8_vec(tiff::tags::Tag::IccProfile)
+                    .ok()
+                    .map(Bytes::from);
+                metadata.xmp = decoder
+                    .get_tag_u8_vec(tiff::tags::Tag::Unknown(TIFF_TAG_XMP))
+                    .ok()
+                    .map(Bytes::from);
+            }
+        }
+
+        // Other containers img-parts does not know
+        if metadata.icc.is_none() {
+            if let Ok(mut decoder) = ImageReader::open(path)?
+                .with_guessed_format()?
+                .into_decoder()
+            {
+                metadata.icc = decoder.icc_profile().ok().flatten().map(Bytes::from);
+            }
+        }
+        if metadata.exif.is_none() {
+            metadata.exif = exif::Reader::new()
+                .read_from_container(&mut Cursor::new(&data))
+                .ok()
+                .map(|exif| Bytes::from(exif.buf().to_vec()));
+        }
+        Ok(metadata)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.icc.is_none() && self.xmp.is_none() && self.png_text.is_empty()
+    }
+
+    /// The EXIF fields to write. Orientation is reset if the pixels are oriented, and location
+    /// is removed on request.
+    fn exif_fields(&self, strip_location: bool) -> Vec<Field> {
+        let Some(raw) = &self.exif else {
+            return vec![];
+        };
+        let exif = match exif::Reader::new().read_raw(raw.to_vec()) {
+            Ok(exif) => exif,
+            Err(e) => {
+                debug!("Dropping unreadable EXIF: {e}");
+                return vec![];
+            }
+        };
+        exif.fields()
+            .filter(|field| field.ifd_num == In::PRIMARY)
+            .filter(|field| !STRUCTURAL_TAGS.contains(&field.tag))
+            .filter(|field| !(strip_location && field.tag.context() == exif::Context::Gps))
+            .map(|field| {
+                if field.tag == Tag::Orientation && self.oriented {
+                    Field {
+                        tag: Tag::Orientation,
+                        ifd_num: In::PRIMARY,
+                        value: Value::Short(vec![1]),
+                    }
+                } else {
+                    field.clone()
+                }
+            })
+            .collect()
+    }
+
+    fn encode_exif(&self, strip_location: bool) -> Result<Option<Bytes>> {
+        let fields = self.exif_fields(strip_location);
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut buf = Cursor::new(vec![]);
+        writer.write(&mut buf, false)?;
+        Ok(Some(buf.into_inner().into()))
+    }
+
+    /// Write the metadata into a saved JPEG, PNG or WebP file
+    pub fn apply(&self, path: &Path, strip_location: bool) -> Result<()> {
+        let input = std::fs::read(path)?;
+        let mut image = DynImage::from_bytes(input.into())?
+            .context("Metadata can only be added to JPEG, PNG and WebP files")?;
+        image.set_exif(self.encode_exif(strip_location)?);
+        image.set_icc_profile(self.icc.clone());
+
+        match &mut image {
+            DynImage::Jpeg(jpeg) => {
+                if let Some(xmp) = &self.xmp {
+                    let contents = [XMP_JPEG_HEADER, &xmp[..]].concat();
+                    // After the JFIF and EXIF segments
+                    let position = jpeg
+                        .segments()
+                        .iter()
+                        .position(|s| !matches!(s.marker(), markers::APP0 | markers::APP1))
+                        .unwrap_or(jpeg.segments().len());
+                    jpeg.segments_mut().insert(
+                        position,
+                        JpegSegment::new_with_contents(markers::APP1, contents.into()),
+                    );
+                }
+            }
+            DynImage::Png(png) => {
+                let mut chunks = self.png_text.clone();
+                if let Some(xmp) = &self.xmp {
+                    // Uncompressed, no language and no translated keyword
+                    let contents = [XMP_PNG_KEYWORD, &[0u8; 4], &xmp[..]].concat();
+                    chunks.push(PngChunk::new(*b"iTXt", contents.into()));
+                }
+                let position = png
+                    .chunks()
+                    .iter()
+                    .position(|c| &c.kind() == b"IDAT")
+                    .unwrap_or(png.chunks().len());
+                png.chunks_mut().splice(position..position, chunks);
+            }
+            DynImage::WebP(webp) => {
+                // XMP needs the extended format, which the EXIF and ICC chunks already require
+                if let (Some(xmp), Some(vp8x)) = (&self.xmp, webp.chunk_by_id(*b"VP8X")) {
+                    let mut header = vp8x.content().data().cloned().unwrap_or_default().to_vec();
+                    if let Some(flags) = header.first_mut() {
+                        *flags |= 0b0000_0100;
+                    }
+                    webp.remove_chunks_by_id(*b"VP8X");
+                    webp.chunks_mut().insert(
+                        0,
+                        RiffChunk::new(*b"VP8X", RiffContent::Data(header.into())),
+                    );
+                    webp.chunks_mut()
+                        .push(RiffChunk::new(*b"XMP ", RiffContent::Data(xmp.clone())));
+                }
+            }
+        }
+
+        image.encoder().write_to(File::create(path)?)?;
+        Ok(())
+    }
+
+    /// Write the EXIF and GPS directories, returning the pointer tags for the image directory
+    fn write_tiff_directories<W: Write + Seek>(
+        &self,
+        encoder: &mut TiffEncoder<W>,
+        strip_location: bool,
+    ) -> Result<Vec<(u16, u64)>> {
+        let fields = self.exif_fields(strip_location);
+        let mut pointers = vec![];
+        for (context, pointer_tag) in [
+            (exif::Context::Exif, TIFF_TAG_EXIF_IFD),
+            (exif::Context::Gps, TIFF_TAG_GPS_IFD),
+        ] {
+            let mut fields = fields
+                .iter()
+                .filter(|f| f.tag.context() == context)
+                .peekable();
+            if fields.peek().is_none() {
+                continue;
+            }
+            let mut directory = encoder.extra_directory()?;
+            for field in fields {
+                write_exif_field(&mut directory, field)?;
+            }
+            pointers.push((pointer_tag, directory.finish_with_offsets()?.offset.into()));
+        }
+        Ok(pointers)
+    }
+}
+
+/// The text of an XMP iTXt chunk, if it is uncompressed
+fn parse_xmp_itxt(contents: &[u8]) -> Option<Bytes> {
+    let rest = contents.strip_prefix(XMP_PNG_KEYWORD)?;
+    let (&compressed, rest) = rest.split_first()?;
+    if compressed != 0 {
+        return None;
+    }
+    // Skip the compression method, language tag and translated keyword
+    let mut rest = &rest[1..];
+    for _ in 0..2 {
+        let end = rest.iter().position(|b| *b == 0)?;
+        rest = &rest[end + 1..];
+    }
+    Some(Bytes::copy_from_slice(rest))
+}
+
+fn write_exif_field<W: Write + Seek, K: TiffKind>(
+    directory: &mut DirectoryEncoder<'_, W, K>,
+    field: &Field,
+) -> Result<()> {
+    use tiff::encoder::{Rational, SRational};
+    let tag = tiff::tags::Tag::from_u16_exhaustive(field.tag.number());
+    match &field.value {
+        Value::Byte(v) | Value::Undefined(v, _) => directory.write_tag(tag, &v[..])?,
+        Value::Ascii(v) => {
+            let text = v
+                .first()
+                .map(|s| String::from_utf8_lossy(s))
+                .unwrap_or_default();
+            directory.write_tag(tag, &*text)?
+        }
+        Value::Short(v) => directory.write_tag(tag, &v[..])?,
+        Value::Long(v) => directory.write_tag(tag, &v[..])?,
+        Value::SLong(v) => directory.write_tag(tag, &v[..])?,
+        Value::Rational(v) => {
+            let v = v
+                .iter()
+                .map(|r| Rational {
+                    n: r.num,
+                    d: r.denom,
+                })
+                .collect::<Vec<_>>();
+            directory.write_tag(tag, &v[..])?
+        }
+        Value::SRational(v) => {
+            let v = v
+                .iter()
+                .map(|r| SRational {
+                    n: r.num,
+                    d: r.denom,
+                })
+                .collect::<Vec<_>>();
+            directory.write_tag(tag, &v[..])?
+        }
+        _ => debug!("Skipping EXIF field {} in TIFF", field.tag),
+    }
+    Ok(())
+}
+
+fn write_tiff_image<W: Write + Seek, C: ColorType>(
+    encoder: &mut TiffEncoder<W>,
+    image: &DynamicImage,
+    data: &[C::Inner],
+    metadata: &ImageMetadata,
+    strip_location: bool,
+) -> Result<()>
+where
+    [C::Inner]: TiffValue,
+{
+    let pointers = metadata.write_tiff_directories(encoder, strip_location)?;
+    let mut tiff_image = encoder.new_image::<C>(image.width(), image.height())?;
+    let directory = tiff_image.encoder();
+    for (tag, offset) in pointers {
+        directory.write_tag(tiff::tags::Tag::Unknown(tag), offset as u32)?;
+    }
+    for field in metadata.exif_fields(strip_location) {
+        if field.tag.context() == exif::Context::Tiff {
+            write_exif_field(directory, &field)?;
+        }
+    }
+    if let Some(icc) = &metadata.icc {
+        directory.write_tag(tiff::tags::Tag::Unknown(TIFF_TAG_ICC), &icc[..])?;
+    }
+    if let Some(xmp) = &metadata.xmp {
+        directory.write_tag(tiff::tags::Tag::Unknown(TIFF_TAG_XMP), &xmp[..])?;
+    }
+    tiff_image.write_data(data)?;
+    Ok(())
+}
+
+/// Encode a TIFF that keeps the bit depth of `image` and carries `metadata`
+pub fn write_tiff(
+    image: &DynamicImage,
+    path: &Path,
+    metadata: &ImageMetadata,
+    strip_location: bool,
+) -> Result<()> {
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+    let m = metadata;
+    let s = strip_location;
+    match image {
+        DynamicImage::ImageLuma8(i) => {
+            write_tiff_image::<_, colortype::Gray8>(&mut encoder, image, i, m, s)
+        }
+        DynamicImage::ImageRgb8(i) => {
+            write_tiff_image::<_, colortype::RGB8>(&mut encoder, image, i, m, s)
+        }
+        DynamicImage::ImageLuma16(i) => {
+            write_tiff_image::<_, colortype::Gray16>(&mut encoder, image, i, m, s)
+        }
+        DynamicImage::ImageRgb16(i) => {
+            write_tiff_image::<_, colortype::RGB16>(&mut encoder, image, i, m, s)
+        }
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba16(_) => {
+            let i = image.to_rgba16();
+            write_tiff_image::<_, colortype::RGBA16>(&mut encoder, image, &i, m, s)
+        }
+        DynamicImage::ImageRgb32F(i) => {
+            write_tiff_image::<_, colortype::RGB32Float>(&mut encoder, image, i, m, s)
+        }
+        DynamicImage::ImageRgba32F(i) => {
+            write_tiff_image::<_, colortype::RGBA32Float>(&mut encoder, image, i, m, s)
+        }
+        _ => {
+            let i = image.to_rgba8();
+            write_tiff_image::<_, colortype::RGBA8>(&mut encoder, image, &i, m, s)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    fn ascii(tag: Tag, text: &[u8]) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.to_vec()]),
+        }
+    }
+
+    /// EXIF with a camera, a rotation and a location, plus an ICC profile and XMP
+    fn sample_metadata(oriented: bool) -> ImageMetadata {
+        let orientation = Field {
+            tag: Tag::Orientation,
+            ifd_num: In::PRIMARY,
+            value: Value::Short(vec![6]),
+        };
+        let mut writer = Writer::new();
+        let make = ascii(Tag::Make, b"Oculante");
+        let latitude = ascii(Tag::GPSLatitudeRef, b"N");
+        for field in [&make, &orientation, &latitude] {
+            writer.push_field(field);
+        }
+        let mut exif = Cursor::new(vec![]);
+        writer.write(&mut exif, false).unwrap();
+        ImageMetadata {
+            exif: Some(exif.into_inner().into()),
+            icc: Some(Bytes::from_static(b"not really a profile")),
+            xmp: Some(Bytes::from_static(
+                b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>",
+            )),
+            png_text: vec![],
+            oriented,
+        }
+    }
+
+    fn exif_value(metadata: &ImageMetadata, tag: Tag) -> Option<Value> {
+        let exif = exif::Reader::new()
+            .read_raw(metadata.exif.as_ref()?.to_vec())
+            .unwrap();
+        exif.get_field(tag, In::PRIMARY).map(|f| f.value.clone())
+    }
+
+    #[test]
+    fn carries_metadata_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        for ext in ["jpg", "png", "webp", "tif"] {
+            for (oriented, strip_location) in [(false, false), (true, true)] {
+                let path = dir.path().join(format!("saved.{ext}"));
+                let metadata = sample_metadata(oriented);
+                if ext == "tif" {
+                    write_tiff(&image, &path, &metadata, strip_location).unwrap();
+                } else {
+                    image.save(&path).unwrap();
+                    metadata.apply(&path, strip_location).unwrap();
+                }
+
+                let saved = ImageMetadata::read(&path).unwrap();
+                assert_eq!(saved.icc, metadata.icc, "{ext}");
+                assert_eq!(saved.xmp, metadata.xmp, "{ext}");
+                assert!(exif_value(&saved, Tag::Make).is_some(), "{ext}");
+                let orientation = exif_value(&saved, Tag::Orientation).and_then(|v| v.get_uint(0));
+                assert_eq!(orientation, Some(if oriented { 1 } else { 6 }), "{ext}");
+                let location = exif_value(&saved, Tag::GPSLatitudeRef);
+                assert_eq!(location.is_some(), !strip_location, "{ext}");
+            }
+        }
+    }
+}
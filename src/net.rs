@@ -1,13 +1,168 @@
+//! Network listen mode.
+//!
+//! Clients can send a single encoded image and close the connection, or use the framed protocol
+//! to stream many images over one connection:
+//!
+//! - The connection starts with the magic bytes [`MAGIC`].
+//! - Each message is a `u32` header length, a JSON [`FrameHeader`], a `u32` payload length and
+//!   the encoded image. All integers are little endian.
+//! - The viewer answers every message with a status byte ([`STATUS_OK`] or [`STATUS_ERROR`]),
+//!   a `u32` length and a UTF-8 error text, which is empty on success.
+
+use crate::appstate::Message;
 use crate::utils::Frame;
-use anyhow::Result;
-use log::{error, info};
-use std::io::Read;
-use std::net::{Shutdown, TcpListener, TcpStream};
+use anyhow::{bail, Result};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
+
+/// Sent once at the start of a connection to select the framed protocol
+pub const MAGIC: &[u8; 4] = b"OCUL";
+pub const STATUS_OK: u8 = 0;
+pub const STATUS_ERROR: u8 = 1;
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_PAYLOAD_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Metadata sent along with each image
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameHeader {
+    /// Shown in place of the file name
+    pub name: String,
+    /// How long to show this frame before the next one is read, in ms
+    pub delay_ms: u16,
+    /// Continue the current stream instead of replacing the image
+    pub append: bool,
+}
+
+fn read_u32(stream: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_chunk(stream: &mut impl Read, max_size: usize) -> Result<Vec<u8>> {
+    let len = read_u32(stream)? as usize;
+    if len > max_size {
+        bail!("Message of {len} bytes exceeds the limit of {max_size} bytes");
+    }
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
 
-fn handle_client(mut stream: TcpStream, texture_sender: Sender<Frame>) -> Result<()> {
+fn write_chunk(stream: &mut impl Write, data: &[u8]) -> Result<()> {
+    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+    stream.write_all(data)?;
+    Ok(())
+}
+
+/// Send one image using the framed protocol and wait for the viewer to acknowledge it.
+/// The connection must have been started by writing [`MAGIC`].
+pub fn send_frame<S: Read + Write>(
+    stream: &mut S,
+    header: &FrameHeader,
+    image_data: &[u8],
+) -> Result<()> {
+    write_chunk(stream, &serde_json::to_vec(header)?)?;
+    write_chunk(stream, image_data)?;
+    stream.flush()?;
+
+    let mut status = [0u8; 1];
+    stream.read_exact(&mut status)?;
+    let text = read_chunk(stream, MAX_HEADER_SIZE)?;
+    if status[0] != STATUS_OK {
+        bail!("{}", String::from_utf8_lossy(&text));
+    }
+    Ok(())
+}
+
+fn process_message(
+    header: &[u8],
+    payload: &[u8],
+    texture_sender: &Sender<Frame>,
+    message_sender: &Sender<Message>,
+) -> Result<FrameHeader> {
+    let header: FrameHeader = serde_json::from_slice(header)?;
+    let img = image::load_from_memory(payload)?;
+    if !header.name.is_empty() {
+        _ = message_sender.send(Message::SourceName(header.name.clone()));
+    }
+    let frame = if header.append {
+        Frame::new_animation(img, header.delay_ms)
+    } else {
+        Frame::new_still(img)
+    };
+    _ = texture_sender.send(frame);
+    Ok(header)
+}
+
+fn handle_framed_client<S: Read + Write>(
+    stream: &mut S,
+    texture_sender: Sender<Frame>,
+    message_sender: Sender<Message>,
+) -> Result<()> {
+    loop {
+        let header = match read_chunk(stream, MAX_HEADER_SIZE) {
+            Ok(header) => header,
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .map(|e| e.kind() == ErrorKind::UnexpectedEof)
+                    .unwrap_or_default() =>
+            {
+                debug!("Client closed the stream");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let payload = read_chunk(stream, MAX_PAYLOAD_SIZE)?;
+
+        match process_message(&header, &payload, &texture_sender, &message_sender) {
+            Ok(header) => {
+                stream.write_all(&[STATUS_OK])?;
+                write_chunk(stream, &[])?;
+                if header.delay_ms > 0 {
+                    thread::sleep(Duration::from_millis(header.delay_ms as u64));
+                }
+            }
+            Err(e) => {
+                error!("Could not decode frame: {e}");
+                stream.write_all(&[STATUS_ERROR])?;
+                write_chunk(stream, e.to_string().as_bytes())?;
+            }
+        }
+    }
+}
+
+fn handle_client<S: Read + Write>(
+    mut stream: S,
+    texture_sender: Sender<Frame>,
+    message_sender: Sender<Message>,
+) -> Result<()> {
     let mut imgbuf: Vec<u8> = Vec::with_capacity(100000);
+
+    // Peek at the start of the stream to tell framed clients from raw image uploads
+    let mut magic = [0u8; 4];
+    if let Err(e) = stream.read_exact(&mut magic) {
+        error!("An error {e} occurred, terminating connection");
+        return Ok(());
+    }
+    if &magic == MAGIC {
+        debug!("Framed client connected");
+        return handle_framed_client(&mut stream, texture_sender, message_sender);
+    }
+    imgbuf.extend_from_slice(&magic);
+
     match stream.read_to_end(&mut imgbuf) {
         Ok(_) => match image::load_from_memory(&imgbuf) {
             Ok(f) => {
@@ -15,43 +170,213 @@ fn handle_client(mut stream: TcpStream, texture_sender: Sender<Frame>) -> Result
                 std::thread::sleep(std::time::Duration::from_millis(30));
             }
             Err(e) => {
-                error!("{e}, terminating connection with {}", stream.peer_addr()?);
-                stream.shutdown(Shutdown::Both)?;
+                error!("{e}, terminating connection");
             }
         },
         Err(e) => {
-            error!(
-                "An error {e} occurred, terminating connection with {}",
-                stream.peer_addr()?
-            );
-            stream.shutdown(Shutdown::Both)?;
+            error!("An error {e} occurred, terminating connection");
         }
     };
     Ok(())
 }
 
-pub fn recv(port: i32, texture_sender: Sender<Frame>) {
-    thread::spawn(move || {
-        // FIXME remove unwrap
-        let listener = TcpListener::bind(format!("0.0.0.0:{port}")).unwrap();
-        // accept connections and process them, spawning a new thread for each one
-        info!("Server listening on port {port}");
-
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let t_s = texture_sender.clone();
-                    thread::spawn(move || {
-                        // connection succeeded
-                        _ = handle_client(stream, t_s)
-                    });
-                }
-                Err(e) => {
-                    info!("Filed connection: {}", e);
-                }
+/// Where the viewer listens for incoming images
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    Tcp {
+        host: String,
+        port: u16,
+    },
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            // IPv6 addresses need brackets to be told apart from the port
+            Self::Tcp { host, port } if host.contains(':') => write!(f, "[{host}]:{port}"),
+            Self::Tcp { host, port } => write!(f, "{host}:{port}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A connected client, either over TCP or a Unix domain socket
+pub trait Stream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> Stream for S {}
+
+fn serve<S: Stream + 'static>(
+    incoming: impl Iterator<Item = std::io::Result<S>>,
+    handler: Arc<dyn Fn(Box<dyn Stream>) -> Result<()> + Send + Sync>,
+) {
+    // accept connections and process them, spawning a new thread for each one
+    for stream in incoming {
+        match stream {
+            Ok(stream) => {
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    // connection succeeded
+                    if let Err(e) = handler(Box::new(stream)) {
+                        error!("{e}");
+                    }
+                });
+            }
+            Err(e) => {
+                info!("Filed connection: {}", e);
             }
         }
-        // close the socket server
-        drop(listener);
+    }
+}
+
+/// Remove a socket file left behind by a previous run, but never one that is still in use.
+/// Other kinds of files are left alone and reported as an error.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    if UnixStream::connect(path).is_err() {
+        debug!("Removing stale socket {}", path.display());
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Connect to a viewer listening on `address`
+pub fn connect(address: &ListenAddress) -> Result<Box<dyn Stream>> {
+    Ok(match address {
+        ListenAddress::Tcp { host, port } => Box::new(TcpStream::connect((host.as_str(), *port))?),
+        #[cfg(unix)]
+        ListenAddress::Unix(path) => Box::new(UnixStream::connect(path)?),
+    })
+}
+
+/// Accept connections on `address` in the background and pass each one to `handler` on its own
+/// thread. `bound` runs once the address is bound, before the first connection is accepted. If
+/// either fails, the error is shown to the user instead.
+pub fn listen(
+    address: ListenAddress,
+    message_sender: Sender<Message>,
+    bound: impl FnOnce() -> Result<()> + Send + 'static,
+    handler: impl Fn(Box<dyn Stream>) -> Result<()> + Send + Sync + 'static,
+) {
+    let handler: Arc<dyn Fn(Box<dyn Stream>) -> Result<()> + Send + Sync> = Arc::new(handler);
+    thread::spawn(move || {
+        let result = match &address {
+            ListenAddress::Tcp { host, port } => TcpListener::bind((host.as_str(), *port))
+                .map_err(anyhow::Error::from)
+                .and_then(|listener| {
+                    bound()?;
+                    info!("Server listening on {address}");
+                    serve(listener.incoming(), handler);
+                    Ok(())
+                }),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => remove_stale_socket(path)
+                .and_then(|_| UnixListener::bind(path))
+                .map_err(anyhow::Error::from)
+                .and_then(|listener| {
+                    bound()?;
+                    info!("Server listening on {address}");
+                    serve(listener.incoming(), handler);
+                    Ok(())
+                }),
+        };
+        if let Err(e) = result {
+            error!("Could not listen on {address}: {e:#}");
+            _ = message_sender.send(Message::Error(format!(
+                "Could not listen on {address}: {e:#}"
+            )));
+        }
     });
 }
+
+/// Receive images sent to `address` and display them
+pub fn recv(
+    address: ListenAddress,
+    texture_sender: Sender<Frame>,
+    message_sender: Sender<Message>,
+) {
+    let m_s = message_sender.clone();
+    listen(
+        address,
+        message_sender,
+        || Ok(()),
+        move |stream| handle_client(stream, texture_sender.clone(), m_s.clone()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn framed_protocol_acks_and_reports_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (texture_sender, texture_receiver) = mpsc::channel();
+        let (message_sender, message_receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_client(stream, texture_sender, message_sender).unwrap();
+        });
+
+        let mut png = vec![];
+        image::DynamicImage::new_rgba8(4, 4)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(MAGIC).unwrap();
+        let header = FrameHeader {
+            name: "render".into(),
+            delay_ms: 0,
+            append: true,
+        };
+        send_frame(&mut stream, &header, &png).unwrap();
+        assert!(send_frame(&mut stream, &header, b"not an image").is_err());
+        send_frame(&mut stream, &FrameHeader::default(), &png).unwrap();
+
+        assert!(matches!(
+            texture_receiver.recv().unwrap(),
+            Frame::Animation(_, 0)
+        ));
+        assert!(matches!(texture_receiver.recv().unwrap(), Frame::Still(_)));
+        assert!(matches!(
+            message_receiver.recv().unwrap(),
+            Message::SourceName(name) if name == "render"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_only_stale_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, b"keep me").unwrap();
+        assert!(remove_stale_socket(&file).is_err());
+        assert_eq!(std::fs::read(&file).unwrap(), b"keep me");
+
+        let socket = dir.path().join("oculante.sock");
+        drop(UnixListener::bind(&socket).unwrap());
+        remove_stale_socket(&socket).unwrap();
+        assert!(!socket.exists());
+
+        let _listener = UnixListener::bind(&socket).unwrap();
+        remove_stale_socket(&socket).unwrap();
+        assert!(socket.exists());
+    }
+}
@@ -0,0 +1,174 @@
+//! Pages of multi-page TIFF files and frames of multi-frame DICOM images.
+//!
+//! They are navigated on their own, independent of the images of a folder in the
+//! [`crate::scrubber::Scrubber`].
+
+use crate::image_loader::{load_dicom_frame, load_tiff_page, rotate_dynimage};
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Whether `path` is a format that can have more than one page
+pub fn is_paged(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "tif" | "tiff" | "dcm" | "ima")
+}
+
+/// The number of pages or frames in a file
+pub fn page_count(path: &Path) -> Result<usize> {
+    match extension(path).as_str() {
+        "tif" | "tiff" => {
+            let mut decoder = tiff::decoder::Decoder::new(File::open(path)?)?;
+            let mut count = 1;
+            while decoder.more_images() {
+                decoder.next_image()?;
+                count += 1;
+            }
+            Ok(count)
+        }
+        "dcm" | "ima" => {
+            let obj = dicom_object::open_file(path)?;
+            let frames = obj
+                .element_by_name("NumberOfFrames")
+                .ok()
+                .and_then(|e| e.to_int::<u32>().ok())
+                .unwrap_or(1);
+            Ok(frames.max(1) as usize)
+        }
+        _ => Ok(1),
+    }
+}
+
+/// Decode a page, counting from zero, oriented like the first page
+pub fn load_page(path: &Path, page: usize) -> Result<DynamicImage> {
+    Ok(load_oriented_page(path, page)?.0)
+}
+
+/// Like [`load_page`], also telling if the EXIF orientation was applied to the pixels
+pub fn load_oriented_page(path: &Path, page: usize) -> Result<(DynamicImage, bool)> {
+    let mut image = match extension(path).as_str() {
+        "tif" | "tiff" => load_tiff_page(path, page)?,
+        "dcm" | "ima" => return Ok((load_dicom_frame(path, page as u32)?, false)),
+        _ if page == 0 => image::open(path)?,
+        _ => bail!("{} has only one page", path.display()),
+    };
+    let oriented = rotate_dynimage(&mut image, path).is_ok();
+    Ok((image, oriented))
+}
+
+/// The pages of the current image and the one on display
+#[derive(Debug, Default)]
+pub struct PageState {
+    /// The file the pages belong to
+    pub path: Option<PathBuf>,
+    pub count: usize,
+    pub current: usize,
+    pub error: Option<String>,
+    pending_count: Option<Receiver<usize>>,
+    pending_page: Option<Receiver<Result<DynamicImage>>>,
+}
+
+impl PageState {
+    /// Count the pages of a newly loaded image in the background
+    pub fn load(&mut self, path: &Path) {
+        *self = Self {
+            path: Some(path.to_path_buf()),
+            count: 1,
+            ..Default::default()
+        };
+        if !is_paged(path) {
+            return;
+        }
+        let (sender, receiver) = channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            _ = sender.send(page_count(&path).unwrap_or(1));
+        });
+        self.pending_count = Some(receiver);
+    }
+
+    /// Whether there is more than one page to navigate
+    pub fn is_paged(&self) -> bool {
+        self.count > 1
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending_count.is_some() || self.pending_page.is_some()
+    }
+
+    /// Decode `page` in the background. It is returned by [`PageState::poll`].
+    pub fn go_to(&mut self, page: usize) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        if page >= self.count || page == self.current {
+            return;
+        }
+        self.current = page;
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            _ = sender.send(load_page(&path, page));
+        });
+        self.error = None;
+        self.pending_page = Some(receiver);
+    }
+
+    pub fn next(&mut self) {
+        if self.is_paged() {
+            self.go_to((self.current + 1) % self.count);
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if self.is_paged() {
+            self.go_to((self.current + self.count - 1) % self.count);
+        }
+    }
+
+    /// Pick up the page count and decoded pages. Returns the page to display, if one arrived.
+    pub fn poll(&mut self) -> Option<DynamicImage> {
+        if let Some(count) = self.pending_count.as_ref().and_then(|r| r.try_recv().ok()) {
+            self.count = count;
+            self.pending_count = None;
+        }
+        let result = self.pending_page.as_ref()?.try_recv().ok()?;
+        self.pending_page = None;
+        match result {
+            Ok(image) => Some(image),
+            Err(e) => {
+                self.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tiff_pages() {
+        let path = std::env::temp_dir().join("oculante_pages.tif");
+        let file = std::io::BufWriter::new(File::create(&path).unwrap());
+        let mut encoder = tiff::encoder::TiffEncoder::new(file).unwrap();
+        for value in [10u8, 200] {
+            encoder
+                .write_image::<tiff::encoder::colortype::Gray8>(4, 2, &[value; 8])
+                .unwrap();
+        }
+        drop(encoder);
+
+        assert_eq!(page_count(&path).unwrap(), 2);
+        let page = load_page(&path, 1).unwrap();
+        assert_eq!(page.to_luma8().get_pixel(0, 0).0, [200]);
+    }
+}
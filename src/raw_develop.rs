@@ -0,0 +1,377 @@
+//! Development of camera RAW files.
+//!
+//! quickraw demosaics the sensor data and converts it to linear sRGB, white balanced
+//! as shot. These linear values are kept, so white balance, exposure and highlight
+//! recovery can be changed without decoding the file again. The result has 16 bits
+//! per channel.
+
+use anyhow::{bail, Result};
+use image::{DynamicImage, ImageBuffer, Rgb};
+use quickraw::{data, DemosaicingMethod, Export, Input, Output, OutputType};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::OnceLock;
+use std::thread;
+use strum::{Display, EnumIter};
+
+/// Whether `path` is a camera RAW file
+pub fn is_raw(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    matches!(
+        extension.as_str(),
+        "nef"
+            | "cr2"
+            | "dng"
+            | "mos"
+            | "erf"
+            | "raf"
+            | "arw"
+            | "3fr"
+            | "ari"
+            | "srf"
+            | "sr2"
+            | "braw"
+            | "r3d"
+            | "nrw"
+            | "raw"
+    )
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, EnumIter, Display, Serialize, Deserialize)]
+pub enum WhiteBalance {
+    /// The white balance the camera recorded
+    #[default]
+    #[strum(to_string = "As shot")]
+    AsShot,
+    /// Make the average color neutral
+    Auto,
+    /// A temperature and tint
+    Custom,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, EnumIter, Display, Serialize, Deserialize)]
+pub enum Demosaic {
+    /// Every 2x2 block of the sensor becomes one pixel
+    #[strum(to_string = "Fast (half size)")]
+    Fast,
+    /// Interpolate the missing colors of every pixel
+    #[default]
+    #[strum(to_string = "Full size")]
+    Full,
+}
+
+impl Demosaic {
+    fn method(&self) -> DemosaicingMethod {
+        match self {
+            Demosaic::Fast => DemosaicingMethod::SuperPixel,
+            Demosaic::Full => DemosaicingMethod::Linear,
+        }
+    }
+}
+
+/// How RAW files are developed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RawSettings {
+    pub white_balance: WhiteBalance,
+    /// The color temperature of the light in Kelvin, for a custom white balance.
+    /// It is relative to the white balance of the camera, which counts as 6500 K.
+    pub temperature: f32,
+    /// From green (-1.0) to magenta (1.0), for a custom white balance
+    pub tint: f32,
+    /// Exposure compensation in stops
+    pub exposure: f32,
+    /// 0.0 clips highlights, 1.0 rolls them off softly and desaturates them
+    pub highlight_recovery: f32,
+    pub demosaic: Demosaic,
+}
+
+impl Default for RawSettings {
+    fn default() -> Self {
+        Self {
+            white_balance: Default::default(),
+            temperature: NEUTRAL_TEMPERATURE,
+            tint: 0.,
+            exposure: 0.,
+            highlight_recovery: 0.,
+            demosaic: Default::default(),
+        }
+    }
+}
+
+/// The temperature at which a custom white balance equals the one of the camera
+pub const NEUTRAL_TEMPERATURE: f32 = 6500.;
+
+/// The linear sRGB color of a black body at `temperature` Kelvin, with a green of 1.0
+fn black_body(temperature: f32) -> [f32; 3] {
+    // Approximation of the Planckian locus by Kim et al.
+    let t = temperature.clamp(1667., 25000.) as f64;
+    let x = if t < 4000. {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t < 2222. {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t < 4000. {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+    let (x, y, z) = (x / y, 1., (1. - x - y) / y);
+    let r = 3.2404542 * x - 1.5371385 * y - 0.4985314 * z;
+    let g = -0.9692660 * x + 1.8760108 * y + 0.0415560 * z;
+    let b = 0.0556434 * x - 0.2040259 * y + 1.0572252 * z;
+    [(r / g) as f32, 1., (b / g) as f32]
+}
+
+/// Channel gains that neutralize light of `temperature`, relative to [`NEUTRAL_TEMPERATURE`]
+pub fn temperature_gains(temperature: f32, tint: f32) -> [f32; 3] {
+    let neutral = black_body(NEUTRAL_TEMPERATURE);
+    let light = black_body(temperature);
+    [
+        neutral[0] / light[0],
+        1. - 0.5 * tint.clamp(-1., 1.),
+        neutral[2] / light[2],
+    ]
+}
+
+/// Roll off values approaching 1.0 instead of clipping them, and move them towards
+/// white the more they are compressed. This hides the magenta cast of clipped sensor
+/// channels.
+fn recover_highlights(rgb: [f32; 3], amount: f32) -> [f32; 3] {
+    let knee = 1. - 0.5 * amount;
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    if amount <= 0. || max <= knee {
+        return rgb;
+    }
+    let compressed = knee + (1. - knee) * (1. - (-(max - knee) / (1. - knee)).exp());
+    let scale = compressed / max;
+    let desaturation = ((max - knee) / max).clamp(0., 1.) * amount;
+    rgb.map(|v| {
+        let v = v * scale;
+        v + (compressed - v) * desaturation
+    })
+}
+
+/// The sRGB transfer function for all 16 bit linear values
+fn srgb_lut() -> &'static [u16] {
+    static LUT: OnceLock<Vec<u16>> = OnceLock::new();
+    LUT.get_or_init(|| {
+        (0..=u16::MAX)
+            .map(|v| {
+                let v = v as f32 / u16::MAX as f32;
+                let encoded = if v <= 0.0031308 {
+                    v * 12.92
+                } else {
+                    1.055 * v.powf(1. / 2.4) - 0.055
+                };
+                (encoded * u16::MAX as f32 + 0.5) as u16
+            })
+            .collect()
+    })
+}
+
+/// The demosaiced pixels of a RAW file in linear sRGB, white balanced as shot
+pub struct RawData {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<u16>,
+}
+
+impl RawData {
+    /// Decode and demosaic a RAW file, rotated as the camera was held
+    pub fn read(path: &Path, demosaic: Demosaic) -> Result<Self> {
+        let export = Export::new(
+            Input::ByFile(&path.to_string_lossy()),
+            Output::new(
+                demosaic.method(),
+                data::XYZ2SRGB,
+                data::GAMMA_LINEAR,
+                OutputType::Raw16,
+                true,
+                true,
+            ),
+        )?;
+        let (pixels, width, height) = export.export_16bit_image();
+        if width * height * 3 != pixels.len() {
+            bail!(
+                "The RAW decoder returned {} values for {width}x{height} pixels",
+                pixels.len()
+            );
+        }
+        Ok(Self {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        })
+    }
+
+    /// Gains that make the average of a sample of unclipped pixels gray
+    fn gray_world(&self) -> [f32; 3] {
+        let sums = self
+            .pixels
+            .par_chunks(3 * 16)
+            .filter(|p| p.len() >= 3 && p[..3].iter().all(|v| *v < u16::MAX - 256))
+            .map(|p| [p[0] as f64, p[1] as f64, p[2] as f64])
+            .reduce(|| [0.; 3], |a, b| [a[0] + b[0], a[1] + b[1], a[2] + b[2]]);
+        if sums.iter().any(|s| *s <= 0.) {
+            return [1.; 3];
+        }
+        [(sums[1] / sums[0]) as f32, 1., (sums[1] / sums[2]) as f32]
+    }
+
+    /// Channel gains of the white balance
+    pub fn white_balance_gains(&self, settings: &RawSettings) -> [f32; 3] {
+        match settings.white_balance {
+            WhiteBalance::AsShot => [1.; 3],
+            WhiteBalance::Auto => self.gray_world(),
+            WhiteBalance::Custom => temperature_gains(settings.temperature, settings.tint),
+        }
+    }
+
+    /// Apply white balance, exposure and highlight recovery, and encode to sRGB
+    pub fn develop(&self, settings: &RawSettings) -> Option<DynamicImage> {
+        let exposure = 2f32.powf(settings.exposure) / u16::MAX as f32;
+        let gains = self.white_balance_gains(settings).map(|g| g * exposure);
+        let recovery = settings.highlight_recovery.clamp(0., 1.);
+        let lut = srgb_lut();
+        let pixels = self
+            .pixels
+            .par_chunks_exact(3)
+            .flat_map_iter(|p| {
+                let rgb = [
+                    p[0] as f32 * gains[0],
+                    p[1] as f32 * gains[1],
+                    p[2] as f32 * gains[2],
+                ];
+                recover_highlights(rgb, recovery)
+                    .map(|v| lut[(v.clamp(0., 1.) * u16::MAX as f32 + 0.5) as usize])
+            })
+            .collect::<Vec<_>>();
+        ImageBuffer::<Rgb<u16>, _>::from_raw(self.width, self.height, pixels)
+            .map(DynamicImage::ImageRgb16)
+    }
+}
+
+/// Decode and develop a RAW file
+pub fn develop_file(path: &Path, settings: &RawSettings) -> Result<DynamicImage> {
+    match RawData::read(path, settings.demosaic)?.develop(settings) {
+        Some(image) => Ok(image),
+        None => bail!("Can't develop {}", path.display()),
+    }
+}
+
+/// The sensor data of the current RAW file, so it can be developed again
+#[derive(Default)]
+pub struct RawDevelopState {
+    /// The file and demosaicing the data belongs to
+    pub source: Option<(PathBuf, Demosaic)>,
+    pub data: Option<RawData>,
+    pub error: Option<String>,
+    /// The image on display does not match the settings anymore
+    outdated: bool,
+    pending: Option<Receiver<Result<RawData>>>,
+}
+
+impl RawDevelopState {
+    /// Decode `path` in the background, unless it is known already
+    pub fn load(&mut self, path: &Path, demosaic: Demosaic) {
+        let source = (path.to_path_buf(), demosaic);
+        if self.source.as_ref() == Some(&source) {
+            return;
+        }
+        // Another demosaicing of the same file replaces the image on display
+        let outdated = self.outdated || self.source.as_ref().is_some_and(|(p, _)| p == path);
+        self.clear();
+        self.source = Some(source);
+        self.outdated = outdated;
+        let (sender, receiver) = channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            _ = sender.send(RawData::read(&path, demosaic));
+        });
+        self.pending = Some(receiver);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Pick up the result of [`RawDevelopState::load`]
+    pub fn poll(&mut self) {
+        let Some(result) = self.pending.as_ref().and_then(|r| r.try_recv().ok()) else {
+            return;
+        };
+        self.pending = None;
+        match result {
+            Ok(data) => self.data = Some(data),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Develop the image again once the sensor data is available
+    pub fn request_develop(&mut self) {
+        self.outdated = true;
+    }
+
+    /// The developed image, if the settings changed since the last call
+    pub fn take_image(&mut self, settings: &RawSettings) -> Option<DynamicImage> {
+        if !self.outdated || self.data.is_none() {
+            return None;
+        }
+        self.outdated = false;
+        self.data.as_ref()?.develop(settings)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balances_temperature() {
+        let neutral = temperature_gains(NEUTRAL_TEMPERATURE, 0.);
+        assert!(neutral.iter().all(|g| (g - 1.).abs() < 1e-4));
+        // Warm light needs less red and more blue
+        let [r, g, b] = temperature_gains(3000., 0.);
+        assert!(r < 1. && b > 1. && g == 1.);
+        assert!(temperature_gains(NEUTRAL_TEMPERATURE, 1.)[1] < 1.);
+    }
+
+    #[test]
+    fn develops_linear_data() {
+        let data = RawData {
+            width: 2,
+            height: 1,
+            pixels: vec![0, 0, 0, 16384, 16384, 16384],
+        };
+        let settings = RawSettings {
+            exposure: 1.,
+            ..Default::default()
+        };
+        let image = data.develop(&settings).unwrap().into_rgb16();
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0]);
+        // A quarter, one stop brighter, is half: 0.5 in sRGB is about 0.735
+        let value = image.get_pixel(1, 0).0[0];
+        assert!((value as f32 / u16::MAX as f32 - 0.735).abs() < 0.01);
+    }
+
+    #[test]
+    fn recovers_highlights() {
+        assert_eq!(recover_highlights([0.2, 0.3, 0.4], 1.), [0.2, 0.3, 0.4]);
+        let [r, g, b] = recover_highlights([4., 2., 4.], 1.);
+        assert!(r < 1. && b < 1. && g < r);
+        // Clipped pixels get closer to neutral
+        assert!(r - g < 0.5);
+    }
+}
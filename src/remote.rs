@@ -0,0 +1,327 @@
+//! Remote control of a running viewer.
+//!
+//! In single-instance mode the viewer listens on a local socket. A second launch forwards its
+//! path there instead of opening another window, and scripts can drive the viewer with named
+//! [`InputEvent`]s, for example `oculante remote NextImage ZoomIn`.
+//!
+//! A connection starts with a line holding the session token, which the viewer writes to a file
+//! only the user can read. Without it, other users and local programs could drive the viewer over
+//! TCP, the only transport on Windows. Then each request is one line of JSON holding a
+//! [`RemoteCommand`]. The viewer answers the token and every request with `ok` or the error that
+//! occurred.
+
+use crate::appstate::Message;
+use crate::archive;
+use crate::net::{self, ListenAddress};
+use crate::scrubber::find_first_image_in_directory;
+use crate::settings::get_config_dir;
+use crate::shortcuts::InputEvent;
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use strum::IntoEnumIterator;
+
+/// Used where Unix domain sockets are not available
+#[cfg(not(unix))]
+const REMOTE_PORT: u16 = 51357;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RemoteCommand {
+    /// Load an image, or the first image of a folder
+    Open(PathBuf),
+    /// Act as if the shortcut for this event was pressed
+    Input(InputEvent),
+}
+
+/// The local socket a single instance listens on
+pub fn address() -> ListenAddress {
+    #[cfg(unix)]
+    {
+        // The runtime dir is private to the user, the temp dir is not always
+        let dir = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+        let user = std::env::var("USER").unwrap_or_default();
+        ListenAddress::Unix(dir.join(format!("oculante-{user}.sock")))
+    }
+    #[cfg(not(unix))]
+    ListenAddress::Tcp {
+        host: "127.0.0.1".into(),
+        port: REMOTE_PORT,
+    }
+}
+
+/// Look up an [`InputEvent`] by its name, such as `NextImage`
+pub fn parse_input_event(name: &str) -> Result<InputEvent> {
+    match InputEvent::iter().find(|event| format!("{event:?}").eq_ignore_ascii_case(name)) {
+        Some(event) => Ok(event),
+        None => bail!(
+            "Unknown command '{name}'. Available are: {}",
+            InputEvent::iter()
+                .map(|event| format!("{event:?}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Where the running instance keeps its session token
+fn token_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("remote_token"))
+}
+
+/// Store the session token where only this user can read it
+fn store_token(path: &Path, token: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(token.as_bytes())?;
+    Ok(())
+}
+
+/// Send commands to a running instance. Fails if no instance is listening.
+pub fn send(commands: &[RemoteCommand]) -> Result<()> {
+    let token = std::fs::read_to_string(token_path()?)
+        .context("No running instance found, its session token is missing")?;
+    let address = address();
+    let stream = net::connect(&address)
+        .with_context(|| format!("No running instance found at {address}"))?;
+    send_to(stream, token.trim(), commands)
+}
+
+/// Write one line and wait for the reply to it
+fn request(reader: &mut BufReader<impl Read + Write>, line: &str) -> Result<()> {
+    let stream = reader.get_mut();
+    writeln!(stream, "{line}")?;
+    stream.flush()?;
+
+    let mut reply = String::new();
+    reader.read_line(&mut reply)?;
+    match reply.trim_end() {
+        "ok" => Ok(()),
+        "" => bail!("The running instance closed the connection"),
+        error => bail!("{error}"),
+    }
+}
+
+fn send_to(stream: impl Read + Write, token: &str, commands: &[RemoteCommand]) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    request(&mut reader, token)?;
+    for command in commands {
+        request(&mut reader, &serde_json::to_string(command)?)?;
+    }
+    Ok(())
+}
+
+fn open(path: &Path, load_sender: &Sender<PathBuf>) -> Result<()> {
+    let path = if path.is_dir() || archive::is_archive(path) {
+        find_first_image_in_directory(path)?
+    } else {
+        path.to_path_buf()
+    };
+    load_sender.send(path)?;
+    Ok(())
+}
+
+fn handle_client(
+    stream: impl Read + Write,
+    token: &str,
+    load_sender: &Sender<PathBuf>,
+    message_sender: &Sender<Message>,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != token {
+        writeln!(reader.get_mut(), "The session token is not valid")?;
+        bail!("Refused a remote client with an invalid session token");
+    }
+    writeln!(reader.get_mut(), "ok")?;
+    line.clear();
+
+    while reader.read_line(&mut line)? > 0 {
+        let result = serde_json::from_str::<RemoteCommand>(&line)
+            .map_err(anyhow::Error::from)
+            .and_then(|command| {
+                debug!("Remote command {command:?}");
+                match command {
+                    RemoteCommand::Open(path) => open(&path, load_sender),
+                    RemoteCommand::Input(event) => {
+                        message_sender.send(Message::Command(event))?;
+                        Ok(())
+                    }
+                }
+            });
+        let reply = match result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string().replace('\n', " "),
+        };
+        writeln!(reader.get_mut(), "{reply}")?;
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Accept remote commands on the local socket
+pub fn listen(load_sender: Sender<PathBuf>, message_sender: Sender<Message>) {
+    match token_path() {
+        Ok(path) => listen_on(address(), path, load_sender, message_sender),
+        Err(e) => {
+            _ = message_sender.send(Message::Error(format!(
+                "Could not create a remote session token: {e}"
+            )));
+        }
+    }
+}
+
+/// Accept remote commands on `address`. The token is only stored once the address is bound,
+/// so an instance that can't listen leaves the token of the running one intact.
+fn listen_on(
+    address: ListenAddress,
+    token_path: PathBuf,
+    load_sender: Sender<PathBuf>,
+    message_sender: Sender<Message>,
+) {
+    let token = format!("{:032x}", rand::random::<u128>());
+    let stored = token.clone();
+    let m_s = message_sender.clone();
+    net::listen(
+        address,
+        message_sender,
+        move || store_token(&token_path, &stored).context("Could not store the session token"),
+        move |stream| handle_client(stream, &token, &load_sender, &m_s),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Serve one client on a local TCP port with the token `secret`
+    fn serve_once() -> (TcpStream, Receiver<PathBuf>, Receiver<Message>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (load_sender, load_receiver) = mpsc::channel();
+        let (message_sender, message_receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            _ = handle_client(stream, "secret", &load_sender, &message_sender);
+        });
+        (
+            TcpStream::connect(addr).unwrap(),
+            load_receiver,
+            message_receiver,
+        )
+    }
+
+    #[test]
+    fn runs_commands_with_the_session_token() {
+        let (stream, load_receiver, message_receiver) = serve_once();
+        let image = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/test.dds");
+        send_to(
+            stream,
+            "secret",
+            &[
+                RemoteCommand::Open(image.clone()),
+                RemoteCommand::Input(InputEvent::NextImage),
+            ],
+        )
+        .unwrap();
+        assert_eq!(load_receiver.recv().unwrap(), image);
+        assert!(matches!(
+            message_receiver.recv().unwrap(),
+            Message::Command(InputEvent::NextImage)
+        ));
+    }
+
+    #[test]
+    fn refuses_a_wrong_session_token() {
+        let (stream, load_receiver, _message_receiver) = serve_once();
+        let image = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/test.dds");
+        let error = send_to(stream, "guess", &[RemoteCommand::Open(image)]).unwrap_err();
+        assert!(error.to_string().contains("not valid"), "{error}");
+        assert!(load_receiver.recv().is_err());
+    }
+
+    #[test]
+    fn reports_command_errors() {
+        let (stream, _load_receiver, _message_receiver) = serve_once();
+        let zoom = RemoteCommand::Input(InputEvent::ZoomIn);
+        let mut reader = BufReader::new(stream);
+        request(&mut reader, "secret").unwrap();
+        assert!(request(&mut reader, "not json").is_err());
+        // The connection stays usable after an error
+        request(&mut reader, &serde_json::to_string(&zoom).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_token_of_the_running_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_path = dir.path().join("remote_token");
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = ListenAddress::Tcp {
+            host: "127.0.0.1".into(),
+            port,
+        };
+        let (load_sender, load_receiver) = mpsc::channel();
+        let (message_sender, message_receiver) = mpsc::channel();
+
+        listen_on(
+            address.clone(),
+            token_path.clone(),
+            load_sender.clone(),
+            message_sender.clone(),
+        );
+        let token = (0..400)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(25));
+                std::fs::read_to_string(&token_path).ok()
+            })
+            .expect("The first instance should store its token");
+
+        // The address is taken, so the second instance must not replace the token
+        listen_on(
+            address.clone(),
+            token_path.clone(),
+            load_sender,
+            message_sender,
+        );
+        assert!(matches!(
+            message_receiver
+                .recv_timeout(Duration::from_secs(10))
+                .unwrap(),
+            Message::Error(_)
+        ));
+        assert_eq!(std::fs::read_to_string(&token_path).unwrap(), token);
+
+        let image = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/test.dds");
+        let stream = net::connect(&address).unwrap();
+        send_to(stream, &token, &[RemoteCommand::Open(image.clone())]).unwrap();
+        assert_eq!(load_receiver.recv().unwrap(), image);
+    }
+
+    #[test]
+    fn parses_input_events_by_name() {
+        assert_eq!(
+            parse_input_event("NextImage").unwrap(),
+            InputEvent::NextImage
+        );
+        assert_eq!(parse_input_event("zoomin").unwrap(), InputEvent::ZoomIn);
+        let error = parse_input_event("Explode").unwrap_err().to_string();
+        assert!(error.contains("Unknown command 'Explode'"), "{error}");
+        assert!(error.contains("NextImage"), "{error}");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::archive;
 use crate::utils::is_ext_compatible;
 use anyhow::{bail, Context, Result};
 use log::{debug, warn};
@@ -99,15 +100,27 @@ impl Scrubber {
     pub fn has_folder_changed(&self, path_to_check: &Path) -> bool {
         self.entries
             .first()
-            .map(|e| e.parent() != path_to_check.parent())
+            .map(|e| folder(e) != folder(path_to_check))
             .unwrap_or(true)
     }
 }
 
+/// The folder or archive a path is browsed in
+fn folder(path: &Path) -> Option<&Path> {
+    archive::split_path(path)
+        .map(|(archive, _)| archive)
+        .or(path.parent())
+}
+
 // Get sorted list of files in a folder
 // TODO: Should probably return an Result<T,E> instead, but am too lazy to figure out + handle a dedicated error type here
 // TODO: Cache this result, instead of doing it each time we need to fetch another file from the folder
 pub fn get_image_filenames_for_directory(folder_path: &Path) -> Result<Vec<PathBuf>> {
+    // Archives are browsed like folders
+    if let Some(archive) = archive::containing_archive(folder_path) {
+        return archive::image_paths(archive);
+    }
+
     let mut folder_path = folder_path.to_path_buf();
     if folder_path.is_file() {
         folder_path = folder_path
@@ -144,10 +157,9 @@ pub fn get_image_filenames_for_directory(folder_path: &Path) -> Result<Vec<PathB
     Ok(dir_files)
 }
 
-/// Find first valid image from the directory
-/// Assumes the given path is a directory and not a file
+/// Find first valid image from the directory or archive
 pub fn find_first_image_in_directory(folder_path: &Path) -> Result<PathBuf> {
-    if !folder_path.is_dir() {
+    if !folder_path.is_dir() && !archive::is_archive(folder_path) {
         bail!("This is not a folder");
     };
     get_image_filenames_for_directory(folder_path).map(|x| {
@@ -1,8 +1,11 @@
-use crate::{file_encoder::FileEncoder, shortcuts::*, utils::ColorChannel};
+use crate::{
+    color_management::DisplayProfile, file_encoder::FileEncoder, raw_develop::RawSettings,
+    shortcuts::*, tonemapping::ToneMapping, utils::ColorChannel,
+};
 use anyhow::{anyhow, Result};
 use log::{debug, info, trace};
 use notan::egui::{Context, Visuals};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[cfg(feature = "heif")]
 use std::sync::OnceLock;
@@ -17,7 +20,7 @@ use std::{
     path::PathBuf,
 };
 
-fn get_config_dir() -> Result<PathBuf> {
+pub(crate) fn get_config_dir() -> Result<PathBuf> {
     Ok(dirs::data_local_dir()
         .ok_or(anyhow!("Can't get local dir"))?
         .join("oculante"))
@@ -81,6 +84,14 @@ pub struct PersistentSettings {
     pub experimental_features: bool,
     /// Tunables for decoders, such as max memory usage
     pub decoders: DecoderSettings,
+    /// Open images in the running viewer instead of starting a new one
+    pub single_instance: bool,
+    /// Remove GPS data from the EXIF of saved images
+    pub strip_location: bool,
+    /// How float images such as EXR and HDR are displayed
+    pub tone_mapping: ToneMapping,
+    /// Browse the DICOM files of a folder by series, in anatomical order
+    pub dicom_series: bool,
 }
 
 impl Default for PersistentSettings {
@@ -118,6 +129,10 @@ impl Default for PersistentSettings {
             min_window_size: (100, 100),
             experimental_features: false,
             decoders: Default::default(),
+            single_instance: false,
+            strip_location: false,
+            tone_mapping: Default::default(),
+            dicom_series: Default::default(),
         }
     }
 }
@@ -135,9 +150,12 @@ impl PersistentSettings {
             );
         debug!("Loaded persistent settings: {}", config_path.display());
 
-        Ok(serde_json::from_reader::<_, PersistentSettings>(
-            File::open(config_path)?,
-        )?)
+        let mut s = serde_json::from_reader::<_, PersistentSettings>(File::open(config_path)?)?;
+        // Shortcuts added since the settings were saved
+        for (event, keys) in Shortcuts::default_keys() {
+            s.shortcuts.entry(event).or_insert(keys);
+        }
+        Ok(s)
     }
 
     pub fn save_blocking(&self) -> Result<()> {
@@ -162,9 +180,21 @@ pub struct VolatileSettings {
     pub window_geometry: ((u32, u32), (u32, u32)),
     pub last_open_directory: PathBuf,
     pub folder_bookmarks: BTreeSet<PathBuf>,
+    #[serde(deserialize_with = "deserialize_encoders")]
     pub encoding_options: Vec<FileEncoder>,
 }
 
+/// Skip encoders whose options can't be read, for example because they changed in a newer
+/// version. [`VolatileSettings::load`] adds the defaults for missing ones back.
+fn deserialize_encoders<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<FileEncoder>, D::Error> {
+    Ok(Vec::<serde_json::Value>::deserialize(deserializer)?
+        .into_iter()
+        .filter_map(|value| serde_json::from_value(value).ok())
+        .collect())
+}
+
 impl Default for VolatileSettings {
     fn default() -> Self {
         Self {
@@ -177,11 +207,26 @@ impl Default for VolatileSettings {
                 // ("jpg".to_string(), FileEncoder::Jpg { quality: 75 }),
                 // ("png".to_string(), FileEncoder::WebP),
                 FileEncoder::Jpg { quality: 75 },
-                FileEncoder::WebP,
+                FileEncoder::WebP {
+                    lossless: true,
+                    quality: 80,
+                },
                 FileEncoder::Png {
                     compressionlevel: crate::file_encoder::CompressionLevel::Default,
                 },
                 FileEncoder::Bmp,
+                FileEncoder::Gif,
+                FileEncoder::Tiff,
+                FileEncoder::Exr,
+                FileEncoder::Avif {
+                    quality: 70,
+                    speed: 6,
+                },
+                #[cfg(feature = "jxl_encode")]
+                FileEncoder::Jxl {
+                    distance: 1.0,
+                    effort: 7,
+                },
             ]
             .into_iter()
             .collect(),
@@ -197,7 +242,13 @@ impl VolatileSettings {
             // migrate old config
             ?;
 
-        let s = serde_json::from_reader::<_, VolatileSettings>(File::open(config_path)?)?;
+        let mut s = serde_json::from_reader::<_, VolatileSettings>(File::open(config_path)?)?;
+        // Encoders added since the settings were saved
+        for encoder in Self::default().encoding_options {
+            if !s.encoding_options.iter().any(|e| e.ext() == encoder.ext()) {
+                s.encoding_options.push(encoder);
+            }
+        }
         info!("Loaded volatile settings.");
         Ok(s)
     }
@@ -225,10 +276,37 @@ pub fn set_system_theme(ctx: &Context) {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct DecoderSettings {
     /// Settings for libheif
     pub heif: HeifLimits,
+    /// Convert images with an embedded ICC profile to `display_profile`
+    pub color_managed: bool,
+    pub display_profile: DisplayProfile,
+    /// How camera RAW files are developed
+    pub raw: RawSettings,
+    /// Show the preview embedded in RAW and JPEG files until the full image is decoded
+    pub embedded_previews: bool,
+}
+
+impl DecoderSettings {
+    /// The profile images are converted to, if color management is on
+    pub fn managed_profile(&self) -> Option<DisplayProfile> {
+        self.color_managed.then_some(self.display_profile)
+    }
+}
+
+impl Default for DecoderSettings {
+    fn default() -> Self {
+        Self {
+            heif: Default::default(),
+            color_managed: true,
+            display_profile: Default::default(),
+            raw: Default::default(),
+            embedded_previews: false,
+        }
+    }
 }
 
 /// Security limits for HEIF via libheif.
@@ -4,8 +4,11 @@ use std::collections::{BTreeMap, BTreeSet};
 use crate::appstate::OculanteState;
 use notan::prelude::App;
 use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, PartialOrd, Ord)]
+#[derive(
+    Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, PartialOrd, Ord, EnumIter,
+)]
 pub enum InputEvent {
     AlwaysOnTop,
     Fullscreen,
@@ -15,6 +18,8 @@ pub enum InputEvent {
     FirstImage,
     LastImage,
     PreviousImage,
+    NextPage,
+    PreviousPage,
     RedChannel,
     GreenChannel,
     BlueChannel,
@@ -119,6 +124,8 @@ impl ShortcutExt for Shortcuts {
             .add_key(InputEvent::FirstImage, "Home")
             .add_key(InputEvent::LastImage, "End")
             .add_key(InputEvent::NextImage, "Right")
+            .add_key(InputEvent::PreviousPage, "PageUp")
+            .add_key(InputEvent::NextPage, "PageDown")
             .add_key(InputEvent::ZoomIn, "Equals")
             .add_key(InputEvent::ZoomOut, "Minus")
             .add_key(InputEvent::ZoomActualSize, "Key1")
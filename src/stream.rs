@@ -0,0 +1,185 @@
+//! Live feed of images read from a byte stream such as stdin.
+//!
+//! The stream may contain concatenated PNG or QOI files, or frames of any supported format
+//! prefixed with their length as a little endian `u32`. Both can be mixed. Every image is
+//! shown as soon as it has been read, until the stream ends. JPEG, GIF, WebP, EXR and TIFF
+//! files without a length prefix are read until the end of the stream, like a single piped file.
+
+use crate::appstate::Message;
+use crate::image_loader::open_image_from_memory;
+use crate::settings::DecoderSettings;
+use crate::utils::Frame;
+use anyhow::{bail, Result};
+use log::{debug, error};
+use std::io::{BufReader, ErrorKind, Read};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+const QOI_MAGIC: &[u8; 4] = b"qoif";
+const QOI_END_MARKER: &[u8; 8] = &[0, 0, 0, 0, 0, 0, 0, 1];
+const QOI_HEADER_SIZE: usize = 14;
+/// Upper bound for a single frame, to catch garbage length prefixes
+const MAX_FRAME_SIZE: usize = 1024 * 1024 * 1024;
+/// Start of files that can't be split and are read until the end of the stream.
+/// These are unlikely to be a length prefix.
+const SINGLE_FILE_SIGNATURES: &[&[u8]] = &[
+    b"\xff\xd8\xff",
+    b"GIF8",
+    b"RIFF",
+    b"\x76\x2f\x31\x01",
+    b"II*\0",
+    b"MM\0*",
+];
+
+fn read_png(reader: &mut impl Read, buf: &mut Vec<u8>) -> Result<()> {
+    let mut signature_rest = [0u8; 4];
+    reader.read_exact(&mut signature_rest)?;
+    buf.extend_from_slice(&signature_rest);
+    if buf.as_slice() != PNG_SIGNATURE {
+        bail!("Invalid PNG signature");
+    }
+    // Each chunk is length, type, data and CRC. The image ends with the IEND chunk.
+    loop {
+        let mut chunk_header = [0u8; 8];
+        reader.read_exact(&mut chunk_header)?;
+        buf.extend_from_slice(&chunk_header);
+        let len = u32::from_be_bytes(chunk_header[..4].try_into()?) as usize;
+        if buf.len() + len > MAX_FRAME_SIZE {
+            bail!("PNG exceeds the limit of {MAX_FRAME_SIZE} bytes");
+        }
+        let start = buf.len();
+        buf.resize(start + len + 4, 0);
+        reader.read_exact(&mut buf[start..])?;
+        if &chunk_header[4..] == b"IEND" {
+            return Ok(());
+        }
+    }
+}
+
+fn read_qoi(reader: &mut impl Read, buf: &mut Vec<u8>) -> Result<()> {
+    let start = buf.len();
+    buf.resize(QOI_HEADER_SIZE, 0);
+    reader.read_exact(&mut buf[start..])?;
+    // The encoder never emits the end marker inside the data, a run would be used instead
+    let mut byte = [0u8; 1];
+    while !buf.ends_with(QOI_END_MARKER) {
+        reader.read_exact(&mut byte)?;
+        buf.push(byte[0]);
+        if buf.len() > MAX_FRAME_SIZE {
+            bail!("QOI exceeds the limit of {MAX_FRAME_SIZE} bytes");
+        }
+    }
+    Ok(())
+}
+
+/// Read the next encoded image. Returns `None` once the stream has ended.
+pub fn read_frame(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut prefix = [0u8; 4];
+    match reader.read_exact(&mut prefix) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut buf = prefix.to_vec();
+
+    if prefix == PNG_SIGNATURE[..4] {
+        read_png(reader, &mut buf)?;
+    } else if &prefix == QOI_MAGIC {
+        read_qoi(reader, &mut buf)?;
+    } else if SINGLE_FILE_SIGNATURES
+        .iter()
+        .any(|signature| prefix.starts_with(signature))
+    {
+        reader.read_to_end(&mut buf)?;
+    } else {
+        let len = u32::from_le_bytes(prefix) as usize;
+        if len > MAX_FRAME_SIZE {
+            bail!("Frame of {len} bytes exceeds the limit of {MAX_FRAME_SIZE} bytes");
+        }
+        buf = vec![0; len];
+        reader.read_exact(&mut buf)?;
+    }
+    Ok(Some(buf))
+}
+
+/// Read images from `reader` in the background and send each one to the texture channel
+pub fn stream(
+    reader: impl Read + Send + 'static,
+    texture_sender: Sender<Frame>,
+    message_sender: Sender<Message>,
+    decoder_opts: DecoderSettings,
+) {
+    thread::spawn(move || {
+        // QOI is read byte by byte to find its end
+        let mut reader = BufReader::new(reader);
+        let mut num_frames = 0;
+        loop {
+            let data = match read_frame(&mut reader) {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    debug!("Stream ended after {num_frames} frames");
+                    return;
+                }
+                Err(e) => {
+                    error!("Could not read from stream: {e}");
+                    _ = message_sender.send(Message::Error(format!("Stream stopped: {e}")));
+                    return;
+                }
+            };
+            match open_image_from_memory(&data, Some(message_sender.clone()), Some(decoder_opts)) {
+                Ok(frames) => {
+                    for frame in frames {
+                        let Some(img) = frame.get_image() else {
+                            continue;
+                        };
+                        // Only the first image resets the view, the rest behave like a video
+                        let frame = if num_frames == 0 {
+                            Frame::new_reset(img)
+                        } else {
+                            Frame::new_animation(img, 0)
+                        };
+                        num_frames += 1;
+                        if texture_sender.send(frame).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Could not decode frame: {e}");
+                    _ = message_sender
+                        .send(Message::Warning(format!("Could not decode frame: {e}")));
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn splits_concatenated_and_length_prefixed_frames() {
+        let mut png = vec![];
+        image::DynamicImage::new_rgba8(3, 2)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let mut qoi = vec![];
+        image::DynamicImage::new_rgba8(2, 5)
+            .write_to(&mut Cursor::new(&mut qoi), image::ImageFormat::Qoi)
+            .unwrap();
+
+        let mut data = png.clone();
+        data.extend_from_slice(&qoi);
+        data.extend_from_slice(&(png.len() as u32).to_le_bytes());
+        data.extend_from_slice(&png);
+
+        let mut reader = Cursor::new(data);
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(png.clone()));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(qoi));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(png));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! Mip levels, array layers and cubemap faces of KTX2 and DDS textures.
+//!
+//! The loader only shows the first face of the first layer at full resolution. Here, the
+//! whole texture is kept so that every part of it can be shown, along with its header.
+
+use crate::ktx2_loader::{
+    dds_buffer_to_image, ktx2_buffer_to_image, CompressedImageFormats, Image,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use image::{imageops, DynamicImage, RgbaImage};
+use ktx2::{BasicDataFormatDescriptor, DataFormatDescriptorHeader, TransferFunction};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use strum::Display;
+use wgpu::Extent3d;
+
+/// The names of the faces of a cubemap, in the order they are stored
+pub const FACE_NAMES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+/// How the color values of a texture are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ColorSpace {
+    #[strum(to_string = "sRGB")]
+    Srgb,
+    Linear,
+    #[strum(to_string = "Not specified")]
+    Unknown,
+}
+
+/// The header of a texture
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureInfo {
+    /// KTX2 or DDS
+    pub container: &'static str,
+    /// The format as named by the file
+    pub format: String,
+    pub supercompression: Option<String>,
+    pub color_space: ColorSpace,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub levels: u32,
+    pub layers: u32,
+    pub faces: u32,
+}
+
+impl TextureInfo {
+    pub fn is_cubemap(&self) -> bool {
+        self.faces == 6
+    }
+
+    /// The size of a mip level
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+}
+
+/// A texture with all of its parts. The data is laid out like wgpu expects it:
+/// For each layer and face, all mip levels.
+pub struct Texture {
+    pub info: TextureInfo,
+    image: Image,
+}
+
+/// Whether `path` is a texture container
+pub fn is_texture(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ktx2") || e.eq_ignore_ascii_case("dds"))
+}
+
+/// Read a KTX2 or DDS file
+pub fn read_texture(path: &Path) -> Result<Texture> {
+    let data = std::fs::read(path)?;
+    if data.starts_with(b"DDS ") {
+        read_dds(&data)
+    } else {
+        read_ktx2(&data)
+    }
+}
+
+fn read_ktx2(data: &[u8]) -> Result<Texture> {
+    let reader = ktx2::Reader::new(data).map_err(|e| anyhow!("Invalid KTX2 file: {e:?}"))?;
+    let header = reader.header();
+    let dfd = reader
+        .data_format_descriptors()
+        .find(|d| d.header == DataFormatDescriptorHeader::BASIC)
+        .and_then(|d| BasicDataFormatDescriptor::parse(d.data).ok());
+
+    // Basis Universal textures have no format, only a color model
+    let format = match (header.format, dfd.as_ref().and_then(|d| d.color_model)) {
+        (Some(format), _) => format!("{format:?}"),
+        (None, Some(model)) => format!("{model:?}"),
+        (None, None) => "Unknown".into(),
+    };
+    let transfer_function = dfd.as_ref().and_then(|d| d.transfer_function);
+    let color_space = if transfer_function == Some(TransferFunction::SRGB) {
+        ColorSpace::Srgb
+    } else if transfer_function == Some(TransferFunction::Linear) {
+        ColorSpace::Linear
+    } else {
+        ColorSpace::Unknown
+    };
+
+    let image = ktx2_buffer_to_image(data, CompressedImageFormats::all(), true)?;
+    Ok(Texture {
+        info: TextureInfo {
+            container: "KTX2",
+            format,
+            supercompression: header.supercompression_scheme.map(|s| format!("{s:?}")),
+            color_space,
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth: header.pixel_depth.max(1),
+            levels: header.level_count.max(1),
+            layers: header.layer_count.max(1),
+            faces: header.face_count.max(1),
+        },
+        image,
+    })
+}
+
+/// The names of common DXGI formats
+fn dxgi_format_name(format: u32) -> String {
+    match format {
+        2 => "R32G32B32A32_FLOAT",
+        10 => "R16G16B16A16_FLOAT",
+        24 => "R10G10B10A2_UNORM",
+        28 => "R8G8B8A8_UNORM",
+        29 => "R8G8B8A8_UNORM_SRGB",
+        41 => "R32_FLOAT",
+        49 => "R8G8_UNORM",
+        54 => "R16_FLOAT",
+        61 => "R8_UNORM",
+        71 => "BC1_UNORM",
+        72 => "BC1_UNORM_SRGB",
+        74 => "BC2_UNORM",
+        75 => "BC2_UNORM_SRGB",
+        77 => "BC3_UNORM",
+        78 => "BC3_UNORM_SRGB",
+        80 => "BC4_UNORM",
+        81 => "BC4_SNORM",
+        83 => "BC5_UNORM",
+        84 => "BC5_SNORM",
+        87 => "B8G8R8A8_UNORM",
+        88 => "B8G8R8X8_UNORM",
+        91 => "B8G8R8A8_UNORM_SRGB",
+        93 => "B8G8R8X8_UNORM_SRGB",
+        95 => "BC6H_UF16",
+        96 => "BC6H_SF16",
+        98 => "BC7_UNORM",
+        99 => "BC7_UNORM_SRGB",
+        _ => return format!("DXGI format {format}"),
+    }
+    .to_string()
+}
+
+/// Read the DDS header, including the DX10 extension
+fn read_dds_info(data: &[u8]) -> Result<TextureInfo> {
+    let field = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .context("The DDS header is too short")
+    };
+    if !data.starts_with(b"DDS ") {
+        bail!("Not a DDS file");
+    }
+    const DEPTH: u32 = 0x800000;
+    const FOURCC: u32 = 0x4;
+    const CUBEMAP: u32 = 0x200;
+    const MISC_TEXTURECUBE: u32 = 0x4;
+
+    let flags = field(8)?;
+    let cubemap = field(112)? & CUBEMAP != 0;
+    let fourcc = data.get(84..88).context("The DDS header is too short")?;
+    let (format, color_space, layers, faces) = if fourcc == b"DX10" {
+        let format = dxgi_format_name(field(128)?);
+        // Without _SRGB, DXGI formats are linear
+        let color_space = if format.ends_with("SRGB") {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Linear
+        };
+        let cube = field(136)? & MISC_TEXTURECUBE != 0;
+        (
+            format,
+            color_space,
+            field(140)?.max(1),
+            if cube { 6 } else { 1 },
+        )
+    } else {
+        let format = if field(80)? & FOURCC == 0 {
+            format!("{} bit uncompressed", field(88)?)
+        } else if fourcc.iter().all(|c| c.is_ascii_alphanumeric()) {
+            String::from_utf8_lossy(fourcc).into_owned()
+        } else {
+            // Float formats are stored as D3DFORMAT numbers
+            format!("D3DFORMAT {}", field(84)?)
+        };
+        let faces = if cubemap { 6 } else { 1 };
+        (format, ColorSpace::Unknown, 1, faces)
+    };
+
+    Ok(TextureInfo {
+        container: "DDS",
+        format,
+        supercompression: None,
+        color_space,
+        width: field(16)?,
+        height: field(12)?.max(1),
+        depth: if flags & DEPTH != 0 {
+            field(24)?.max(1)
+        } else {
+            1
+        },
+        levels: field(28)?.max(1),
+        layers,
+        faces,
+    })
+}
+
+fn read_dds(data: &[u8]) -> Result<Texture> {
+    Ok(Texture {
+        info: read_dds_info(data)?,
+        image: dds_buffer_to_image(data, CompressedImageFormats::all(), true)?,
+    })
+}
+
+impl Texture {
+    /// The number of bytes of a mip level of one face
+    fn level_bytes(&self, level: u32) -> usize {
+        let format = self.image.texture_descriptor.format;
+        let (block_width, block_height) = format.block_dimensions();
+        let block_bytes = format.block_copy_size(None).unwrap_or(4);
+        let (width, height) = self.info.level_size(level);
+        let depth = (self.info.depth >> level).max(1);
+        (width.div_ceil(block_width) * height.div_ceil(block_height) * depth * block_bytes) as usize
+    }
+
+    /// Decode a mip level of one layer and face
+    pub fn subresource(&self, level: u32, layer: u32, face: u32) -> Result<DynamicImage> {
+        let info = &self.info;
+        if level >= info.levels || layer >= info.layers || face >= info.faces {
+            bail!("The texture has no level {level}, layer {layer} and face {face}");
+        }
+        let face_bytes = (0..info.levels).map(|l| self.level_bytes(l)).sum::<usize>();
+        let start = (layer * info.faces + face) as usize * face_bytes
+            + (0..level).map(|l| self.level_bytes(l)).sum::<usize>();
+        let data = self
+            .image
+            .data
+            .get(start..start + self.level_bytes(level))
+            .context("This part of the texture could not be decoded")?;
+
+        let (width, height) = info.level_size(level);
+        let mut image = Image {
+            data: data.to_vec(),
+            ..Default::default()
+        };
+        image.texture_descriptor.format = self.image.texture_descriptor.format;
+        image.texture_descriptor.size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        Ok(image.try_into_dynamic()?)
+    }
+
+    /// All six faces of a cubemap layer, unfolded into a cross
+    pub fn cross(&self, level: u32, layer: u32) -> Result<DynamicImage> {
+        let faces = (0..6)
+            .map(|face| Ok(self.subresource(level, layer, face)?.into_rgba8()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(DynamicImage::ImageRgba8(cube_cross(&faces)))
+    }
+}
+
+/// Lay out the faces of a cube as a horizontal cross: +Y on top, then -X, +Z, +X and -Z,
+/// then -Y at the bottom
+pub fn cube_cross(faces: &[RgbaImage]) -> RgbaImage {
+    // Column and row of each face, in storage order
+    const CELLS: [(u32, u32); 6] = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+    let size = faces.first().map(|f| f.width()).unwrap_or_default();
+    let mut cross = RgbaImage::new(size * 4, size * 3);
+    for (face, (column, row)) in faces.iter().zip(CELLS) {
+        imageops::replace(
+            &mut cross,
+            face,
+            (column * size) as i64,
+            (row * size) as i64,
+        );
+    }
+    cross
+}
+
+/// The texture of the current image and the part of it on display
+#[derive(Default)]
+pub struct TextureInspectorState {
+    /// The file the texture was read from
+    pub path: Option<PathBuf>,
+    pub texture: Option<Texture>,
+    pub level: u32,
+    pub layer: u32,
+    pub face: u32,
+    /// Show all faces of a cubemap at once
+    pub cross: bool,
+    pub error: Option<String>,
+    pending: Option<Receiver<Result<Texture>>>,
+}
+
+impl TextureInspectorState {
+    /// Read the texture of `path` in the background, unless it is known already
+    pub fn load(&mut self, path: &Path) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+        self.clear();
+        self.path = Some(path.to_path_buf());
+        let (sender, receiver) = channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            _ = sender.send(read_texture(&path));
+        });
+        self.pending = Some(receiver);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Pick up the result of [`TextureInspectorState::load`]
+    pub fn poll(&mut self) {
+        let Some(result) = self.pending.as_ref().and_then(|r| r.try_recv().ok()) else {
+            return;
+        };
+        self.pending = None;
+        match result {
+            Ok(texture) => self.texture = Some(texture),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// The selected level, layer and face, or the cross of all faces
+    pub fn render(&mut self) -> Option<DynamicImage> {
+        let texture = self.texture.as_ref()?;
+        let result = if self.cross && texture.info.is_cubemap() {
+            texture.cross(self.level, self.layer)
+        } else {
+            texture.subresource(self.level, self.layer, self.face)
+        };
+        self.error = result.as_ref().err().map(|e| e.to_string());
+        result.ok()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn unfolds_cube_cross() {
+        let faces = (0..6u8)
+            .map(|face| RgbaImage::from_pixel(2, 2, Rgba([face, 0, 0, 255])))
+            .collect::<Vec<_>>();
+        let cross = cube_cross(&faces);
+        assert_eq!(cross.dimensions(), (8, 6));
+        // +Y on top, -X +Z +X -Z in the middle, -Y at the bottom
+        assert_eq!(cross.get_pixel(2, 0).0, [2, 0, 0, 255]);
+        let middle = (0..4).map(|c| cross.get_pixel(c * 2, 2).0[0]);
+        assert_eq!(middle.collect::<Vec<_>>(), [1, 4, 0, 5]);
+        assert_eq!(cross.get_pixel(2, 4).0, [3, 0, 0, 255]);
+        assert_eq!(cross.get_pixel(0, 0).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn reads_dds_header() {
+        let mut data = vec![0u8; 148];
+        data[0..4].copy_from_slice(b"DDS ");
+        data[12..16].copy_from_slice(&64u32.to_le_bytes());
+        data[16..20].copy_from_slice(&128u32.to_le_bytes());
+        data[28..32].copy_from_slice(&8u32.to_le_bytes());
+        data[80..84].copy_from_slice(&4u32.to_le_bytes());
+        data[84..88].copy_from_slice(b"DX10");
+        data[128..132].copy_from_slice(&99u32.to_le_bytes());
+        data[136..140].copy_from_slice(&4u32.to_le_bytes());
+        data[140..144].copy_from_slice(&2u32.to_le_bytes());
+
+        let info = read_dds_info(&data).unwrap();
+        assert_eq!(info.format, "BC7_UNORM_SRGB");
+        assert_eq!(info.color_space, ColorSpace::Srgb);
+        assert_eq!((info.width, info.height, info.levels), (128, 64, 8));
+        assert_eq!((info.layers, info.faces), (2, 6));
+        assert_eq!(info.level_size(7), (1, 1));
+    }
+}
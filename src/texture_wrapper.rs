@@ -1,4 +1,5 @@
 use crate::settings::PersistentSettings;
+use crate::tonemapping::ToneMapping;
 use crate::utils::ColorChannel;
 use image::imageops;
 use image::DynamicImage;
@@ -23,6 +24,7 @@ pub struct TexWrap {
     pub image_format: image::ColorType,
     uniform_swizzle_mask: Buffer,
     uniform_offset_vec: Buffer,
+    uniform_tone_mapping: Buffer,
 }
 
 #[derive(Default)]
@@ -82,6 +84,16 @@ impl TextureWrapperManager {
         }
     }
 
+    /// Apply changed exposure, gamma or tone mapper without uploading the image again
+    pub fn update_tone_mapping(&mut self, gfx: &mut Graphics, tone_mapping: &ToneMapping) {
+        if let Some(tex) = &mut self.current_texture {
+            gfx.set_buffer_data(
+                &tex.uniform_tone_mapping,
+                &tone_mapping.uniform(tex.image_format),
+            );
+        }
+    }
+
     fn get_mat_vec(channel_selection: ColorChannel, image_color: image::ColorType) -> (Mat4, Vec4) {
         //Currently we have two types of textures: rgba and gray ones.
         //All other types will be converted to rgba, so we only need to take care of those types here
@@ -181,10 +193,51 @@ const FRAGMENT_IMAGE_RENDER: ShaderSource = notan::fragment_shader! {
         vec4 offset;
     };
 
+    // x: exposure in stops, y: gamma, z: operator (0: none, 1: clamp, 2: Reinhard, 3: ACES, 4: AgX)
+    layout(binding = 3) uniform ToneMapping {
+        vec4 tone_mapping;
+    };
+
     layout(location = 0) out vec4 color;
 
+    const float AGX_MIN_EV = -12.47393;
+    const float AGX_MAX_EV = 4.026069;
+
+    vec3 agx(vec3 c) {
+        const mat3 agx_mat = mat3(
+            0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+            0.0784335999999992, 0.878468636469772, 0.0784336,
+            0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+        const mat3 agx_mat_inv = mat3(
+            1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+            -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+            -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+        vec3 x = max(agx_mat * c, vec3(1e-10));
+        x = (clamp(log2(x), AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+        vec3 x2 = x * x;
+        vec3 x4 = x2 * x2;
+        x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+        return pow(max(agx_mat_inv * x, vec3(0.0)), vec3(2.2));
+    }
+
+    vec3 tonemap(vec3 c) {
+        c = max(c, vec3(0.0)) * exp2(tone_mapping.x);
+        int op = int(tone_mapping.z + 0.5);
+        if (op == 2) {
+            c = c / (c + 1.0);
+        } else if (op == 3) {
+            c = (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14);
+        } else if (op == 4) {
+            c = agx(c);
+        }
+        return pow(clamp(c, 0.0, 1.0), vec3(1.0 / tone_mapping.y));
+    }
+
     void main() {
         vec4 tex_col = texture(u_texture, v_uvs);
+        if (tone_mapping.z > 0.5) {
+            tex_col.rgb = tonemap(tex_col.rgb);
+        }
         color = ((swizzle_mat*tex_col)+offset) * v_color;
     }
     "#
@@ -212,7 +265,8 @@ impl TexWrap {
         gfx: &mut Graphics,
         swizzle_mask: Mat4,
         offset_vec: Vec4,
-    ) -> Result<(Buffer, Buffer), String> {
+        tone_mapping: Vec4,
+    ) -> Result<(Buffer, Buffer, Buffer), String> {
         let uniform_swizzle_mask = gfx
             .create_uniform_buffer(1, "SwizzleMask")
             .with_data(&swizzle_mask)
@@ -223,7 +277,16 @@ impl TexWrap {
             .with_data(&offset_vec)
             .build()?;
 
-        Ok((uniform_swizzle_mask, uniform_offset_vector))
+        let uniform_tone_mapping = gfx
+            .create_uniform_buffer(3, "ToneMapping")
+            .with_data(&tone_mapping)
+            .build()?;
+
+        Ok((
+            uniform_swizzle_mask,
+            uniform_offset_vector,
+            uniform_tone_mapping,
+        ))
     }
 
     fn update_uniform_buffer(&self, gfx: &mut Graphics, swizzle_mat: Mat4, offset_vec: Vec4) {
@@ -669,8 +732,12 @@ impl TexWrap {
 
         let texture_count = texture_vec.len();
 
-        let (uniforms, uniforms2) =
-            Self::gen_uniform_buffer_swizzle_mask(gfx, swizzle_mask, add_vec)?;
+        let (uniforms, uniforms2, uniforms3) = Self::gen_uniform_buffer_swizzle_mask(
+            gfx,
+            swizzle_mask,
+            add_vec,
+            settings.tone_mapping.uniform(image.color()),
+        )?;
 
         Ok(TexWrap {
             texture_boundary,
@@ -686,6 +753,7 @@ impl TexWrap {
             image_format: image.color(),
             uniform_swizzle_mask: uniforms,
             uniform_offset_vec: uniforms2,
+            uniform_tone_mapping: uniforms3,
         })
     }
 
@@ -944,7 +1012,8 @@ impl TexWrap {
             draw.image_pipeline()
                 .pipeline(pip)
                 .uniform_buffer(&self.uniform_swizzle_mask)
-                .uniform_buffer(&self.uniform_offset_vec);
+                .uniform_buffer(&self.uniform_offset_vec)
+                .uniform_buffer(&self.uniform_tone_mapping);
         }
     }
 
@@ -15,7 +15,8 @@ use anyhow::{anyhow, bail, Context, Result};
 use image::{imageops, DynamicImage, GenericImageView};
 use log::{debug, error, trace, warn};
 
-use crate::image_loader::open_image;
+use crate::image_loader::{load_embedded_preview, open_image};
+use crate::tonemapping::{is_scene_linear, ToneMapping};
 
 #[derive(Debug, Default, Clone)]
 pub struct Thumbnails {
@@ -95,8 +96,14 @@ pub fn generate<P: AsRef<Path>>(source_path: P) -> Result<()> {
         source_path.as_ref().display(),
         dest_path.display()
     );
-    let f = open_image(source_path.as_ref(), None, None)?;
-    let i = f.recv()?.get_image().context("Can't get buffer")?;
+    // The preview embedded in RAW and JPEG files is plenty for a thumbnail
+    let i = match load_embedded_preview(source_path.as_ref()) {
+        Ok(preview) => preview,
+        Err(_) => {
+            let f = open_image(source_path.as_ref(), None, None)?;
+            f.recv()?.get_image().context("Can't get buffer")?
+        }
+    };
 
     debug!("\tOpened {}", source_path.as_ref().display());
 
@@ -107,6 +114,15 @@ pub fn generate<P: AsRef<Path>>(source_path: P) -> Result<()> {
 pub fn from_existing<P: AsRef<Path>>(dest_path: P, image: &DynamicImage) -> Result<()> {
     debug!("TMB=> Original image size: {:?}", image.dimensions());
 
+    // Float images would look dark without tone mapping
+    let tone_mapped;
+    let image = if is_scene_linear(image.color()) {
+        tone_mapped = ToneMapping::default().bake(image);
+        &tone_mapped
+    } else {
+        image
+    };
+
     let target_width = 120;
     let target_height = 90;
     let desired_aspect = target_width as f32 / target_height as f32;
@@ -0,0 +1,176 @@
+//! Tone mapping: Display float (scene linear) images such as EXR and HDR.
+//!
+//! The viewer does this in the fragment shader so exposure and operator can change
+//! without decoding the image again. The same curves are available on the CPU for
+//! places that need 8 bit pixels, such as thumbnails.
+
+use image::{ColorType, DynamicImage, Rgba, RgbaImage};
+use notan::math::{Mat3, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter};
+
+/// The curve that maps scene linear values to the displayable range
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter, Display)]
+pub enum ToneMapper {
+    /// Cut off everything above 1.0
+    #[default]
+    Clamp,
+    Reinhard,
+    #[strum(to_string = "ACES")]
+    Aces,
+    #[strum(to_string = "AgX")]
+    AgX,
+}
+
+/// How float images are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub mapper: ToneMapper,
+    /// Exposure adjustment in stops
+    pub exposure: f32,
+    pub gamma: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            mapper: ToneMapper::default(),
+            exposure: 0.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+/// Whether an image holds scene linear values that need tone mapping
+pub fn is_scene_linear(color: ColorType) -> bool {
+    matches!(color, ColorType::Rgb32F | ColorType::Rgba32F)
+}
+
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+// The matrices are the published AgX values, kept as they are
+#[allow(clippy::excessive_precision)]
+fn agx(color: Vec3) -> Vec3 {
+    let agx_mat = Mat3::from_cols_array(&[
+        0.842479062253094,
+        0.0423282422610123,
+        0.0423756549057051,
+        0.0784335999999992,
+        0.878468636469772,
+        0.0784336,
+        0.0792237451477643,
+        0.0791661274605434,
+        0.879142973793104,
+    ]);
+    let agx_mat_inv = Mat3::from_cols_array(&[
+        1.19687900512017,
+        -0.0528968517574562,
+        -0.0529716355144438,
+        -0.0980208811401368,
+        1.15190312990417,
+        -0.0980434501171241,
+        -0.0990297440797205,
+        -0.0989611768448433,
+        1.15107367264116,
+    ]);
+    let encoded = (agx_mat * color).max(Vec3::splat(1e-10));
+    let x = ((Vec3::new(encoded.x.log2(), encoded.y.log2(), encoded.z.log2()))
+        .clamp(Vec3::splat(AGX_MIN_EV), Vec3::splat(AGX_MAX_EV))
+        - AGX_MIN_EV)
+        / (AGX_MAX_EV - AGX_MIN_EV);
+    // Polynomial fit of the default AgX contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+    // Back to linear, the caller applies the display gamma
+    let linear = (agx_mat_inv * curve).max(Vec3::ZERO);
+    Vec3::new(linear.x.powf(2.2), linear.y.powf(2.2), linear.z.powf(2.2))
+}
+
+impl ToneMapping {
+    /// Map a scene linear color to display values in 0..1
+    pub fn map(&self, color: Vec3) -> Vec3 {
+        let c = color.max(Vec3::ZERO) * self.exposure.exp2();
+        let mapped = match self.mapper {
+            ToneMapper::Clamp => c,
+            ToneMapper::Reinhard => c / (c + 1.0),
+            ToneMapper::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+            ToneMapper::AgX => agx(c),
+        };
+        let mapped = mapped.clamp(Vec3::ZERO, Vec3::ONE);
+        let exponent = 1.0 / self.gamma.max(0.01);
+        Vec3::new(
+            mapped.x.powf(exponent),
+            mapped.y.powf(exponent),
+            mapped.z.powf(exponent),
+        )
+    }
+
+    /// The shader uniform: Exposure, gamma, operator (0 disables mapping) and one unused value
+    pub fn uniform(&self, color: ColorType) -> Vec4 {
+        let operator = if is_scene_linear(color) {
+            self.mapper as u8 as f32 + 1.0
+        } else {
+            0.0
+        };
+        Vec4::new(self.exposure, self.gamma.max(0.01), operator, 0.0)
+    }
+
+    /// Convert a scene linear image to 8 bit as it is displayed. Other images are returned as they are.
+    pub fn bake(&self, image: &DynamicImage) -> DynamicImage {
+        if !is_scene_linear(image.color()) {
+            return image.clone();
+        }
+        let float = image.to_rgba32f();
+        let mapped = RgbaImage::from_fn(float.width(), float.height(), |x, y| {
+            let [r, g, b, a] = float.get_pixel(x, y).0;
+            let c = self.map(Vec3::new(r, g, b)) * 255.0;
+            Rgba([
+                c.x.round() as u8,
+                c.y.round() as u8,
+                c.z.round() as u8,
+                (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+            ])
+        });
+        DynamicImage::ImageRgba8(mapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn maps_into_display_range() {
+        for mapper in ToneMapper::iter() {
+            let tone_mapping = ToneMapping {
+                mapper,
+                ..Default::default()
+            };
+            let black = tone_mapping.map(Vec3::ZERO);
+            let bright = tone_mapping.map(Vec3::splat(100.0));
+            assert!(black.max_element() < 0.05, "{mapper} black is {black}");
+            assert!(
+                bright.min_element() > 0.8,
+                "{mapper} highlights are {bright}"
+            );
+            assert!(bright.max_element() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn exposure_brightens() {
+        let mut tone_mapping = ToneMapping {
+            mapper: ToneMapper::Reinhard,
+            ..Default::default()
+        };
+        let before = tone_mapping.map(Vec3::splat(0.18));
+        tone_mapping.exposure = 1.0;
+        assert!(tone_mapping.map(Vec3::splat(0.18)).x > before.x);
+    }
+}
@@ -1,6 +1,7 @@
 use super::*;
 use crate::appstate::OculanteState;
 use crate::utils::*;
+use crate::web_export::{format_size, WEB_FORMATS};
 use image::{ColorType, GenericImageView, RgbaImage};
 #[cfg(not(any(target_os = "netbsd", target_os = "freebsd")))]
 use notan::egui::*;
@@ -380,6 +381,8 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                 #[cfg(feature = "turbo")]
                 jpg_lossless_ui(state, ui);
 
+                web_export_ui(ctx, state, ui, gfx);
+
 
                 if state.current_path.is_none() && state.current_image.is_some() {
                     #[cfg(not(feature = "file_open"))]
@@ -400,7 +403,11 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         let image_to_save = state.edit_state.result_pixel_op.clone();
                         let msg_sender = state.message_channel.0.clone();
                         let err_sender = state.message_channel.0.clone();
-                        let image_info = state.image_metadata.clone();
+                        let source = state.current_path.clone();
+                        let encoders = state.volatile_settings.encoding_options.clone();
+                        let strip_location = state.persistent_settings.strip_location;
+                        let animation_edits = state.is_animation.then(|| state.edit_state.clone());
+                        let display_profile = state.persistent_settings.decoders.managed_profile();
 
                         std::thread::spawn(move || {
                             let file_dialog_result = rfd::FileDialog::new()
@@ -409,26 +416,9 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
 
                                 if let Some(file_path) = file_dialog_result {
                                     debug!("Selected File Path = {:?}", file_path);
-                                    match image_to_save
-                                        .save(&file_path) {
+                                    match save_with_encoding(&image_to_save, &file_path, source.as_deref(), &encoders, strip_location, animation_edits.as_ref(), display_profile) {
                                             Ok(_) => {
                                                 _ = msg_sender.send(crate::appstate::Message::Saved(file_path.clone()));
-                                                debug!("Saved to {}", file_path.display());
-                                                // Re-apply exif
-                                                if let Some(info) = &image_info {
-                                                    debug!("Extended image info present");
-
-                                                    // before doing anything, make sure we have raw exif data
-                                                    if info.raw_exif.is_some() {
-                                                        if let Err(e) = fix_exif(&file_path, info.raw_exif.clone()) {
-                                                            error!("{e}");
-                                                        } else {
-                                                            info!("Saved EXIF.")
-                                                        }
-                                                    } else {
-                                                        debug!("No raw exif");
-                                                    }
-                                                }
                                             }
                                             Err(e) => {
                                                 _ = err_sender.send(crate::appstate::Message::err(&format!("Error: Could not save: {e}")));
@@ -455,12 +445,14 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         let keys = &state.volatile_settings.encoding_options.iter().map(|e|e.ext()).collect::<Vec<_>>();
                         let key_slice = keys.iter().map(|k|k.as_str()).collect::<Vec<_>>();
                         let encoders = state.volatile_settings.encoding_options.clone();
+                        let source = state.current_path.clone();
+                        let strip_location = state.persistent_settings.strip_location;
                         filebrowser::browse_modal(
                             true,
                             key_slice.as_slice(),
                             &mut state.volatile_settings,
                             |p| {
-                                _ = save_with_encoding(&state.edit_state.result_pixel_op, p, &state.image_metadata, &encoders);
+                                _ = save_with_encoding(&state.edit_state.result_pixel_op, p, source.as_deref(), &encoders, strip_location, state.is_animation.then_some(&state.edit_state), state.persistent_settings.decoders.managed_profile());
                             },
                             ctx,
                         );
@@ -471,7 +463,7 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     let text = if p.exists() { "Overwrite" } else { "Save"};
 
                     let modal = show_modal(ui.ctx(), "Overwrite?", |_|{
-                        _ = save_with_encoding(&state.edit_state.result_pixel_op, p, &state.image_metadata, &state.volatile_settings.encoding_options).map(|_| state.send_message_info("Saved")).map_err(|e| state.send_message_err(&format!("Error: {e}")));
+                        _ = save_with_encoding(&state.edit_state.result_pixel_op, p, Some(p), &state.volatile_settings.encoding_options, state.persistent_settings.strip_location, state.is_animation.then_some(&state.edit_state), state.persistent_settings.decoders.managed_profile()).map(|_| state.send_message_info("Saved")).map_err(|e| state.send_message_err(&format!("Error: {e}")));
                     }, "overwrite");
 
 
@@ -479,7 +471,7 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         if p.exists() {
                             modal.open();
                         } else {
-                            _ = save_with_encoding(&state.edit_state.result_pixel_op, p, &state.image_metadata, &state.volatile_settings.encoding_options).map(|_| state.send_message_info("Saved")).map_err(|e| state.send_message_err(&format!("Error: {e}")));
+                            _ = save_with_encoding(&state.edit_state.result_pixel_op, p, Some(p), &state.volatile_settings.encoding_options, state.persistent_settings.strip_location, state.is_animation.then_some(&state.edit_state), state.persistent_settings.decoders.managed_profile()).map(|_| state.send_message_info("Saved")).map_err(|e| state.send_message_err(&format!("Error: {e}")));
                         }
                     }
 
@@ -534,7 +526,8 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     let stamp = Instant::now();
                     // start with a fresh copy of the unmodified image
                     // FIXME This needs to go, and we need to implement operators for DynamicImage
-                    state.edit_state.result_image_op = img.clone();
+                    // Float images are edited the way they are currently tone mapped
+                    state.edit_state.result_image_op = state.persistent_settings.tone_mapping.bake(img);
                     for operation in &state.edit_state.image_op_stack {
                         if !operation.active {
                             continue;
@@ -558,6 +551,9 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
             }
 
             if pixels_changed {
+                // A compressed preview would show outdated edits
+                state.web_export.clear();
+
                 // init result as a clean copy of image operation result
                 let stamp = Instant::now();
 
@@ -848,6 +844,153 @@ fn modifier_stack_ui(
     }
 }
 
+/// "Save for web": compress to a target size and compare the result with the image
+fn web_export_ui(ctx: &Context, state: &mut OculanteState, ui: &mut Ui, gfx: &mut Graphics) {
+    if state.current_image.is_none() {
+        return;
+    }
+
+    if state.web_export.poll() {
+        if let Some(export) = &state.web_export.result {
+            state.web_export.show_preview = true;
+            if let Err(e) =
+                state
+                    .current_texture
+                    .set_image(&export.preview, gfx, &state.persistent_settings)
+            {
+                state.send_message_warn(&format!("Error while displaying preview: {e}"));
+            }
+        }
+    }
+
+    ui.styled_collapsing("Save for web", |ui| {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("web export format")
+                .selected_text(state.web_export.ext.to_uppercase())
+                .show_ui(ui, |ui| {
+                    for ext in WEB_FORMATS {
+                        ui.selectable_value(&mut state.web_export.ext, ext.to_string(), ext.to_uppercase());
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut state.web_export.target_kb)
+                    .range(1..=100_000)
+                    .suffix(" KB"),
+            )
+            .on_hover_text("The largest file size to allow");
+        });
+
+        if state.web_export.is_pending() {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Compressing...");
+            });
+            ui.ctx().request_repaint();
+        } else if ui
+            .button("Compress")
+            .on_hover_text("Find the highest quality that fits the target size")
+            .clicked()
+        {
+            let encoder = FileEncoder::matching_variant(
+                Path::new("web").with_extension(&state.web_export.ext).as_path(),
+                &state.volatile_settings.encoding_options,
+            );
+            state
+                .web_export
+                .start(state.edit_state.result_pixel_op.clone(), encoder);
+        }
+
+        if let Some(e) = &state.web_export.error {
+            ui.label(format!("{WARNING_CIRCLE} {e}"));
+        }
+
+        let Some(export) = &state.web_export.result else {
+            return;
+        };
+        ui.label(format!(
+            "Quality {}: {}",
+            export.quality,
+            format_size(export.data.len())
+        ));
+
+        if ui
+            .styled_checkbox(&mut state.web_export.show_preview, "Show compressed")
+            .on_hover_text("Toggle between the compressed result and the image")
+            .changed()
+        {
+            if state.web_export.show_preview {
+                if let Err(e) =
+                    state
+                        .current_texture
+                        .set_image(&export.preview, gfx, &state.persistent_settings)
+                {
+                    state.send_message_warn(&format!("Error while displaying preview: {e}"));
+                }
+            } else {
+                state.send_frame(crate::utils::Frame::UpdateTexture);
+            }
+        }
+
+        #[cfg(feature = "file_open")]
+        if ui.button(format!("Save at quality {}...", export.quality)).clicked() {
+            let file_name = state
+                .current_path
+                .as_deref()
+                .and_then(|p| p.file_stem())
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| "untitled".into());
+            let file_name = format!("{file_name}.{}", export.encoder.ext());
+            let start_directory = state.volatile_settings.last_open_directory.clone();
+            let data = export.data.clone();
+            let msg_sender = state.message_channel.0.clone();
+            std::thread::spawn(move || {
+                if let Some(file_path) = rfd::FileDialog::new()
+                    .set_directory(start_directory)
+                    .set_file_name(file_name)
+                    .save_file()
+                {
+                    match std::fs::write(&file_path, data) {
+                        Ok(_) => {
+                            _ = msg_sender.send(crate::appstate::Message::Saved(file_path));
+                        }
+                        Err(e) => {
+                            _ = msg_sender.send(crate::appstate::Message::err(&format!(
+                                "Error: Could not save: {e}"
+                            )));
+                        }
+                    }
+                }
+            });
+        }
+
+        #[cfg(not(feature = "file_open"))]
+        {
+            if ui.button(format!("Save at quality {}...", export.quality)).clicked() {
+                ui.ctx().memory_mut(|w| w.open_popup(Id::new("SAVE_WEB")));
+            }
+            if ctx.memory(|w| w.is_popup_open(Id::new("SAVE_WEB"))) {
+                let ext = export.encoder.ext();
+                let data = export.data.clone();
+                let msg_sender = state.message_channel.0.clone();
+                filebrowser::browse_modal(
+                    true,
+                    &[ext.as_str()],
+                    &mut state.volatile_settings,
+                    |p| match std::fs::write(p, &data) {
+                        Ok(_) => _ = msg_sender.send(crate::appstate::Message::Saved(p.clone())),
+                        Err(e) => {
+                            _ = msg_sender.send(crate::appstate::Message::err(&format!(
+                                "Error: Could not save: {e}"
+                            )))
+                        }
+                    },
+                    ctx,
+                );
+            }
+        }
+    });
+}
+
 /// A ui for lossless JPEG editing
 #[cfg(feature = "turbo")]
 fn jpg_lossless_ui(state: &mut OculanteState, ui: &mut Ui) {
@@ -1,9 +1,13 @@
 use crate::appstate::OculanteState;
 use crate::comparelist::CompareItem;
+use crate::dicom_window::WindowPreset;
+use crate::exr_layers::ExrView;
 #[cfg(feature = "file_open")]
 use crate::filebrowser::browse_for_image_path;
 use crate::filebrowser::BrowserDir;
 use crate::icons::*;
+use crate::raw_develop::{Demosaic, WhiteBalance};
+use crate::texture_inspector::FACE_NAMES;
 use crate::utils::*;
 use egui_plot::{Line, Plot, PlotPoints};
 use image::ColorType;
@@ -109,6 +113,27 @@ pub fn info_ui(
                     );
                     ui.end_row();
 
+                    // The original value of 16 bit, float and DICOM images
+                    if let Some(stored) = state.current_image.as_ref().and_then(|img| {
+                        let x = state.cursor_relative.x as u32;
+                        let y = state.cursor_relative.y as u32;
+                        match &state.image_metadata {
+                            Some(info) => info.stored_value(img, x, y),
+                            None => stored_value(img, x, y),
+                        }
+                    }) {
+                        ui.label_i(format!("{PALETTE} Stored"));
+                        ui.label_right(RichText::new(stored));
+                        ui.end_row();
+                    }
+
+                    if let Some(range) = state.image_metadata.as_ref().and_then(|info| info.value_range_text()) {
+                        ui.label_i(format!("{PALETTE} Range"));
+                        ui.label_right(RichText::new(range))
+                            .on_hover_text("Smallest and largest stored value of the image");
+                        ui.end_row();
+                    }
+
                     ui.label_i(format!("{PALETTE} HEX"));
                     let hex = Color32::from_rgba_unmultiplied(state.sampled_color[0] as u8, state.sampled_color[1] as u8, state.sampled_color[2] as u8, state.sampled_color[3] as u8).to_hex();
                     ui.label_right(
@@ -242,6 +267,84 @@ pub fn info_ui(
                 });
             });
 
+            let is_exr = state
+                .current_path
+                .as_ref()
+                .and_then(|p| p.extension())
+                .is_some_and(|e| e.eq_ignore_ascii_case("exr"));
+            if is_exr {
+                ui.styled_collapsing("EXR layers", |ui| {
+                    ui.vertical_centered_justified(|ui| {
+                        dark_panel(ui, |ui| {
+                            exr_layers_ui(ui, state);
+                        });
+                    });
+                });
+            }
+
+            let has_layers = state
+                .current_path
+                .as_deref()
+                .is_some_and(crate::layers::has_layers);
+            if has_layers {
+                ui.styled_collapsing("Layers", |ui| {
+                    ui.vertical_centered_justified(|ui| {
+                        dark_panel(ui, |ui| {
+                            layers_ui(ui, state);
+                        });
+                    });
+                });
+            }
+
+            let is_raw = state
+                .current_path
+                .as_deref()
+                .is_some_and(crate::raw_develop::is_raw);
+            if is_raw {
+                ui.styled_collapsing("RAW development", |ui| {
+                    ui.vertical_centered_justified(|ui| {
+                        dark_panel(ui, |ui| {
+                            raw_develop_ui(ui, state);
+                        });
+                    });
+                });
+            }
+
+            let is_texture = state
+                .current_path
+                .as_deref()
+                .is_some_and(crate::texture_inspector::is_texture);
+            if is_texture {
+                ui.styled_collapsing("Texture", |ui| {
+                    ui.vertical_centered_justified(|ui| {
+                        dark_panel(ui, |ui| {
+                            texture_ui(ui, state);
+                        });
+                    });
+                });
+            }
+
+            let is_dicom = state
+                .current_path
+                .as_deref()
+                .is_some_and(|path| state.dicom_window.is_dicom(path));
+            if is_dicom {
+                ui.styled_collapsing("DICOM window", |ui| {
+                    ui.vertical_centered_justified(|ui| {
+                        dark_panel(ui, |ui| {
+                            dicom_window_ui(ui, state);
+                        });
+                    });
+                });
+                ui.styled_collapsing("DICOM series", |ui| {
+                    ui.vertical_centered_justified(|ui| {
+                        dark_panel(ui, |ui| {
+                            dicom_series_ui(ui, state);
+                        });
+                    });
+                });
+            }
+
             if state.current_texture.get().is_some() {
                 ui.styled_collapsing("Alpha tools", |ui| {
                     ui.vertical_centered_justified(|ui| {
@@ -297,7 +400,570 @@ pub fn info_ui(
     (bbox_tl, bbox_br)
 }
 
+/// Pick a layer or channel of an EXR image to display
+fn exr_layers_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let Some(path) = state.current_path.clone() else {
+        return;
+    };
+    state.exr_layers.load(&path);
+    if state.exr_layers.poll() {
+        show_exr_view(state);
+    }
+
+    let layers = &state.exr_layers;
+    let mut picked = None;
+    egui::ComboBox::from_id_salt("exr_view")
+        .width(ui.available_width())
+        .selected_text(
+            layers
+                .decoded
+                .as_ref()
+                .map(|d| d.view.label(&layers.layers))
+                .unwrap_or("Default".into()),
+        )
+        .show_ui(ui, |ui| {
+            for view in &layers.views {
+                let selected = layers.decoded.as_ref().is_some_and(|d| &d.view == view);
+                if ui
+                    .selectable_label(selected, view.label(&layers.layers))
+                    .clicked()
+                {
+                    picked = Some(view.clone());
+                }
+            }
+        });
+    if let Some(view) = picked {
+        state.exr_layers.select(view);
+    }
+
+    if state.exr_layers.is_pending() {
+        ui.horizontal(|ui| {
+            ui.add(egui::Spinner::default());
+            ui.label("Decoding layer");
+        });
+        ui.ctx().request_repaint();
+    }
+    if let Some(error) = &state.exr_layers.error {
+        ui.colored_label(Color32::RED, error);
+    }
+
+    let Some(decoded) = &state.exr_layers.decoded else {
+        return;
+    };
+    if let Some(value) = decoded.value_at(
+        state.cursor_relative.x as u32,
+        state.cursor_relative.y as u32,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("Value");
+            ui.label_right(value);
+        });
+    }
+
+    if matches!(decoded.view, ExrView::Channel { .. }) {
+        let normalization = &mut state.exr_layers.normalization;
+        let speed = (normalization.max - normalization.min).abs().max(0.001) * 0.005;
+        let mut changed = ui
+            .styled_checkbox(&mut normalization.auto, "Auto range")
+            .on_hover_text("Show the smallest value as black and the largest as white")
+            .changed();
+        ui.add_enabled_ui(!normalization.auto, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Range");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut normalization.min).speed(speed))
+                    .changed();
+                changed |= ui
+                    .add(egui::DragValue::new(&mut normalization.max).speed(speed))
+                    .changed();
+            });
+        });
+        if changed {
+            show_exr_view(state);
+        }
+    }
+}
+
+/// Window center and width of DICOM images
+fn dicom_window_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let window_state = &mut state.dicom_window;
+    if window_state.is_pending() {
+        ui.horizontal(|ui| {
+            ui.add(egui::Spinner::default());
+            ui.label("Reading values");
+        });
+        ui.ctx().request_repaint();
+    }
+    if let Some(error) = &window_state.error {
+        ui.colored_label(Color32::RED, error);
+    }
+    let Some(frame) = &window_state.frame else {
+        return;
+    };
+    let lut_explanation = frame.voi.lut.as_ref().map(|lut| {
+        lut.explanation
+            .clone()
+            .unwrap_or_else(|| format!("{} entries", lut.data.len()))
+    });
+
+    let mut preset = window_state.preset;
+    egui::ComboBox::from_id_salt("dicom_window_preset")
+        .width(ui.available_width())
+        .selected_text(preset.to_string())
+        .show_ui(ui, |ui| {
+            for p in WindowPreset::iter() {
+                ui.selectable_value(&mut preset, p, p.to_string());
+            }
+        });
+    if preset != window_state.preset {
+        window_state.set_preset(preset);
+        ui.ctx().request_repaint();
+    }
+
+    let unit = state
+        .image_metadata
+        .as_ref()
+        .and_then(|info| info.dicom.as_ref())
+        .map(|dicom| dicom.unit())
+        .unwrap_or_default();
+    match window_state.window {
+        Some(mut window) => {
+            let speed = window_state
+                .range
+                .map(|(min, max)| (max - min).max(1.) * 0.002)
+                .unwrap_or(1.);
+            let mut changed = false;
+            egui::Grid::new("dicom_window")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Center");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut window.center)
+                                .speed(speed)
+                                .suffix(&unit),
+                        )
+                        .changed();
+                    ui.end_row();
+                    ui.label("Width");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut window.width)
+                                .speed(speed)
+                                .range(1.0..=f32::MAX)
+                                .suffix(&unit),
+                        )
+                        .changed();
+                    ui.end_row();
+                });
+            if changed {
+                window_state.set_window(window);
+                ui.ctx().request_repaint();
+            }
+        }
+        None => {
+            ui.label(format!("VOI LUT: {}", lut_explanation.unwrap_or_default()));
+        }
+    }
+    ui.label("Drag the image with the right mouse button: Horizontally for width, vertically for center.")
+        .on_hover_text("Changing the window switches to the Custom preset");
+}
+
+/// Series mode and the metadata of the series
+fn dicom_series_ui(ui: &mut Ui, state: &mut OculanteState) {
+    ui.styled_checkbox(&mut state.persistent_settings.dicom_series, "Series mode")
+        .on_hover_text(
+            "Browse only the files of this series, ordered by slice position or instance number",
+        );
+    if !state.persistent_settings.dicom_series {
+        return;
+    }
+    if state.dicom_series.is_pending() {
+        ui.horizontal(|ui| {
+            ui.add(egui::Spinner::default());
+            ui.label("Reading the folder");
+        });
+        ui.ctx().request_repaint();
+    }
+    if let Some(error) = &state.dicom_series.error {
+        ui.colored_label(Color32::RED, error);
+    }
+    let Some(series) = &state.dicom_series.series else {
+        return;
+    };
+    let tags = state
+        .image_metadata
+        .as_ref()
+        .and_then(|info| info.dicom.as_ref())
+        .map(|dicom| &dicom.dicom_data);
+    let tag = |name: &str| tags.and_then(|t| t.get(name)).map(|v| v.trim().to_string());
+
+    egui::Grid::new("dicom_series")
+        .num_columns(2)
+        .show(ui, |ui| {
+            if let Some(description) = &series.description {
+                ui.label("Series");
+                ui.label_right(description);
+                ui.end_row();
+            }
+            if let Some(modality) = &series.modality {
+                ui.label("Modality");
+                ui.label_right(modality);
+                ui.end_row();
+            }
+            ui.label("Slice");
+            ui.label_right(format!(
+                "{} / {}",
+                state.scrubber.index + 1,
+                series.entries.len()
+            ));
+            ui.end_row();
+            if let Some(instance) = tag("InstanceNumber") {
+                ui.label("Instance");
+                ui.label_right(instance);
+                ui.end_row();
+            }
+            if let Some(location) = tag("SliceLocation") {
+                ui.label("Location");
+                ui.label_right(format!("{location} mm"));
+                ui.end_row();
+            }
+            if let Some(thickness) = tag("SliceThickness") {
+                ui.label("Thickness");
+                ui.label_right(format!("{thickness} mm"));
+                ui.end_row();
+            }
+            if let Some(spacing) = series.spacing {
+                ui.label("Spacing");
+                ui.label_right(format!("{:.2} mm", spacing.abs()));
+                ui.end_row();
+            }
+        });
+}
+
+/// Whether an edit through `r` is complete. Slow updates such as compositing layers or
+/// developing RAW images wait for the end of a drag instead of following every step.
+fn settled(r: &Response) -> bool {
+    r.drag_stopped() || (r.changed() && !r.dragged())
+}
+
+/// Show, hide, fade and export the layers of PSD and KRA documents
+fn layers_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let Some(path) = state.current_path.clone() else {
+        return;
+    };
+    state.layers.load(&path);
+    state.layers.poll();
+    if state.layers.is_pending() {
+        ui.horizontal(|ui| {
+            ui.add(egui::Spinner::default());
+            ui.label("Reading layers");
+        });
+        ui.ctx().request_repaint();
+    }
+    if let Some(error) = &state.layers.error {
+        ui.colored_label(Color32::RED, error);
+    }
+    let Some(image) = &mut state.layers.image else {
+        return;
+    };
+
+    let mut changed = false;
+    let mut export = None;
+    egui::Grid::new("layers").num_columns(3).show(ui, |ui| {
+        // Top layer first, like in the editors
+        for (index, layer) in image.layers.iter_mut().enumerate().rev() {
+            let icon = if layer.visible { EYE } else { EYEOFF };
+            if unframed_button(icon, ui)
+                .on_hover_text("Show or hide the layer")
+                .clicked()
+            {
+                layer.visible = !layer.visible;
+                changed = true;
+            }
+            ui.label(&layer.name);
+            ui.horizontal(|ui| {
+                let mut percent = layer.opacity * 100.;
+                let r = ui.add(
+                    egui::DragValue::new(&mut percent)
+                        .range(0.0..=100.0)
+                        .suffix("%"),
+                );
+                if r.changed() {
+                    layer.opacity = percent / 100.;
+                }
+                changed |= settled(&r);
+                if unframed_button(DOWNLOAD, ui)
+                    .on_hover_text("Export this layer as PNG")
+                    .clicked()
+                {
+                    export = Some(index);
+                }
+            });
+            ui.end_row();
+        }
+    });
+
+    if changed {
+        if let Some(img) = state.layers.composite() {
+            state.replace_current_image(img);
+        }
+    }
+    let mut exported = export.map(|index| state.layers.export_layer(index));
+    if ui
+        .styled_button("Export all layers")
+        .on_hover_text("Save every layer as PNG in a folder next to the document")
+        .clicked()
+    {
+        exported = Some(state.layers.export_all());
+    }
+    match exported {
+        Some(Ok(dest)) => state.send_message_info(&format!("Saved to {}", dest.display())),
+        Some(Err(e)) => state.send_message_err(&format!("Could not export: {e}")),
+        None => {}
+    }
+}
+
+fn raw_develop_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let Some(path) = state.current_path.clone() else {
+        return;
+    };
+    let settings = &mut state.persistent_settings.decoders.raw;
+    let mut changed = false;
+    let mut track = |r: Response| changed |= settled(&r);
+
+    egui::Grid::new("raw_develop")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("White balance");
+            egui::ComboBox::from_id_salt("raw_white_balance")
+                .selected_text(settings.white_balance.to_string())
+                .show_ui(ui, |ui| {
+                    for white_balance in WhiteBalance::iter() {
+                        track(ui.selectable_value(
+                            &mut settings.white_balance,
+                            white_balance,
+                            white_balance.to_string(),
+                        ));
+                    }
+                });
+            ui.end_row();
+            if settings.white_balance == WhiteBalance::Custom {
+                ui.label("Temperature");
+                track(
+                    ui.add(
+                        egui::DragValue::new(&mut settings.temperature)
+                            .range(2000.0..=12000.0)
+                            .speed(10.)
+                            .suffix(" K"),
+                    )
+                    .on_hover_text("The light of the scene. The camera setting counts as 6500 K."),
+                );
+                ui.end_row();
+                ui.label("Tint");
+                track(
+                    ui.add(
+                        egui::DragValue::new(&mut settings.tint)
+                            .range(-1.0..=1.0)
+                            .speed(0.01),
+                    ),
+                );
+                ui.end_row();
+            }
+            ui.label("Exposure");
+            track(
+                ui.add(
+                    egui::DragValue::new(&mut settings.exposure)
+                        .range(-5.0..=5.0)
+                        .speed(0.02)
+                        .suffix(" EV"),
+                ),
+            );
+            ui.end_row();
+            ui.label("Highlight recovery");
+            track(ui.add(egui::Slider::new(
+                &mut settings.highlight_recovery,
+                0.0..=1.0,
+            )));
+            ui.end_row();
+            ui.label("Demosaic");
+            egui::ComboBox::from_id_salt("raw_demosaic")
+                .selected_text(settings.demosaic.to_string())
+                .show_ui(ui, |ui| {
+                    for demosaic in Demosaic::iter() {
+                        ui.selectable_value(&mut settings.demosaic, demosaic, demosaic.to_string());
+                    }
+                });
+            ui.end_row();
+        });
+    if ui
+        .styled_button("Reset")
+        .on_hover_text("Develop as shot")
+        .clicked()
+    {
+        *settings = Default::default();
+        changed = true;
+    }
+
+    // The loader developed the image on display already. The sensor data is only decoded
+    // once a setting changes and kept for further changes, or again for another demosaicing.
+    let settings = *settings;
+    if changed {
+        state.raw_develop.request_develop();
+        state.raw_develop.load(&path, settings.demosaic);
+    }
+    state.raw_develop.poll();
+    if state.raw_develop.is_pending() {
+        ui.horizontal(|ui| {
+            ui.add(egui::Spinner::default());
+            ui.label("Decoding sensor data");
+        });
+        ui.ctx().request_repaint();
+    }
+    if let Some(img) = state.raw_develop.take_image(&settings) {
+        state.replace_current_image(img);
+    }
+    if let Some(error) = &state.raw_develop.error {
+        ui.colored_label(Color32::RED, error);
+    }
+}
+
+fn texture_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let Some(path) = state.current_path.clone() else {
+        return;
+    };
+    state.texture_inspector.load(&path);
+    state.texture_inspector.poll();
+    if state.texture_inspector.is_pending() {
+        ui.horizontal(|ui| {
+            ui.add(egui::Spinner::default());
+            ui.label("Reading texture");
+        });
+        ui.ctx().request_repaint();
+    }
+    let inspector = &mut state.texture_inspector;
+    let Some(info) = inspector.texture.as_ref().map(|t| t.info.clone()) else {
+        if let Some(error) = &inspector.error {
+            ui.colored_label(Color32::RED, error);
+        }
+        return;
+    };
+
+    egui::Grid::new("texture_info")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Container");
+            ui.label_right(info.container);
+            ui.end_row();
+            ui.label("Format");
+            ui.label_right(&info.format);
+            ui.end_row();
+            if let Some(supercompression) = &info.supercompression {
+                ui.label("Supercompression");
+                ui.label_right(supercompression);
+                ui.end_row();
+            }
+            ui.label("Color space");
+            ui.label_right(info.color_space.to_string());
+            ui.end_row();
+            ui.label("Size");
+            if info.depth > 1 {
+                ui.label_right(format!("{}x{}x{}", info.width, info.height, info.depth));
+            } else {
+                ui.label_right(format!("{}x{}", info.width, info.height));
+            }
+            ui.end_row();
+        });
+
+    let mut changed = false;
+    egui::Grid::new("texture_parts")
+        .num_columns(2)
+        .show(ui, |ui| {
+            if info.levels > 1 {
+                ui.label("Mip level");
+                let size = |level| {
+                    let (width, height) = info.level_size(level);
+                    format!("{level}: {width}x{height}")
+                };
+                egui::ComboBox::from_id_salt("texture_level")
+                    .selected_text(size(inspector.level))
+                    .show_ui(ui, |ui| {
+                        for level in 0..info.levels {
+                            changed |= ui
+                                .selectable_value(&mut inspector.level, level, size(level))
+                                .changed();
+                        }
+                    });
+                ui.end_row();
+            }
+            if info.layers > 1 {
+                ui.label("Array layer");
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut inspector.layer)
+                            .range(0..=info.layers - 1)
+                            .suffix(format!(" / {}", info.layers)),
+                    )
+                    .changed();
+                ui.end_row();
+            }
+            if info.is_cubemap() {
+                ui.label("Face");
+                ui.add_enabled_ui(!inspector.cross, |ui| {
+                    egui::ComboBox::from_id_salt("texture_face")
+                        .selected_text(FACE_NAMES[inspector.face as usize])
+                        .show_ui(ui, |ui| {
+                            for (face, name) in FACE_NAMES.iter().enumerate() {
+                                changed |= ui
+                                    .selectable_value(&mut inspector.face, face as u32, *name)
+                                    .changed();
+                            }
+                        });
+                });
+                ui.end_row();
+            }
+        });
+    if info.is_cubemap() {
+        changed |= ui
+            .styled_checkbox(&mut inspector.cross, "Cross layout")
+            .on_hover_text("Show all six faces unfolded")
+            .changed();
+    }
+
+    if changed {
+        if let Some(img) = state.texture_inspector.render() {
+            state.replace_current_image(img);
+        }
+    }
+    if let Some(error) = &state.texture_inspector.error {
+        ui.colored_label(Color32::RED, error);
+    }
+}
+
+/// Replace the current image with the decoded EXR view
+fn show_exr_view(state: &mut OculanteState) {
+    if let Some(img) = state.exr_layers.render() {
+        state.replace_current_image(img);
+    }
+}
+
 fn advanced_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let has_profile = state
+        .image_metadata
+        .as_ref()
+        .is_some_and(|info| info.color_profile.is_some());
+    if has_profile
+        && ui
+            .styled_checkbox(
+                &mut state.persistent_settings.decoders.color_managed,
+                "Color managed",
+            )
+            .on_hover_text("Convert to the display profile. Turn off to see the pixels as stored.")
+            .changed()
+    {
+        reload_with_decoder_settings(state);
+    }
+
     if let Some(info) = &state.image_metadata {
         egui::Grid::new("extended").num_columns(2).show(ui, |ui| {
             ui.label("Number of colors");
@@ -313,6 +979,12 @@ fn advanced_ui(ui: &mut Ui, state: &mut OculanteState) {
             ui.label("Pixels");
             ui.label_right(format!("{}", info.num_pixels));
             ui.end_row();
+
+            if let Some(profile) = &info.color_profile {
+                ui.label("Color profile");
+                ui.label_right(profile);
+                ui.end_row();
+            }
         });
 
         if !info.exif.is_empty() {
@@ -43,11 +43,14 @@ use crate::filebrowser;
 
 use crate::{
     appstate::{ImageGeometry, OculanteState},
+    color_management::DisplayProfile,
     file_encoder::FileEncoder,
     image_editing::{
-        process_pixels, Channel, ColorTypeExt, GradientStop, ImageOperation, ImgOpItem,
-        MeasureShape, ScaleFilter,
+        process_pixels, Channel, ColorTypeExt, EditState, GradientStop, ImageOperation,
+        ImgOpItem, MeasureShape, ScaleFilter,
     },
+    image_loader::{open_animation, read_orientation},
+    metadata::ImageMetadata,
     paint::PaintStroke,
     settings::{set_system_theme, ColorTheme, PersistentSettings, VolatileSettings},
     shortcuts::{key_pressed, keypresses_as_string, lookup},
@@ -863,26 +866,58 @@ fn show_modal<R>(
     modal
 }
 
-/// Save an image to a path using encoding options and generate a thumbnail
+/// Apply changed decoder settings and load the current image again
+pub fn reload_with_decoder_settings(state: &mut OculanteState) {
+    state
+        .player
+        .set_decoder_opts(state.persistent_settings.decoders);
+    state.player.cache.clear();
+    if let Some(path) = &state.current_path {
+        state.is_loaded = false;
+        state.player.load(path);
+    }
+}
+
+/// Save an image to a path using encoding options and generate a thumbnail.
+/// With `animation_edits`, all frames of the `source` animation are saved with the edits applied.
+/// `display_profile` is the profile the pixels were converted to, if any.
 fn save_with_encoding(
     image: &DynamicImage,
     path: &Path,
-    image_info: &Option<ExtendedImageInfo>,
+    source: Option<&Path>,
     encoders: &Vec<FileEncoder>,
+    strip_location: bool,
+    animation_edits: Option<&EditState>,
+    display_profile: Option<DisplayProfile>,
 ) -> anyhow::Result<()> {
+    // Read before saving, the source may be the file that gets overwritten
+    let mut metadata = source
+        .and_then(|source| {
+            ImageMetadata::read(source)
+                .map_err(|e| debug!("No metadata to carry over: {e}"))
+                .ok()
+        })
+        .unwrap_or_default();
+    // The viewer turns still images upright when loading them
+    metadata.oriented = animation_edits.is_none()
+        && source.is_some_and(|source| read_orientation(source).is_ok());
+    if let (Some(display_profile), Some(_)) = (display_profile, &metadata.icc) {
+        // The pixels were converted to the display profile when loading
+        metadata.icc = display_profile.icc().ok().map(Into::into);
+    }
     let encoding_options = FileEncoder::matching_variant(path, encoders);
-    encoding_options.save(image, path)?;
-    debug!("Saved to {}", path.display());
-    // Re-apply exif
-    if let Some(info) = &image_info {
-        debug!("Extended image info present");
-        // before doing anything, make sure we have raw exif data
-        if info.raw_exif.is_some() {
-            fix_exif(path, info.raw_exif.clone())?;
-        } else {
-            debug!("No raw exif");
+    match (source, animation_edits) {
+        // Re-decode the source to edit every frame, not just the one on screen.
+        // Stdin and network feeds have no file to decode again.
+        (Some(source), Some(edits))
+            if source.is_file() && encoding_options.supports_animation() =>
+        {
+            let frames = edits.apply_to_frames(&open_animation(source, None)?)?;
+            encoding_options.save_animation(&frames, path)?;
         }
+        _ => encoding_options.save_with_metadata(image, path, &metadata, strip_location)?,
     }
+    debug!("Saved to {}", path.display());
     thumbnails::generate(path)?;
     Ok(())
 }
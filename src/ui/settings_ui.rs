@@ -166,6 +166,10 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, _gfx
                                         ui,
                                     );
 
+                                    configuration_item_ui("Single instance", "Open images in the running window instead of starting a new one. This also lets scripts control the viewer with 'oculante remote'. A restart is required to take effect.", |ui| {
+                                        ui.styled_checkbox(&mut state.persistent_settings.single_instance, "");
+                                    }, ui);
+
                                     configuration_item_ui("Do not reset image view", "When a new image is loaded, keep the current zoom and offset.", |ui| {
                                         ui.styled_checkbox(&mut state.persistent_settings.keep_view, "");
                                     }, ui);
@@ -174,6 +178,10 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, _gfx
                                         ui.styled_checkbox(&mut state.persistent_settings.keep_edits, "");
                                     }, ui);
 
+                                    configuration_item_ui("Remove location when saving", "Saved images keep the EXIF, color profile and XMP of the original. This removes GPS coordinates from them.", |ui| {
+                                        ui.styled_checkbox(&mut state.persistent_settings.strip_location, "");
+                                    }, ui);
+
                                     configuration_item_ui("Redraw every frame", "Turns off optimisations and redraws everything each frame. This will consume more CPU but gives you instant feedback if new images come in or if modifications are made. A restart is required to take effect.", |ui| {
                                         if ui.styled_checkbox(&mut state.persistent_settings.force_redraw, "").changed(){
                                             app.window().set_lazy_loop(!state.persistent_settings.force_redraw);
@@ -343,6 +351,45 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, _gfx
                                     decoders.scroll_to_me(Some(Align::TOP));
                                 }
                                 light_panel(ui, |ui| {
+                                    configuration_item_ui(
+                                        "Color management",
+                                        "Convert images with an embedded ICC profile to the display profile.",
+                                        |ui| {
+                                            if ui.styled_checkbox(&mut state.persistent_settings.decoders.color_managed, "").changed() {
+                                                reload_with_decoder_settings(state);
+                                            }
+                                        },
+                                        ui
+                                    );
+
+                                    configuration_item_ui(
+                                        "Embedded previews",
+                                        "Show the preview embedded in RAW and JPEG files first. The full image is decoded once you stay on it or zoom in.",
+                                        |ui| {
+                                            if ui.styled_checkbox(&mut state.persistent_settings.decoders.embedded_previews, "").changed() {
+                                                state.player.set_decoder_opts(state.persistent_settings.decoders);
+                                            }
+                                        },
+                                        ui
+                                    );
+
+                                    configuration_item_ui(
+                                        "Display profile",
+                                        "The color space of your display. Most displays are close to sRGB.",
+                                        |ui| {
+                                            egui::ComboBox::from_id_salt("Display profile")
+                                                .selected_text(state.persistent_settings.decoders.display_profile.to_string())
+                                                .show_ui(ui, |ui| {
+                                                    for profile in DisplayProfile::iter() {
+                                                        if ui.selectable_value(&mut state.persistent_settings.decoders.display_profile, profile, profile.to_string()).changed() {
+                                                            reload_with_decoder_settings(state);
+                                                        }
+                                                    }
+                                                });
+                                        },
+                                        ui
+                                    );
+
                                     configuration_item_ui(
                                         "HEIF security override",
                                         "Disable all HEIF security limits. A restart is required to take effect.",
@@ -2,6 +2,7 @@ use super::Modal;
 use super::*;
 use crate::appstate::OculanteState;
 use crate::filebrowser::BrowserDir;
+use crate::tonemapping::{is_scene_linear, ToneMapper, ToneMapping};
 use crate::utils::*;
 #[cfg(not(any(target_os = "netbsd", target_os = "freebsd")))]
 use notan::egui::*;
@@ -111,6 +112,26 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, gfx: &mu
                 .update_color_selection(gfx, &state.persistent_settings);
         }
 
+        let scene_linear = state
+            .current_image
+            .as_ref()
+            .map(|img| is_scene_linear(img.color()))
+            .unwrap_or_default();
+        if scene_linear && window_x > ui.cursor().left() + 80. {
+            ui.scope(|ui| {
+                ui.style_mut().visuals.button_frame = false;
+                ui.menu_button(RichText::new(APERTURE).size(ICON_SIZE), |ui| {
+                    if tone_mapping_ui(&mut state.persistent_settings.tone_mapping, ui) {
+                        state
+                            .current_texture
+                            .update_tone_mapping(gfx, &state.persistent_settings.tone_mapping);
+                    }
+                })
+                .response
+                .on_hover_text("Tone mapping");
+            });
+        }
+
         let label_rect = ui.ctx().available_rect().shrink(50.);
 
         // TODO Center toast to image viewing area (Shift to the left / Right if the info or edit panel gets opened)
@@ -261,6 +282,41 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, gfx: &mu
             }
         }
 
+        if state.pages.is_paged() && window_x > ui.cursor().left() {
+            ui.separator();
+            if tooltip(
+                unframed_button(CARET_LEFT, ui),
+                "Previous page",
+                &lookup(&state.persistent_settings.shortcuts, &PreviousPage),
+                ui,
+            )
+            .clicked()
+            {
+                state.pages.previous();
+            }
+            ui.label(format!(
+                "Page {}/{}",
+                state.pages.current + 1,
+                state.pages.count
+            ));
+            if tooltip(
+                unframed_button(CARET_RIGHT, ui),
+                "Next page",
+                &lookup(&state.persistent_settings.shortcuts, &NextPage),
+                ui,
+            )
+            .clicked()
+            {
+                state.pages.next();
+            }
+            if state.pages.is_pending() {
+                ui.add(egui::Spinner::default());
+            }
+            if let Some(error) = &state.pages.error {
+                ui.label(WARNING_CIRCLE).on_hover_text(error);
+            }
+        }
+
         if state.current_path.is_some() && !state.is_loaded {
             ui.horizontal(|ui| {
                 ui.add(egui::Spinner::default());
@@ -314,6 +370,44 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, gfx: &mu
     });
 }
 
+/// Tone mapper, exposure and gamma of float images. Returns true if anything changed.
+fn tone_mapping_ui(tone_mapping: &mut ToneMapping, ui: &mut Ui) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        for mapper in ToneMapper::iter() {
+            changed |= ui
+                .selectable_value(&mut tone_mapping.mapper, mapper, mapper.to_string())
+                .changed();
+        }
+    });
+    egui::Grid::new("tone_mapping")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Exposure");
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut tone_mapping.exposure, -10.0..=10.0)
+                        .step_by(0.1)
+                        .suffix(" EV"),
+                )
+                .changed();
+            ui.end_row();
+            ui.label("Gamma");
+            changed |= ui
+                .add(egui::Slider::new(&mut tone_mapping.gamma, 1.0..=3.0).step_by(0.05))
+                .changed();
+            ui.end_row();
+        });
+    if ui.button("Reset").clicked() {
+        *tone_mapping = ToneMapping {
+            mapper: tone_mapping.mapper,
+            ..Default::default()
+        };
+        changed = true;
+    }
+    changed
+}
+
 pub fn draw_hamburger_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App) {
     use crate::shortcuts::InputEvent::*;
 
@@ -20,13 +20,14 @@ use anyhow::{Context, Result};
 use image::{self, DynamicImage, GenericImageView};
 use image::{EncodableLayout, Rgba, RgbaImage};
 use std::sync::mpsc::{self};
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use strum::Display;
 use strum_macros::EnumIter;
 
 use crate::appstate::{ImageGeometry, Message, OculanteState};
 use crate::cache::Cache;
-use crate::image_loader::{open_image, rotate_dynimage};
+use crate::color_management::{self, profile_description};
+use crate::image_loader::{load_embedded_preview, open_image, rotate_dynimage};
 use crate::settings::DecoderSettings;
 use crate::shortcuts::{lookup, InputEvent, Shortcuts};
 
@@ -101,14 +102,53 @@ fn is_pixel_fully_transparent(p: &Rgba<u8>) -> bool {
     p.0 == [0, 0, 0, 0]
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct DicomData {
     pub physical_size: (f32, f32),
     pub dicom_data: HashMap<String, String>,
+    /// Values of the first frame after the modality LUT, such as Hounsfield units for CT
+    #[serde(skip)]
+    pub modality_values: Vec<f32>,
+    #[serde(skip)]
+    pub width: u32,
 }
 
-#[derive(Debug, Clone, Default)]
+impl DicomData {
+    /// The unit of [`DicomData::modality_values`] with a leading space, if it is known
+    pub fn unit(&self) -> String {
+        let tag = |name| {
+            self.dicom_data
+                .get(name)
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+        };
+        // "US" stands for unspecified
+        let unit = match (tag("RescaleType"), tag("Modality")) {
+            (Some(unit), _) if unit != "US" => unit,
+            (_, Some("CT")) => "HU",
+            (_, Some("PT")) => tag("Units").unwrap_or_default(),
+            _ => "",
+        };
+        if unit.is_empty() {
+            String::new()
+        } else {
+            format!(" {unit}")
+        }
+    }
+
+    pub fn modality_value(&self, x: u32, y: u32) -> Option<f32> {
+        if x >= self.width {
+            return None;
+        }
+        self.modality_values
+            .get(y as usize * self.width as usize + x as usize)
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct ExtendedImageInfo {
+    pub dimensions: (u32, u32),
     pub num_pixels: usize,
     pub num_transparent_pixels: usize,
     pub num_colors: usize,
@@ -117,6 +157,11 @@ pub struct ExtendedImageInfo {
     pub blue_histogram: Vec<(i32, u64)>,
     pub exif: HashMap<String, String>,
     pub dicom: Option<DicomData>,
+    /// Description of the embedded ICC profile
+    pub color_profile: Option<String>,
+    /// Smallest and largest stored color value, for images with more than 8 bits
+    pub value_range: Option<(f32, f32)>,
+    #[serde(skip)]
     pub raw_exif: Option<Bytes>,
     pub name: String,
 }
@@ -154,7 +199,13 @@ impl ExtendedImageInfo {
         Ok(())
     }
 
-    pub fn with_dicom(&mut self, image_path: &Path) -> Result<()> {
+    pub fn with_color_profile(&mut self, image_path: &Path) {
+        self.color_profile = color_management::read_profile(image_path)
+            .map(|icc| profile_description(&icc).unwrap_or_else(|| "Unnamed".into()));
+    }
+
+    /// Read the DICOM tags and the modality values of `frame`
+    pub fn with_dicom(&mut self, image_path: &Path, frame: u32) -> Result<()> {
         self.name = image_path.to_string_lossy().to_string();
         if image_path.extension() != Some(OsStr::new("dcm"))
             || image_path.extension() != Some(OsStr::new("ima"))
@@ -179,6 +230,17 @@ impl ExtendedImageInfo {
                 "PatientBirthDate",
                 "PatientAge",
                 "PixelSpacing",
+                "SeriesDescription",
+                "SeriesNumber",
+                "InstanceNumber",
+                "SliceLocation",
+                "SliceThickness",
+                "RescaleSlope",
+                "RescaleIntercept",
+                "RescaleType",
+                "Units",
+                "WindowCenter",
+                "WindowWidth",
             ] {
                 if let Ok(e) = obj.element_by_name(name) {
                     if let Ok(s) = e.to_str() {
@@ -187,9 +249,15 @@ impl ExtendedImageInfo {
                     }
                 }
             }
+            let (modality_values, width) = dicom_modality_values(&obj, frame).unwrap_or_default();
+            if let Some(range) = value_range(modality_values.iter().copied()) {
+                self.value_range = Some(range);
+            }
             self.dicom = Some(DicomData {
                 physical_size: (0.0, 0.0),
                 dicom_data,
+                modality_values,
+                width,
             })
         }
 
@@ -251,6 +319,7 @@ impl ExtendedImageInfo {
             .collect();
 
         Self {
+            dimensions: img.dimensions(),
             num_pixels,
             num_transparent_pixels,
             num_colors: full_colors as usize,
@@ -261,14 +330,131 @@ impl ExtendedImageInfo {
             name: Default::default(),
             exif: Default::default(),
             dicom: Default::default(),
+            color_profile: Default::default(),
+            value_range: Default::default(),
         }
     }
+
+    /// Record the range of stored values, unless the image has only 8 bits
+    pub fn with_value_range(&mut self, img: &DynamicImage) {
+        self.value_range = match img {
+            DynamicImage::ImageLuma16(buf) => color_values(buf),
+            DynamicImage::ImageLumaA16(buf) => color_values(buf),
+            DynamicImage::ImageRgb16(buf) => color_values(buf),
+            DynamicImage::ImageRgba16(buf) => color_values(buf),
+            DynamicImage::ImageRgb32F(buf) => color_values(buf),
+            DynamicImage::ImageRgba32F(buf) => color_values(buf),
+            _ => None,
+        };
+    }
+
+    /// The value stored at a position, if it has more precision than the displayed one.
+    /// DICOM images report the value after the modality LUT.
+    pub fn stored_value(&self, img: &DynamicImage, x: u32, y: u32) -> Option<String> {
+        if let Some(dicom) = &self.dicom {
+            if let Some(value) = dicom.modality_value(x, y) {
+                return Some(format!("{}{}", format_value(value), dicom.unit()));
+            }
+        }
+        stored_value(img, x, y)
+    }
+
+    /// [`ExtendedImageInfo::value_range`] with a unit where one is known
+    pub fn value_range_text(&self) -> Option<String> {
+        let (min, max) = self.value_range?;
+        let unit = self.dicom.as_ref().map(|d| d.unit()).unwrap_or_default();
+        Some(format!(
+            "{}{unit} .. {}{unit}",
+            format_value(min),
+            format_value(max)
+        ))
+    }
+}
+
+/// Values after the modality LUT of a frame, along with the image width
+pub fn dicom_modality_values(
+    obj: &dicom_object::DefaultDicomObject,
+    frame: u32,
+) -> Result<(Vec<f32>, u32)> {
+    use dicom_pixeldata::{ConvertOptions, ModalityLutOption, PixelDecoder};
+    let pixels = obj.decode_pixel_data_frame(frame)?;
+    if pixels.samples_per_pixel() != 1 {
+        anyhow::bail!("Only monochrome DICOM images have modality values");
+    }
+    let options = ConvertOptions::new().with_modality_lut(ModalityLutOption::Default);
+    let mut values = pixels.to_vec_with_options::<f32>(&options)?;
+    values.truncate(pixels.rows() as usize * pixels.columns() as usize);
+    Ok((values, pixels.columns()))
+}
+
+/// Whole numbers without decimals, others with four
+fn format_value(value: f32) -> String {
+    if value.fract() == 0.0 {
+        format!("{value}")
+    } else {
+        format!("{value:.4}")
+    }
+}
+
+/// The smallest and largest finite value
+pub fn value_range(values: impl Iterator<Item = f32>) -> Option<(f32, f32)> {
+    values
+        .filter(|v| v.is_finite())
+        .fold(None, |range, v| match range {
+            None => Some((v, v)),
+            Some((min, max)) => Some((v.min(min), v.max(max))),
+        })
+}
+
+/// The range of all channels except alpha
+fn color_values<P>(buf: &image::ImageBuffer<P, Vec<P::Subpixel>>) -> Option<(f32, f32)>
+where
+    P: image::Pixel,
+    P::Subpixel: Into<f32>,
+{
+    let color_channels = P::CHANNEL_COUNT as usize - P::HAS_ALPHA as usize;
+    value_range(
+        buf.pixels()
+            .flat_map(|p| p.channels()[..color_channels].iter().map(|c| (*c).into())),
+    )
+}
+
+/// The stored channels of a pixel of a 16 bit or float image
+pub fn stored_value(img: &DynamicImage, x: u32, y: u32) -> Option<String> {
+    fn join<T: std::fmt::Display>(channels: &[T]) -> String {
+        channels
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+    fn join_float(channels: &[f32]) -> String {
+        channels
+            .iter()
+            .map(|c| format!("{c:.4}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+    if !img.in_bounds(x, y) {
+        return None;
+    }
+    Some(match img {
+        DynamicImage::ImageLuma16(buf) => join(&buf.get_pixel(x, y).0),
+        DynamicImage::ImageLumaA16(buf) => join(&buf.get_pixel(x, y).0),
+        DynamicImage::ImageRgb16(buf) => join(&buf.get_pixel(x, y).0),
+        DynamicImage::ImageRgba16(buf) => join(&buf.get_pixel(x, y).0),
+        DynamicImage::ImageRgb32F(buf) => join_float(&buf.get_pixel(x, y).0),
+        DynamicImage::ImageRgba32F(buf) => join_float(&buf.get_pixel(x, y).0),
+        _ => return None,
+    })
 }
 
 #[derive(Debug)]
 pub struct Player {
     pub image_sender: Sender<Frame>,
     pub stop_sender: Sender<()>,
+    /// Start the full decode of an image that is shown as a preview
+    decode_sender: Sender<()>,
     pub message_sender: Sender<Message>,
     pub cache: Cache,
     watcher: HashMap<PathBuf, SystemTime>,
@@ -284,9 +470,11 @@ impl Player {
         decoder_opts: DecoderSettings,
     ) -> Player {
         let (stop_sender, _): (Sender<()>, Receiver<()>) = mpsc::channel();
+        let (decode_sender, _): (Sender<()>, Receiver<()>) = mpsc::channel();
         Player {
             image_sender,
             stop_sender,
+            decode_sender,
             message_sender,
             cache: Cache {
                 data: Default::default(),
@@ -297,6 +485,11 @@ impl Player {
         }
     }
 
+    /// Use new decoder settings for the next images to load
+    pub fn set_decoder_opts(&mut self, decoder_opts: DecoderSettings) {
+        self.decoder_opts = decoder_opts;
+    }
+
     pub fn check_modified(&mut self, path: &Path) {
         if let Some(watched_mod) = self.watcher.get(path) {
             if let Ok(meta) = std::fs::metadata(path) {
@@ -321,6 +514,8 @@ impl Player {
         self.stop();
         let (stop_sender, stop_receiver): (Sender<()>, Receiver<()>) = mpsc::channel();
         self.stop_sender = stop_sender;
+        let (decode_sender, decode_receiver): (Sender<()>, Receiver<()>) = mpsc::channel();
+        self.decode_sender = decode_sender;
 
         if let Some(cached_image) = self.cache.get(img_location) {
             debug!("Cache hit for {}", img_location.display());
@@ -342,6 +537,7 @@ impl Player {
             self.image_sender.clone(),
             self.message_sender.clone(),
             stop_receiver,
+            decode_receiver,
             forced_frame_source,
             self.decoder_opts,
         );
@@ -360,6 +556,49 @@ impl Player {
     pub fn stop(&self) {
         _ = self.stop_sender.send(());
     }
+
+    /// Decode the current image right away if only its preview is shown
+    pub fn decode_full(&self) {
+        _ = self.decode_sender.send(());
+    }
+}
+
+/// How long an embedded preview is shown before the full decode starts. Images that are
+/// skipped within this time are never fully decoded.
+const PREVIEW_DELAY: Duration = Duration::from_millis(400);
+
+/// Send the embedded preview of `img_location`, if there is one, and wait until the full
+/// image is needed. Returns false if the player has moved on in the meantime.
+fn send_embedded_preview(
+    img_location: &Path,
+    texture_sender: &Sender<Frame>,
+    stop_receiver: &Receiver<()>,
+    decode_receiver: &Receiver<()>,
+) -> bool {
+    let timer = std::time::Instant::now();
+    let preview = match load_embedded_preview(img_location) {
+        Ok(preview) => preview,
+        Err(e) => {
+            debug!("No embedded preview: {e}");
+            return true;
+        }
+    };
+    debug!("Received preview in {:?}", timer.elapsed());
+    _ = texture_sender.send(Frame::Preview(preview));
+
+    while timer.elapsed() < PREVIEW_DELAY {
+        if stop_receiver.try_recv().is_ok() {
+            debug!("Stopped from receiver.");
+            return false;
+        }
+        match decode_receiver.recv_timeout(Duration::from_millis(20)) {
+            Ok(_) => break,
+            Err(RecvTimeoutError::Timeout) => (),
+            // The player has loaded another image
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+    true
 }
 
 pub fn send_image_threaded(
@@ -367,6 +606,7 @@ pub fn send_image_threaded(
     texture_sender: Sender<Frame>,
     message_sender: Sender<Message>,
     stop_receiver: Receiver<()>,
+    decode_receiver: Receiver<()>,
     forced_frame_source: Option<Frame>,
     decoder_opts: DecoderSettings,
 ) {
@@ -377,6 +617,14 @@ pub fn send_image_threaded(
         let mut framecache = vec![];
         let mut timer = std::time::Instant::now();
 
+        // Collection members and other forced frames are always decoded in full
+        if decoder_opts.embedded_previews
+            && forced_frame_source.is_none()
+            && !send_embedded_preview(&loc, &texture_sender, &stop_receiver, &decode_receiver)
+        {
+            return;
+        }
+
         match open_image(&loc, Some(message_sender.clone()), Some(decoder_opts)) {
             Ok(frame_receiver) => {
                 debug!("Got a frame receiver from opening image");
@@ -463,6 +711,8 @@ pub fn send_image_threaded(
 pub enum Frame {
     /// A regular still frame (most common)
     Still(DynamicImage),
+    /// The preview embedded in a still image, shown until the `Still` is decoded
+    Preview(DynamicImage),
     /// Part of an animation. Delay in ms
     Animation(DynamicImage, u16),
     /// First frame of animation. This is necessary to reset the image and stop the player.
@@ -510,12 +760,14 @@ impl Frame {
         let mut forced_variant = forced_variant;
         match &self {
             Frame::Still(img)
+            | Frame::Preview(img)
             | Frame::Animation(img, _)
             | Frame::AnimationStart(img)
             | Frame::EditResult(img)
             | Frame::CompareResult(img, _)
             | Frame::ImageCollectionMember(img) => match forced_variant {
                 Frame::Still(ref mut image_buffer)
+                | Frame::Preview(ref mut image_buffer)
                 | Frame::Animation(ref mut image_buffer, _)
                 | Frame::AnimationStart(ref mut image_buffer)
                 | Frame::EditResult(ref mut image_buffer)
@@ -528,11 +780,26 @@ impl Frame {
         forced_variant
     }
 
+    /// Mutable access to the image buffer of a `Frame`
+    pub fn image_mut(&mut self) -> Option<&mut DynamicImage> {
+        match self {
+            Frame::AnimationStart(img)
+            | Frame::Still(img)
+            | Frame::Preview(img)
+            | Frame::EditResult(img)
+            | Frame::CompareResult(img, _)
+            | Frame::Animation(img, _)
+            | Frame::ImageCollectionMember(img) => Some(img),
+            Frame::UpdateTexture => None,
+        }
+    }
+
     /// Return the image buffor of a `Frame`.
     pub fn get_image(&self) -> Option<DynamicImage> {
         match self {
             Frame::AnimationStart(img)
             | Frame::Still(img)
+            | Frame::Preview(img)
             | Frame::EditResult(img)
             | Frame::CompareResult(img, _)
             | Frame::Animation(img, _)
@@ -721,17 +988,25 @@ pub fn pos_from_coord(
 pub fn send_extended_info(
     current_image: &Option<DynamicImage>,
     current_path: &Option<PathBuf>,
+    page: usize,
     channel: &(Sender<ExtendedImageInfo>, Receiver<ExtendedImageInfo>),
 ) {
     if let Some(img) = current_image {
         let copied_img = img.to_rgba8();
+        // The stored values only matter if they have more precision than the display
+        let high_precision =
+            (img.color().bytes_per_pixel() > img.color().channel_count()).then(|| img.clone());
         let sender = channel.0.clone();
         let current_path = current_path.clone();
         thread::spawn(move || {
             let mut e_info = ExtendedImageInfo::from_image(&copied_img);
+            if let Some(img) = high_precision {
+                e_info.with_value_range(&img);
+            }
             if let Some(p) = current_path {
                 _ = e_info.with_exif(&p);
-                _ = e_info.with_dicom(&p);
+                _ = e_info.with_dicom(&p, page as u32);
+                e_info.with_color_profile(&p);
             }
             debug!("Sending extended info");
             _ = sender.send(e_info);
@@ -969,17 +1244,6 @@ pub fn toggle_zen_mode(state: &mut OculanteState, app: &mut App) {
     set_title(app, state);
 }
 
-/// Fix missing exif by re-applying exif to saved files
-pub fn fix_exif(p: &Path, exif: Option<Bytes>) -> Result<()> {
-    use std::fs::{self, File};
-    let input = fs::read(p)?;
-    let mut dynimage = DynImage::from_bytes(input.into())?.context("Unsupported EXIF format")?;
-    dynimage.set_exif(exif);
-    let output = File::create(p)?;
-    dynimage.encoder().write_to(output)?;
-    Ok(())
-}
-
 pub fn clipboard_to_image() -> Result<DynamicImage> {
     let clipboard = &mut Clipboard::new()?;
 
@@ -1012,3 +1276,23 @@ pub fn get_pixel_checked(img: &DynamicImage, x: u32, y: u32) -> Option<Rgba<u8>>
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_range_ignores_alpha() {
+        let img = DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(4, 4, |x, y| {
+            image::Rgba([x as u16 * 1000, y as u16 * 100, 7, u16::MAX])
+        }));
+        let mut info = ExtendedImageInfo::default();
+        info.with_value_range(&img);
+        assert_eq!(info.value_range, Some((0., 3000.)));
+        assert_eq!(
+            stored_value(&img, 1, 2).as_deref(),
+            Some("1000,200,7,65535")
+        );
+        assert_eq!(stored_value(&DynamicImage::new_rgba8(4, 4), 1, 2), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! "Save for web": Find the highest encoder quality that keeps a file within a byte budget.
+
+use crate::file_encoder::FileEncoder;
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use log::debug;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// The formats offered for web export
+pub const WEB_FORMATS: [&str; 2] = ["jpg", "webp"];
+
+/// An encoded image that fits the target size
+#[derive(Debug, Clone)]
+pub struct WebExport {
+    /// The encoder with the quality that was found
+    pub encoder: FileEncoder,
+    pub quality: u32,
+    pub data: Vec<u8>,
+    /// The encoded image, decoded again to show compression artifacts
+    pub preview: DynamicImage,
+}
+
+/// Settings and result of the "Save for web" panel
+#[derive(Debug)]
+pub struct WebExportState {
+    pub ext: String,
+    pub target_kb: u32,
+    pub result: Option<WebExport>,
+    /// Show the compressed preview instead of the image
+    pub show_preview: bool,
+    pub error: Option<String>,
+    pending: Option<Receiver<Result<WebExport>>>,
+}
+
+impl Default for WebExportState {
+    fn default() -> Self {
+        Self {
+            ext: WEB_FORMATS[0].into(),
+            target_kb: 200,
+            result: None,
+            show_preview: false,
+            error: None,
+            pending: None,
+        }
+    }
+}
+
+impl WebExportState {
+    /// Start searching for a quality in the background
+    pub fn start(&mut self, image: DynamicImage, encoder: FileEncoder) {
+        let (sender, receiver) = channel();
+        let max_bytes = self.target_kb as usize * 1024;
+        thread::spawn(move || {
+            _ = sender.send(fit_to_size(&image, encoder, max_bytes));
+        });
+        self.clear();
+        self.pending = Some(receiver);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Pick up the result of [`WebExportState::start`]. Returns true once it arrived.
+    pub fn poll(&mut self) -> bool {
+        let Some(result) = self.pending.as_ref().and_then(|r| r.try_recv().ok()) else {
+            return false;
+        };
+        self.pending = None;
+        match result {
+            Ok(export) => self.result = Some(export),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+        true
+    }
+
+    /// Forget the result, for example because the image changed
+    pub fn clear(&mut self) {
+        self.result = None;
+        self.show_preview = false;
+        self.error = None;
+        self.pending = None;
+    }
+}
+
+/// Binary search the quality of `encoder` for the largest file not exceeding `max_bytes`
+pub fn fit_to_size(
+    image: &DynamicImage,
+    mut encoder: FileEncoder,
+    max_bytes: usize,
+) -> Result<WebExport> {
+    let (mut low, mut high) = (1, 100);
+    let mut best: Option<(u32, Vec<u8>)> = None;
+    let mut smallest = usize::MAX;
+
+    while low <= high {
+        let quality = (low + high) / 2;
+        encoder.set_quality(quality);
+        let data = encoder.encode_to_memory(image)?;
+        debug!("Quality {quality}: {} bytes", data.len());
+        smallest = smallest.min(data.len());
+        if data.len() <= max_bytes {
+            best = Some((quality, data));
+            low = quality + 1;
+        } else {
+            high = quality - 1;
+        }
+    }
+
+    let Some((quality, data)) = best else {
+        bail!(
+            "Even the lowest quality needs {}, more than {}",
+            format_size(smallest),
+            format_size(max_bytes)
+        );
+    };
+    encoder.set_quality(quality);
+    let preview = image::load_from_memory(&data)?;
+    Ok(WebExport {
+        encoder,
+        quality,
+        data,
+        preview,
+    })
+}
+
+/// A file size in human readable units
+pub fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f32 / 1024.)
+    } else {
+        format!("{:.2} MB", bytes as f32 / (1024. * 1024.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_within_budget() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 256, |x, y| {
+            image::Rgb([(x ^ y) as u8, (x * 3) as u8, (y * 7) as u8])
+        }));
+        let max_bytes = 20 * 1024;
+        let export = fit_to_size(&image, FileEncoder::Jpg { quality: 75 }, max_bytes).unwrap();
+        assert!(export.data.len() <= max_bytes);
+        assert_eq!(export.encoder, FileEncoder::Jpg { quality: export.quality });
+
+        // A higher quality must not fit, unless the best one was already picked
+        if export.quality < 100 {
+            let mut encoder = export.encoder.clone();
+            encoder.set_quality(export.quality + 1);
+            assert!(encoder.encode_to_memory(&image).unwrap().len() > max_bytes);
+        }
+    }
+}